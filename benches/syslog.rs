@@ -15,14 +15,17 @@ use futures::Future;
 use futures::sync::mpsc::{channel, Sender, Receiver};
 use futures::Sink;
 use futures::future::ok;
+use std::io::Error as IoError;
 use std::thread;
 
 use logslash::{null_logger, event_loop};
-use logslash::codec::syslog::{SyslogCodec, SyslogEvent};
+use logslash::codec::syslog::{SyslogCodec, OwnedSyslogEvent};
 use logslash::output::write::{write_blocking, write_threaded};
+use logslash::output::retry::Backoff;
 use logslash::serialize::JsonLogstashEventSerializer;
 use logslash::serialize::Serializer;
 use logslash::PipeError;
+use logslash::shutdown::Shutdown;
 use futures::stream::Stream;
 use tempfile::tempfile;
 
@@ -94,17 +97,42 @@ fn syslog_rfc5424_newline_x10_to_logstash_json(bench: &mut Bencher) {
     })
 }
 
+// events are serialized in the codec while they still borrow from the input buffer
+fn syslog_rfc5424_newline_x10_serialized_to_logstash_json(bench: &mut Bencher) {
+    let mut buf = Vec::from(SYSLOG_RFC5424_NEWLINE_EXAMPLES);
+    let mut event_loop = event_loop();
+    let handle = event_loop.handle();
+    let logger = null_logger();
+
+    bench.iter(move || {
+        let input = BufStream::new(buf.as_mut_slice());
+
+        let output = BufStream::default();
+
+        let write = write_blocking(&logger, "syslog", handle.clone(), output, move |data: &Vec<u8>, buf| {
+            buf.extend_from_slice(data);
+            Ok::<(), IoError>(())
+        });
+
+        let pipe = input
+            .framed(SyslogCodec::rfc5424_in_newline_frame().serialized(JsonLogstashEventSerializer::default()))
+            .map_err(|e| PipeError::Input(e))
+            .forward(write);
+        event_loop.run(pipe).expect("Ok result");
+    })
+}
+
 fn syslog_rfc5424_newline_x10_to_logstash_json_to_file(bench: &mut Bencher) {
     let mut buf = Vec::from(SYSLOG_RFC5424_NEWLINE_EXAMPLES);
     let tempfile = tempfile().unwrap();
 
     let mut event_loop = event_loop();
-    let (input, receiver): (Sender<SyslogEvent>, Receiver<SyslogEvent>) = channel(1);
+    let (input, receiver): (Sender<OwnedSyslogEvent>, Receiver<OwnedSyslogEvent>) = channel(1);
 
     let logger = null_logger();
     let ser = JsonLogstashEventSerializer::default();
 
-    let write = write_threaded(&logger, "syslog", tempfile, move |event, buf| {
+    let write = write_threaded(&logger, "syslog", &Shutdown::new(), tempfile, Backoff::default(), move |event, buf| {
         ser.serialize(event, buf).map(|_| ())
     });
 
@@ -127,5 +155,6 @@ benchmark_group!(benches,
                  syslog_rfc5424_newline_x10,
                  syslog_rfc5424_newline_no_meta_x10,
                  syslog_rfc5424_newline_x10_to_logstash_json,
+                 syslog_rfc5424_newline_x10_serialized_to_logstash_json,
                  syslog_rfc5424_newline_x10_to_logstash_json_to_file);
 benchmark_main!(benches);
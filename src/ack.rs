@@ -0,0 +1,80 @@
+use futures::{Sink, StartSend, AsyncSink, Poll};
+use futures::sync::oneshot;
+
+/// Event that input needs to know was accepted downstream before acknowledging it to the
+/// sender
+///
+/// Dropping Acked without passing it through AckSink cancels the acknowledgment.
+#[derive(Debug)]
+pub struct Acked<T> {
+    pub event: T,
+    ack: oneshot::Sender<()>,
+}
+
+impl<T> Acked<T> {
+    pub fn new(event: T) -> (Acked<T>, oneshot::Receiver<()>) {
+        let (ack, acked) = oneshot::channel();
+        (Acked {
+            event: event,
+            ack: ack
+        }, acked)
+    }
+
+    pub fn map<U, F>(self, f: F) -> Acked<U> where F: FnOnce(T) -> U {
+        Acked {
+            event: f(self.event),
+            ack: self.ack
+        }
+    }
+
+    pub fn ack(self) -> T {
+        self.ack.complete(());
+        self.event
+    }
+}
+
+/// Sink that passes events on to inner sink and acknowledges them once inner sink completed
+/// accepting them
+///
+/// For sinks that queue events (like write_threaded) this means the event was queued, not written;
+/// use output::write::write_threaded_acked to acknowledge events only after they were persisted.
+pub struct AckSink<S> {
+    sink: S,
+    pending: Vec<oneshot::Sender<()>>,
+}
+
+impl<S, T> Sink for AckSink<S> where S: Sink<SinkItem=T> {
+    type SinkItem = Acked<T>;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: Acked<T>) -> StartSend<Acked<T>, S::SinkError> {
+        let Acked { event, ack } = item;
+        match self.sink.start_send(event)? {
+            AsyncSink::Ready => {
+                self.pending.push(ack);
+                Ok(AsyncSink::Ready)
+            }
+            AsyncSink::NotReady(event) => Ok(AsyncSink::NotReady(Acked {
+                event: event,
+                ack: ack
+            }))
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        let complete = self.sink.poll_complete()?;
+        if complete.is_ready() {
+            for ack in self.pending.drain(..) {
+                ack.complete(());
+            }
+        }
+        Ok(complete)
+    }
+}
+
+pub fn acknowledging<S: Sink>(sink: S) -> AckSink<S> {
+    AckSink {
+        sink: sink,
+        pending: Vec::new()
+    }
+}
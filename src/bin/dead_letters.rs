@@ -0,0 +1,373 @@
+extern crate logslash;
+extern crate nom;
+#[cfg(test)]
+extern crate tempfile;
+#[cfg(test)]
+extern crate chrono;
+
+use std::env;
+use std::process::exit;
+use std::path::{Path, PathBuf};
+use std::io::{self, Write};
+use std::fs::OpenOptions;
+use std::net::TcpStream;
+
+use nom::{IResult, ErrorKind};
+
+use logslash::dead_letter::{DeadLetter, list_segments, read_segment, write_segment};
+use logslash::codec::error::ParseError;
+use logslash::codec::syslog::{SyslogEvent, Rfc3164Config, IdStrategy, parse_errors, syslog_frame};
+use logslash::serialize::{Serializer, JsonLogstashEventSerializer};
+
+const USAGE: &'static str = "Usage:
+    dead_letters <spool dir> list
+    dead_letters <spool dir> inspect <segment> <index>
+    dead_letters <spool dir> reprocess <parser> file <path> [<id strategy>]
+    dead_letters <spool dir> reprocess <parser> tcp <host:port> [<id strategy>]
+
+Reprocessed messages are written as Logstash JSON, one per line; dead letters holding a frame that
+still fails to parse stay in the spool. The newest segment is not reprocessed as logslash may still
+be appending to it.
+
+ID strategies:
+    content_hash (default)
+    msg_id (only if senders put unique values in RFC 5424 MSGID)
+    random
+
+Parsers:
+    syslog_rfc5424
+    syslog_rfc5424_in_newline_frame
+    syslog_rfc5424_in_rfc5425_frame
+    syslog_rfc3164
+    syslog_rfc3164_in_newline_frame
+    syslog_auto";
+
+// events borrow from the dead letter data; nothing is copied before serialization
+type SyslogParser = fn(&[u8]) -> IResult<&[u8], SyslogEvent, ParseError>;
+
+fn syslog_rfc3164(input: &[u8]) -> IResult<&[u8], SyslogEvent, ParseError> {
+    parse_errors::syslog_rfc3164(input, &Rfc3164Config::default())
+}
+
+fn syslog_rfc3164_in_newline_frame(input: &[u8]) -> IResult<&[u8], SyslogEvent, ParseError> {
+    parse_errors::syslog_rfc3164_in_newline_frame(input, &Rfc3164Config::default())
+}
+
+fn syslog_auto(input: &[u8]) -> IResult<&[u8], SyslogEvent, ParseError> {
+    parse_errors::syslog_auto(input, &Rfc3164Config::default())
+}
+
+fn parser(name: &str) -> Option<SyslogParser> {
+    let parser: SyslogParser = match name {
+        "syslog_rfc5424" => parse_errors::syslog_rfc5424,
+        "syslog_rfc5424_in_newline_frame" => parse_errors::syslog_rfc5424_in_newline_frame,
+        "syslog_rfc5424_in_rfc5425_frame" => parse_errors::syslog_rfc5424_in_rfc5425_frame,
+        "syslog_rfc3164" => syslog_rfc3164,
+        "syslog_rfc3164_in_newline_frame" => syslog_rfc3164_in_newline_frame,
+        "syslog_auto" => syslog_auto,
+        _ => return None
+    };
+    Some(parser)
+}
+
+fn id_strategy(name: &str) -> Option<IdStrategy> {
+    match name {
+        "content_hash" => Some(IdStrategy::ContentHash),
+        "msg_id" => Some(IdStrategy::MsgId),
+        "random" => Some(IdStrategy::Random),
+        _ => None
+    }
+}
+
+fn fail(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", message);
+    exit(2)
+}
+
+// rows of hexdump shown before and after the row with failing byte
+const CONTEXT_ROWS: usize = 4;
+
+/// Dump data around the failing offset marking the failing row; whole data if offset is unknown
+fn hexdump<W: Write>(out: &mut W, data: &[u8], offset: Option<usize>) -> io::Result<()> {
+    for (line, chunk) in data.chunks(16).enumerate() {
+        if let Some(offset) = offset {
+            let failing = offset / 16;
+            if line + CONTEXT_ROWS < failing || line > failing + CONTEXT_ROWS {
+                continue
+            }
+            write!(out, "{}", if line == failing { "> " } else { "  " })?;
+        }
+        write!(out, "{:08x}  ", line * 16)?;
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => write!(out, "{:02x} ", byte)?,
+                None => write!(out, "   ")?
+            }
+            if i == 7 {
+                write!(out, " ")?;
+            }
+        }
+        let printable: String = chunk.iter().map(|&byte| if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' }).collect();
+        writeln!(out, " |{}|", printable)?;
+    }
+    Ok(())
+}
+
+fn describe<W: Write>(out: &mut W, segment: &Path, index: usize, letter: &DeadLetter) -> io::Result<()> {
+    writeln!(out, "{} {} {} input={} remote={} bytes={} error={:?}",
+             segment.file_name().and_then(|name| name.to_str()).unwrap_or("?"),
+             index,
+             letter.timestamp.to_rfc3339(),
+             letter.input,
+             letter.remote_addr.as_ref().map(|addr| addr.as_str()).unwrap_or("-"),
+             letter.data.len(),
+             letter.error)
+}
+
+fn segments(spool_dir: &Path) -> Vec<PathBuf> {
+    list_segments(spool_dir).unwrap_or_else(|err| fail(&format!("Failed to open spool {}: {}", spool_dir.display(), err)))
+}
+
+fn read(segment: &Path) -> Vec<DeadLetter> {
+    read_segment(segment).unwrap_or_else(|err| fail(&format!("Failed to read segment {}: {}", segment.display(), err)))
+}
+
+fn list(spool_dir: &Path) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    for segment in segments(spool_dir) {
+        for (index, letter) in read(&segment).iter().enumerate() {
+            describe(&mut out, &segment, index, letter)?;
+        }
+    }
+    Ok(())
+}
+
+fn inspect(spool_dir: &Path, segment: &str, index: &str) -> io::Result<()> {
+    let segment = spool_dir.join(segment);
+    let index: usize = index.parse().unwrap_or_else(|_| fail("Index needs to be a number"));
+    let letters = read(&segment);
+    let letter = letters.get(index).unwrap_or_else(|| fail("No such dead letter in segment"));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    describe(&mut out, &segment, index, letter)?;
+    writeln!(out, "{}", letter.error)?;
+    hexdump(&mut out, &letter.data, letter.offset)
+}
+
+/// Parse every frame in dead letter data (it may hold more than one, e.g. when input spooled all
+/// it had buffered); letter is reprocessed only if all of its frames parse
+fn parse_all<'a>(parser: SyslogParser, id_strategy: IdStrategy, data: &'a [u8]) -> Result<Vec<SyslogEvent<'a>>, Option<ParseError>> {
+    let mut events = Vec::new();
+    let mut input = data;
+
+    while !input.is_empty() {
+        match parser(input) {
+            IResult::Done(rest, _) if rest.len() == input.len() => return Err(None),
+            IResult::Done(rest, event) => {
+                // ID is assigned from message without framing as SyslogCodec does
+                let frame = &input[..input.len() - rest.len()];
+                let message = match event.framing.map(|framing| syslog_frame(frame, framing)) {
+                    Some(IResult::Done(_, message)) => message,
+                    _ => frame
+                };
+                events.push(event.identified(id_strategy, message));
+                input = rest;
+            }
+            IResult::Error(ErrorKind::Custom(err)) => return Err(Some(err.in_frame(data, data.len() - input.len()))),
+            IResult::Error(_) | IResult::Incomplete(_) => return Err(None),
+        }
+    }
+    Ok(events)
+}
+
+fn reprocess(spool_dir: &Path, parser: SyslogParser, id_strategy: IdStrategy, mut out: Box<Write>) -> io::Result<()> {
+    let serializer = JsonLogstashEventSerializer::default();
+    let mut reprocessed = 0;
+    let mut failed = 0;
+
+    // logslash may still be appending to the newest segment; rewriting it would lose new letters
+    let mut segments = segments(spool_dir);
+    if let Some(active) = segments.pop() {
+        writeln!(io::stderr(), "Skipping newest segment {}", active.display())?;
+    }
+
+    for segment in segments {
+        let mut remaining = Vec::new();
+
+        for mut letter in read(&segment) {
+            let failure = match parse_all(parser, id_strategy, &letter.data) {
+                Ok(events) => {
+                    for event in events {
+                        let mut buf = serializer.serialize(&event, Vec::new()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                        buf.push(b'\n');
+                        out.write_all(&buf)?;
+                        reprocessed += 1;
+                    }
+                    continue
+                }
+                Err(failure) => failure
+            };
+
+            match failure {
+                Some(err) => {
+                    writeln!(io::stderr(), "Still failing: {}", err)?;
+                    // keep the latest error
+                    letter.error = format!("{}", err);
+                    letter.offset = Some(err.offset);
+                }
+                None => writeln!(io::stderr(), "Still failing: Failed to apply parser")?
+            }
+            remaining.push(letter);
+        }
+
+        // make sure events are delivered before we remove them from the spool
+        out.flush()?;
+        failed += remaining.len();
+        write_segment(&segment, &remaining)?;
+    }
+
+    writeln!(io::stderr(), "Reprocessed {} messages; {} messages still fail to parse", reprocessed, failed)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    let result = match (args.get(0), args.get(1), args.len()) {
+        (Some(spool_dir), Some(&"list"), 2) => list(Path::new(spool_dir)),
+        (Some(spool_dir), Some(&"inspect"), 4) => inspect(Path::new(spool_dir), args[2], args[3]),
+        (Some(spool_dir), Some(&"reprocess"), 5) | (Some(spool_dir), Some(&"reprocess"), 6) => {
+            let (parser_name, output, destination) = (args[2], args[3], args[4]);
+            let parser = parser(parser_name).unwrap_or_else(|| fail(&format!("Unknown parser: {}\n\n{}", parser_name, USAGE)));
+            let id_strategy = match args.get(5) {
+                Some(name) => id_strategy(name).unwrap_or_else(|| fail(&format!("Unknown ID strategy: {}\n\n{}", name, USAGE))),
+                None => IdStrategy::default()
+            };
+            let out: Box<Write> = match output {
+                "file" => Box::new(OpenOptions::new().append(true).create(true).open(destination)
+                    .unwrap_or_else(|err| fail(&format!("Failed to open output file: {}", err)))),
+                "tcp" => Box::new(TcpStream::connect(destination)
+                    .unwrap_or_else(|err| fail(&format!("Failed to connect to {}: {}", destination, err)))),
+                _ => fail(USAGE)
+            };
+            reprocess(Path::new(spool_dir), parser, id_strategy, out)
+        }
+        _ => fail(USAGE)
+    };
+
+    if let Err(err) = result {
+        fail(&format!("Error: {}", err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+    use chrono::UTC;
+    use tempfile::TempDir;
+    use logslash::dead_letter::{Spool, SpoolConfig};
+
+    const FRAMES: &'static [u8] = b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n<165>1 2003-10-11T22:14:16.003Z mymachine.example.com evntslog - ID48 - bar\n";
+
+    fn letter(data: &[u8]) -> DeadLetter {
+        DeadLetter {
+            timestamp: UTC::now(),
+            input: "syslog".to_owned(),
+            remote_addr: None,
+            error: "Failed to parse".to_owned(),
+            offset: Some(0),
+            data: data.to_vec(),
+        }
+    }
+
+    // spool letter followed by one in newer segment as if logslash was still appending
+    fn spool(dir: &Path, data: &[u8]) {
+        let mut spool = Spool::open(dir, SpoolConfig { segment_size: 1, max_size: SpoolConfig::default().max_size }).unwrap();
+        spool.append(&letter(data)).unwrap();
+        spool.append(&letter(b"newest")).unwrap();
+    }
+
+    #[test]
+    fn parse_multiple_frames() {
+        let events = parse_all(parse_errors::syslog_rfc5424_in_newline_frame, IdStrategy::default(), FRAMES).unwrap();
+        assert_eq!(events.iter().map(|event| event.msg_id.clone().unwrap()).collect::<Vec<_>>(), vec!["ID47", "ID48"]);
+
+        // same ID as SyslogCodec would assign
+        let message = &FRAMES[..FRAMES.iter().position(|&byte| byte == b'\n').unwrap()];
+        let (_, expected) = parse_errors::syslog_rfc5424(message).unwrap();
+        assert_eq!(events[0].id, expected.identified(IdStrategy::ContentHash, message).id);
+
+        let mut data = FRAMES.to_vec();
+        data.extend_from_slice(b"<165>1 foo\n");
+        let err = parse_all(parse_errors::syslog_rfc5424_in_newline_frame, IdStrategy::default(), &data).err().unwrap().unwrap();
+        assert!(err.offset >= FRAMES.len());
+    }
+
+    #[test]
+    fn reprocess_multi_frame_letter() {
+        let dir = TempDir::new().unwrap();
+        let spool_dir = dir.path().join("spool");
+        let out_path = dir.path().join("out");
+        spool(&spool_dir, FRAMES);
+
+        reprocess(&spool_dir, parse_errors::syslog_rfc5424_in_newline_frame, IdStrategy::default(), Box::new(File::create(&out_path).unwrap())).unwrap();
+
+        let mut out = String::new();
+        File::open(&out_path).unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("ID47"));
+        assert!(out.contains("ID48"));
+
+        let segments = segments(&spool_dir);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(read(&segments[0])[0].data, b"newest".to_vec());
+    }
+
+    #[test]
+    fn skip_newest_segment() {
+        let dir = TempDir::new().unwrap();
+        let spool_dir = dir.path().join("spool");
+        let out_path = dir.path().join("out");
+        Spool::open(&spool_dir, SpoolConfig::default()).unwrap().append(&letter(FRAMES)).unwrap();
+
+        reprocess(&spool_dir, parse_errors::syslog_rfc5424_in_newline_frame, IdStrategy::default(), Box::new(File::create(&out_path).unwrap())).unwrap();
+
+        let mut out = String::new();
+        File::open(&out_path).unwrap().read_to_string(&mut out).unwrap();
+        assert!(out.is_empty());
+        assert_eq!(read(&segments(&spool_dir)[0])[0].data, FRAMES.to_vec());
+    }
+
+    #[test]
+    fn missing_spool_dir() {
+        let dir = TempDir::new().unwrap();
+        let spool_dir = dir.path().join("spool");
+
+        assert!(list_segments(&spool_dir).is_err());
+        assert!(!spool_dir.exists());
+    }
+
+    #[test]
+    fn keep_letter_with_failing_frame() {
+        let dir = TempDir::new().unwrap();
+        let spool_dir = dir.path().join("spool");
+        let out_path = dir.path().join("out");
+        let mut data = FRAMES.to_vec();
+        data.extend_from_slice(b"<165>1 foo\n");
+        spool(&spool_dir, &data);
+
+        reprocess(&spool_dir, parse_errors::syslog_rfc5424_in_newline_frame, IdStrategy::default(), Box::new(File::create(&out_path).unwrap())).unwrap();
+
+        let mut out = String::new();
+        File::open(&out_path).unwrap().read_to_string(&mut out).unwrap();
+        assert!(out.is_empty());
+        let letters = read(&segments(&spool_dir)[0]);
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].data, data);
+    }
+}
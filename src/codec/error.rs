@@ -0,0 +1,118 @@
+use std::fmt::{self, Display};
+use std::error::Error;
+
+// bytes of context shown on each side of the error position
+const EXCERPT_CONTEXT: usize = 32;
+
+/// Printable part of the input around offset with caret marking the offset on the second line
+pub fn excerpt(input: &[u8], offset: usize) -> String {
+    let offset = if offset > input.len() { input.len() } else { offset };
+    let start = offset.saturating_sub(EXCERPT_CONTEXT);
+    let end = if input.len() - offset > EXCERPT_CONTEXT { offset + EXCERPT_CONTEXT } else { input.len() };
+
+    let mut line = String::new();
+    if start > 0 {
+        line.push_str("...");
+    }
+    let caret = line.len() + offset - start;
+
+    // one character per byte so caret lines up
+    line.extend(input[start..end].iter().map(|&byte| if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' }));
+    if end < input.len() {
+        line.push_str("...");
+    }
+
+    format!("{}\n{:>width$}", line, "^", width = caret + 1)
+}
+
+/// Parser failure with location of the failing field in the parsed frame
+#[derive(Debug)]
+pub struct ParseError {
+    /// Name of the field that failed to parse
+    pub field: &'static str,
+    pub message: &'static str,
+    /// Byte offset of the failing field within the frame
+    pub offset: usize,
+    pub excerpt: String,
+    cause: Option<Box<Error + Send + Sync>>,
+}
+
+impl ParseError {
+    pub fn new(field: &'static str, message: &'static str, input: &[u8], offset: usize) -> ParseError {
+        ParseError {
+            field: field,
+            message: message,
+            offset: offset,
+            excerpt: excerpt(input, offset),
+            cause: None,
+        }
+    }
+
+    /// Attach underlying error (e.g. UTF-8 or timestamp parsing error); available as Error::cause
+    pub fn caused_by<E: Into<Box<Error + Send + Sync>>>(mut self, cause: E) -> ParseError {
+        self.cause = Some(cause.into());
+        self
+    }
+
+    /// Relocate error found in part of the frame starting at given offset
+    pub fn in_frame(mut self, frame: &[u8], start: usize) -> ParseError {
+        self.offset += start;
+        self.excerpt = excerpt(frame, self.offset);
+        self
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {} in {}", self.message, self.offset, self.field)?;
+        if let Some(ref cause) = self.cause {
+            write!(f, ": {}", cause)?;
+        }
+        write!(f, "\n{}", self.excerpt)
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        self.message
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        self.cause.as_ref().map(|cause| &**cause as &Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    #[test]
+    fn excerpt_short() {
+        assert_eq!(excerpt(b"<165>1 foo", 7), "<165>1 foo\n       ^");
+        assert_eq!(excerpt(b"foo", 3), "foo\n   ^");
+    }
+
+    #[test]
+    fn excerpt_long() {
+        let mut input = vec![b'a'; 100];
+        input[50] = b'\n';
+        let a = |n| iter::repeat('a').take(n).collect::<String>();
+        assert_eq!(excerpt(&input, 50), format!("...{}.{}...\n{:>36}", a(32), a(31), "^"));
+    }
+
+    #[test]
+    fn display() {
+        let error = ParseError::new("timestamp", "Unrecognized syslog timestamp format", b"<165>1 2003-13-11T22:14:15.003Z host", 7)
+            .caused_by("input is out of range");
+        assert_eq!(error.to_string(), "Unrecognized syslog timestamp format at byte 7 in timestamp: input is out of range\n<165>1 2003-13-11T22:14:15.003Z host\n       ^");
+    }
+
+    #[test]
+    fn cause() {
+        let error = ParseError::new("timestamp", "Unrecognized syslog timestamp format", b"<165>1 foo", 7)
+            .caused_by("input is out of range");
+        assert_eq!(error.cause().unwrap().to_string(), "input is out of range");
+        assert!(ParseError::new("timestamp", "Unrecognized syslog timestamp format", b"<165>1 foo", 7).cause().is_none());
+    }
+}
@@ -1,12 +1,98 @@
+pub mod error;
 pub mod nom;
 pub mod syslog;
+pub mod relp;
+
+use std::fmt::{self, Display};
+use std::error::Error;
+use std::io::Write;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+
+use codec::error::ParseError;
+
+/// How messages are delimited in a stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    /// Message followed by new line; message needs to be free of new lines (e.g. syslog
+    /// messages have them written as #012)
+    Newline,
+    /// Length of the message in bytes followed by space and the message (RFC 6587 octet-counting)
+    OctetCounting,
+}
+
+impl Framing {
+    /// RFC 6587 octet-counted frames start with message length where messages start with priority
+    /// tag
+    pub fn detect(input: &[u8]) -> Option<Framing> {
+        input.first().map(|&c| if c >= b'0' && c <= b'9' {
+            Framing::OctetCounting
+        } else {
+            Framing::Newline
+        })
+    }
+
+    pub fn frame(&self, data: &[u8], out: &mut Vec<u8>) {
+        match *self {
+            Framing::Newline => {
+                out.extend_from_slice(data);
+                out.push(b'\n');
+            }
+            Framing::OctetCounting => {
+                write!(out, "{} ", data.len()).expect("length written to buf");
+                out.extend_from_slice(data);
+            }
+        }
+    }
+}
+
+/// Data that codec failed to decode; returned by codecs wrapped in IoError
+#[derive(Debug)]
+pub struct DecodeError {
+    /// Raw bytes of the frame (or whole buffer if frame could not be found)
+    pub frame: Vec<u8>,
+    pub error: ParseError,
+    /// Codec has skipped the frame and can continue decoding
+    pub resumable: bool,
+}
+
+impl DecodeError {
+    /// Get DecodeError out of error returned by codec
+    pub fn from_io_error(error: &IoError) -> Option<&DecodeError> {
+        error.get_ref().and_then(|error| error.downcast_ref::<DecodeError>())
+    }
+}
+
+impl From<DecodeError> for IoError {
+    fn from(error: DecodeError) -> IoError {
+        IoError::new(IoErrorKind::InvalidInput, error)
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for DecodeError {
+    fn description(&self) -> &str {
+        self.error.description()
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        Some(&self.error)
+    }
+}
 
 // Shared parsing primitives
 mod parse {
+    use std::error::Error as StdError;
     use std::str::{from_utf8, Utf8Error};
     use std::num::ParseIntError;
 
-    use chrono::{DateTime, FixedOffset};
+    use chrono::{DateTime, UTC, FixedOffset, TimeZone, Datelike};
+    use chrono::format::{self, Parsed, StrftimeItems};
     use chrono::format::ParseError as ChronoParesError;
 
     error_chain! {
@@ -17,6 +103,18 @@ mod parse {
         }
     }
 
+    impl Error {
+        /// Underlying error that can be kept as cause of ParseError
+        pub fn into_cause(self) -> Box<StdError + Send + Sync> {
+            match self.0 {
+                ErrorKind::Utf8Error(ref err) => Box::new(err.clone()),
+                ErrorKind::ParseIntError(ref err) => Box::new(err.clone()),
+                ErrorKind::TimestampError(ref err) => Box::new(err.clone()),
+                ref kind => From::from(kind.to_string())
+            }
+        }
+    }
+
     pub fn string(bytes: &[u8]) -> Result<&str> {
         from_utf8(bytes).map_err(From::from)
     }
@@ -26,6 +124,11 @@ mod parse {
         .and_then(|s| s.parse().map_err(From::from))
     }
 
+    pub fn int_usize(bytes: &[u8]) -> Result<usize> {
+        string(bytes).map_err(From::from)
+        .and_then(|s| s.parse().map_err(From::from))
+    }
+
     pub fn timestamp(bytes: &[u8]) -> Result<DateTime<FixedOffset>> {
         let s = string(bytes)?;
 
@@ -34,4 +137,30 @@ mod parse {
         .or(DateTime::parse_from_str(s, "%b %d %H:%M:%S"))
         .map_err(From::from)
     }
+
+    /// RFC 3164 timestamps carry no year; assume that message is not coming from more than a
+    /// month in to the future so that messages from December received in January get the last year
+    pub fn infer_year(month: u32, now_year: i32, now_month: u32) -> i32 {
+        if month > now_month + 1 {
+            now_year - 1
+        } else if month == 1 && now_month == 12 {
+            now_year + 1
+        } else {
+            now_year
+        }
+    }
+
+    pub fn bsd_timestamp(bytes: &[u8], timezone: &FixedOffset, now: DateTime<UTC>) -> Result<DateTime<FixedOffset>> {
+        let s = string(bytes)?;
+
+        let mut parsed = Parsed::new();
+        format::parse(&mut parsed, s, StrftimeItems::new("%b %e %H:%M:%S"))?;
+
+        let now = now.with_timezone(timezone);
+        let month = parsed.month.ok_or("missing month")?;
+        parsed.set_year(infer_year(month, now.year(), now.month()) as i64)?;
+
+        let local = parsed.to_naive_datetime_with_offset(0)?;
+        timezone.from_local_datetime(&local).single().ok_or("no single local time".into())
+    }
 }
@@ -1,63 +1,227 @@
 use std::io::Error as IoError;
-use std::io::ErrorKind as IoErrorKind;
+use std::str::from_utf8;
 
 use tokio_core::io::{Codec, EasyBuf};
 
 use nom::{IResult, ErrorKind};
 
-pub type NomParser<T> = fn(&[u8]) -> IResult<&[u8], T, &'static str>;
+use codec::DecodeError;
+use codec::error::ParseError;
+
+/// Parser reporting where and why it failed; see codec::syslog::parse_errors
+pub type NomParser<T> = fn(&[u8]) -> IResult<&[u8], T, ParseError>;
+
+// decimal digits of usize::MAX on 64 bit platforms
+const MAX_LENGTH_DIGITS: usize = 20;
+
+/// How to find start of the next frame after a frame failed to parse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resync {
+    /// Frames are terminated with new line; skip past the next `\n`
+    Newline,
+    /// Frames are prefixed with their length followed by space (RFC 5425); skip the announced length
+    OctetCounting,
+}
+
+impl Resync {
+    /// Length of the frame at the start of the buffer; None if more data is needed to tell
+    pub fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>, ()> {
+        match *self {
+            Resync::Newline => Ok(buf.iter().position(|&byte| byte == b'\n').map(|pos| pos + 1)),
+            Resync::OctetCounting => {
+                let digits = buf.iter().take_while(|byte| (**byte as char).is_digit(10)).count();
+                if digits == buf.len() {
+                    // no space after more digits than usize can hold
+                    return if digits < MAX_LENGTH_DIGITS { Ok(None) } else { Err(()) }
+                }
+                if digits == 0 || buf[digits] != b' ' {
+                    return Err(())
+                }
+                let length: usize = from_utf8(&buf[..digits]).ok().and_then(|length| length.parse().ok()).ok_or(())?;
+                let frame_len = digits + 1 + length;
+                Ok(if buf.len() < frame_len { None } else { Some(frame_len) })
+            }
+        }
+    }
+}
 
 pub struct NomCodec<T> {
-    parser: NomParser<T>
+    parser: NomParser<T>,
+    resync: Option<Resync>,
+    max_errors: usize,
+    errors: usize,
 }
 
 impl<T> NomCodec<T> {
+    /// Codec failing on first frame that did not parse
     pub fn new(parser: NomParser<T>) -> NomCodec<T> {
         NomCodec {
-            parser: parser
+            parser: parser,
+            resync: None,
+            max_errors: 0,
+            errors: 0,
+        }
+    }
+
+    /// Skip frames that failed to parse reporting them as resumable DecodeError; decoding fails
+    /// once more than max_errors frames failed to parse
+    ///
+    /// Each connection gets its own clone of the codec so the error budget is per connection.
+    pub fn resyncing(parser: NomParser<T>, resync: Resync, max_errors: usize) -> NomCodec<T> {
+        NomCodec {
+            parser: parser,
+            resync: Some(resync),
+            max_errors: max_errors,
+            errors: 0,
         }
     }
+
+    /// Number of frames that failed to parse so far
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
 }
 
 impl<T> Clone for NomCodec<T> {
     fn clone(&self) -> NomCodec<T> {
         NomCodec {
-            parser: self.parser
+            parser: self.parser,
+            resync: self.resync,
+            max_errors: self.max_errors,
+            errors: self.errors,
         }
     }
 }
 
+/// Apply parser to the buffer draining consumed bytes; parser failures are reported as
+/// DecodeError carrying the ParseError
+pub fn decode_with<T, F>(buf: &mut EasyBuf, parser: F) -> Result<Option<T>, IoError> where F: FnOnce(&[u8]) -> IResult<&[u8], T, ParseError> {
+    parse_with(buf, parser).map_err(From::from)
+}
+
+fn parse_with<T, F>(buf: &mut EasyBuf, parser: F) -> Result<Option<T>, DecodeError> where F: FnOnce(&[u8]) -> IResult<&[u8], T, ParseError> {
+    let have_bytes = buf.len();
+
+    let mut consumed = 0;
+    let result = match parser(buf.as_slice()) {
+        IResult::Done(input_left, output) => {
+            consumed = have_bytes - input_left.len();
+            Ok(Some(output))
+        }
+        IResult::Error(ErrorKind::Custom(err)) => {
+            // we don't know where next message starts
+            Err(DecodeError {
+                frame: buf.as_slice().to_vec(),
+                error: err,
+                resumable: false,
+            })
+        }
+        IResult::Error(_) => {
+            Err(DecodeError {
+                frame: buf.as_slice().to_vec(),
+                error: ParseError::new("input", "Failed to apply parser", buf.as_slice(), 0),
+                resumable: false,
+            })
+        }
+        IResult::Incomplete(_) => {
+            Ok(None)
+        }
+    };
+
+    if consumed > 0 {
+        buf.drain_to(consumed);
+    }
+    result
+}
+
+/// NomCodec only decodes; no value of this type can exist so encode can not be called
+#[derive(Debug)]
+pub enum NoEncode {}
+
 impl<T> Codec for NomCodec<T> {
     type In = T;
-    type Out = ();
+    type Out = NoEncode;
 
     fn decode(&mut self, buf: &mut EasyBuf) -> Result<Option<Self::In>, IoError> {
-        let have_bytes = buf.len();
+        let resync = match self.resync {
+            Some(resync) => resync,
+            None => return decode_with(buf, self.parser)
+        };
 
-        let mut consumed = 0;
-        let result = match (self.parser)(buf.as_slice()) {
-            IResult::Done(input_left, output) => {
-                consumed = have_bytes - input_left.len();
-                Ok(Some(output))
-            }
-            IResult::Error(ErrorKind::Custom(err)) => {
-                Err(IoError::new(IoErrorKind::InvalidInput, err))
-            }
-            IResult::Error(_) => {
-                Err(IoError::new(IoErrorKind::InvalidData, "unexpected parser error"))
-            }
-            IResult::Incomplete(_) => {
-                Ok(None)
-            }
+        let mut failure = match parse_with(buf, self.parser) {
+            Ok(output) => return Ok(output),
+            Err(failure) => failure
         };
 
-        if consumed > 0 {
-            buf.drain_to(consumed);
+        let frame_len = match resync.frame_len(buf.as_slice()) {
+            Ok(Some(frame_len)) => frame_len,
+            // failed before the end of the frame; wait for the rest of it
+            Ok(None) => return Ok(None),
+            Err(()) => return Err(failure.into())
+        };
+
+        failure.frame.truncate(frame_len);
+        failure.resumable = true;
+        buf.drain_to(frame_len);
+
+        self.errors += 1;
+        if self.errors > self.max_errors {
+            failure.resumable = false;
         }
-        result
+        Err(failure.into())
+    }
+
+    fn encode(&mut self, msg: Self::Out, _buf: &mut Vec<u8>) -> Result<(), IoError> {
+        match msg {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::syslog::{SyslogEvent, OwnedSyslogEvent, parse_errors};
+
+    fn syslog_rfc5424_in_newline_frame(input: &[u8]) -> IResult<&[u8], OwnedSyslogEvent, ParseError> {
+        parse_errors::syslog_rfc5424_in_newline_frame(input).map(SyslogEvent::into_owned)
+    }
+
+    fn syslog_rfc5424_in_rfc5425_frame(input: &[u8]) -> IResult<&[u8], OwnedSyslogEvent, ParseError> {
+        parse_errors::syslog_rfc5424_in_rfc5425_frame(input).map(SyslogEvent::into_owned)
+    }
+
+    fn failure(result: Result<Option<OwnedSyslogEvent>, IoError>) -> (Vec<u8>, bool) {
+        let err = result.unwrap_err();
+        let failure = DecodeError::from_io_error(&err).unwrap();
+        (failure.frame.clone(), failure.resumable)
+    }
+
+    #[test]
+    fn resync_newline() {
+        let mut codec = NomCodec::resyncing(syslog_rfc5424_in_newline_frame, Resync::Newline, 1);
+        let mut buf = EasyBuf::from(b"<165>1 bad\n<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n<165>1 worse\n".to_vec());
+
+        assert_eq!(failure(codec.decode(&mut buf)), (b"<165>1 bad\n".to_vec(), true));
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().msg_id, Some("ID47".into()));
+        // error budget used up
+        assert_eq!(failure(codec.decode(&mut buf)), (b"<165>1 worse\n".to_vec(), false));
+        assert_eq!(codec.errors(), 2);
+    }
+
+    #[test]
+    fn resync_octet_counting() {
+        let mut codec = NomCodec::resyncing(syslog_rfc5424_in_rfc5425_frame, Resync::OctetCounting, 10);
+        let mut buf = EasyBuf::from(b"10 <165>1 bad79 <165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\nbar".to_vec());
+
+        assert_eq!(failure(codec.decode(&mut buf)), (b"10 <165>1 bad".to_vec(), true));
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().msg_id, Some("ID47".into()));
+        assert!(codec.decode(&mut buf).unwrap().is_none());
     }
 
-    fn encode(&mut self, _msg: Self::Out, _buf: &mut Vec<u8>) -> Result<(), IoError> {
-        panic!("NomCodec: encode unimplemented!")
+    #[test]
+    fn resync_wait_for_frame_end() {
+        let mut codec = NomCodec::resyncing(syslog_rfc5424_in_rfc5425_frame, Resync::OctetCounting, 10);
+        let mut buf = EasyBuf::from(b"10 <165>1 ba".to_vec());
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(codec.errors(), 0);
     }
 }
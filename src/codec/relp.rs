@@ -0,0 +1,211 @@
+use std::io::Write;
+use std::io::Result as IoResult;
+
+use tokio_core::io::{Codec, EasyBuf};
+use nom::{ErrorKind, is_digit, is_alphabetic};
+
+use codec::parse;
+use codec::nom::decode_with;
+
+/// RELP command frame sent by the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelpFrame {
+    pub txnr: usize,
+    pub command: String,
+    pub data: Vec<u8>,
+}
+
+/// RELP response to command with given transaction number
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelpResponse {
+    pub txnr: usize,
+    pub data: Vec<u8>,
+}
+
+impl RelpResponse {
+    pub fn ok(txnr: usize) -> RelpResponse {
+        RelpResponse::status(txnr, 200, "OK")
+    }
+
+    pub fn status(txnr: usize, code: u16, message: &str) -> RelpResponse {
+        RelpResponse {
+            txnr: txnr,
+            data: format!("{} {}", code, message).into_bytes()
+        }
+    }
+
+    /// Response to open command listing offers supported by this server
+    pub fn open(txnr: usize) -> RelpResponse {
+        RelpResponse {
+            txnr: txnr,
+            data: b"200 OK\nrelp_version=0\nrelp_software=logslash\ncommands=syslog".to_vec()
+        }
+    }
+
+    /// Response to close command has no data
+    pub fn close(txnr: usize) -> RelpResponse {
+        RelpResponse {
+            txnr: txnr,
+            data: Vec::new()
+        }
+    }
+}
+
+// TXNR SP COMMAND SP DATALEN [SP DATA] TRAILER
+named!(pub relp_frame<&[u8], RelpFrame>, do_parse!(
+        txnr: return_error!(ErrorKind::Custom(1),
+            terminated!(map_res!(take_while1!(is_digit), parse::int_usize), tag!(b" "))) >>
+        command: return_error!(ErrorKind::Custom(2),
+            terminated!(map_res!(take_while1!(is_alphabetic), parse::string), tag!(b" "))) >>
+        data_len: return_error!(ErrorKind::Custom(3),
+            map_res!(take_while1!(is_digit), parse::int_usize)) >>
+        data: cond!(data_len > 0, preceded!(tag!(b" "), take!(data_len))) >>
+        return_error!(ErrorKind::Custom(4), tag!(b"\n")) >>
+        (RelpFrame {
+            txnr: txnr,
+            command: command.to_owned(),
+            data: data.map(|d| d.to_vec()).unwrap_or_default()
+        })
+    ));
+
+pub mod simple_errors {
+    use super::RelpFrame;
+    use nom::{IResult, ErrorKind};
+
+    pub fn relp_frame(input: &[u8]) -> IResult<&[u8], RelpFrame, &'static str> {
+        super::relp_frame(input).map_err(|err| ErrorKind::Custom(match err {
+            ErrorKind::Custom(1) => "Expected RELP transaction number",
+            ErrorKind::Custom(2) => "Expected RELP command",
+            ErrorKind::Custom(3) => "Expected RELP data length",
+            ErrorKind::Custom(4) => "Expected RELP frame trailer",
+            _ => "RELP frame parser did not match"
+        }))
+    }
+}
+
+pub mod parse_errors {
+    use std::str::from_utf8;
+    use super::{RelpFrame, simple_errors};
+    use codec::error::ParseError;
+    use nom::{IResult, ErrorKind, is_digit};
+
+    // offset of the space separated header field with given index
+    fn field_offset(input: &[u8], index: usize) -> usize {
+        let offset: usize = input.split(|&byte| byte == b' ').take(index).map(|field| field.len() + 1).sum();
+        if offset > input.len() { input.len() } else { offset }
+    }
+
+    fn failed_field(message: &'static str) -> (&'static str, usize) {
+        match message {
+            "Expected RELP transaction number" => ("txnr", 0),
+            "Expected RELP command" => ("command", 1),
+            "Expected RELP data length" => ("data_len", 2),
+            "Expected RELP frame trailer" => ("trailer", 3),
+            _ => ("frame", 0)
+        }
+    }
+
+    fn trailer_offset(input: &[u8]) -> usize {
+        let start = field_offset(input, 2);
+        let digits = input[start..].iter().take_while(|&&byte| is_digit(byte)).count();
+        let data_len = from_utf8(&input[start..start + digits]).ok().and_then(|len| len.parse::<usize>().ok()).unwrap_or(0);
+        let offset = start + digits + if data_len > 0 { 1 + data_len } else { 0 };
+        if offset > input.len() { input.len() } else { offset }
+    }
+
+    pub fn relp_frame(input: &[u8]) -> IResult<&[u8], RelpFrame, ParseError> {
+        simple_errors::relp_frame(input).map_err(|err| {
+            let message = match err {
+                ErrorKind::Custom(message) => message,
+                _ => "RELP frame parser did not match"
+            };
+            let (field, index) = failed_field(message);
+            let offset = if field == "trailer" { trailer_offset(input) } else { field_offset(input, index) };
+            ErrorKind::Custom(ParseError::new(field, message, input, offset))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RelpCodec;
+
+impl Codec for RelpCodec {
+    type In = RelpFrame;
+    type Out = RelpResponse;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> IoResult<Option<Self::In>> {
+        decode_with(buf, parse_errors::relp_frame)
+    }
+
+    fn encode(&mut self, response: Self::Out, buf: &mut Vec<u8>) -> IoResult<()> {
+        write!(buf, "{} rsp {}", response.txnr, response.data.len())?;
+        if !response.data.is_empty() {
+            buf.push(b' ');
+            buf.extend_from_slice(&response.data);
+        }
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod relp_frame_tests {
+    use super::simple_errors::relp_frame;
+    use super::{RelpCodec, RelpResponse, parse_errors};
+    use tokio_core::io::Codec;
+    use nom::{IResult, ErrorKind};
+
+    #[test]
+    fn open() {
+        let (i, o) = relp_frame(b"1 open 86 relp_version=0\nrelp_software=librelp,1.2.12,http://librelp.adiscon.com\ncommands=syslog\n").unwrap();
+        assert!(i.is_empty());
+        assert_eq!(o.txnr, 1);
+        assert_eq!(o.command, "open");
+        assert_eq!(o.data, b"relp_version=0\nrelp_software=librelp,1.2.12,http://librelp.adiscon.com\ncommands=syslog".to_vec());
+    }
+
+    #[test]
+    fn no_data() {
+        let (i, o) = relp_frame(b"3 close 0\nEOF").unwrap();
+        assert_eq!(i, &b"EOF"[..]);
+        assert_eq!(o.txnr, 3);
+        assert_eq!(o.command, "close");
+        assert!(o.data.is_empty());
+    }
+
+    #[test]
+    fn incomplete() {
+        relp_frame(b"2 syslog 80 <165>1 2003").unwrap_inc();
+    }
+
+    #[test]
+    fn bad_trailer() {
+        let err = relp_frame(b"3 close 0X").unwrap_err();
+        assert_matches!(err, ErrorKind::Custom("Expected RELP frame trailer"));
+    }
+
+    #[test]
+    fn error_location() {
+        let err = match parse_errors::relp_frame(b"3 close 0X") {
+            IResult::Error(ErrorKind::Custom(err)) => err,
+            _ => panic!("expected parse error")
+        };
+        assert_eq!(err.field, "trailer");
+        assert_eq!(err.offset, 9);
+
+        let err = match parse_errors::relp_frame(b"3 c1ose 0\n") {
+            IResult::Error(ErrorKind::Custom(err)) => err,
+            _ => panic!("expected parse error")
+        };
+        assert_eq!(err.field, "command");
+        assert_eq!(err.offset, 2);
+    }
+
+    #[test]
+    fn encode() {
+        let mut buf = Vec::new();
+        RelpCodec.encode(RelpResponse::ok(2), &mut buf).unwrap();
+        RelpCodec.encode(RelpResponse::close(3), &mut buf).unwrap();
+        assert_eq!(buf, b"2 rsp 6 200 OK\n3 rsp 0\n".to_vec());
+    }
+}
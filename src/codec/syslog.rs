@@ -1,39 +1,75 @@
 use std::mem;
+use std::cmp;
 use std::borrow::Cow;
 use std::iter;
 use std::slice;
 use std::iter::once;
+use std::io::Write;
+use std::net::IpAddr;
 
 use tokio_core::io::Codec;
 use tokio_core::io::EasyBuf;
 use std::io::Result as IoResult;
-use nom::{ErrorKind, rest};
-use maybe_string::{MaybeStr, MaybeString};
-use chrono::{DateTime, UTC, FixedOffset};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use nom::{IResult, ErrorKind, rest};
+use maybe_string::MaybeStr;
+use chrono::{DateTime, UTC, FixedOffset, Timelike};
 use uuid::Uuid;
 
 use codec::parse;
-use codec::nom::NomCodec;
+use codec::DecodeError;
+pub use codec::Framing;
+use codec::error::ParseError;
 
 use event::{Payload, MetaValue, Event, LogstashEvent};
+use serialize::Serializer;
 
-// TODO: use &str instead of String; make OwnedSyslogMessage variant that is Send
+fn owned(s: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
 
 #[derive(Debug, Clone)]
-pub struct StructuredElement {
-    pub id: String,
-    pub params: Vec<(String, String)>
+pub struct StructuredElement<'a> {
+    pub id: Cow<'a, str>,
+    pub params: Vec<(Cow<'a, str>, Cow<'a, str>)>
+}
+
+impl<'a> StructuredElement<'a> {
+    pub fn into_owned(self) -> StructuredElement<'static> {
+        StructuredElement {
+            id: owned(self.id),
+            params: self.params.into_iter().map(|(key, value)| (owned(key), owned(value))).collect()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
-pub struct StructuredData {
-    pub elements: Vec<StructuredElement>
+pub struct StructuredData<'a> {
+    pub elements: Vec<StructuredElement<'a>>
+}
+
+impl<'a> StructuredData<'a> {
+    pub fn into_owned(self) -> StructuredData<'static> {
+        StructuredData {
+            elements: self.elements.into_iter().map(StructuredElement::into_owned).collect()
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Message {
-    String(String),
-    MaybeString(MaybeString)
+pub enum Message<'a> {
+    String(Cow<'a, str>),
+    MaybeString(Cow<'a, MaybeStr>)
+}
+
+impl<'a> Message<'a> {
+    pub fn into_owned(self) -> Message<'static> {
+        match self {
+            Message::String(s) => Message::String(owned(s)),
+            Message::MaybeString(ms) => Message::MaybeString(Cow::Owned(ms.into_owned())),
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -100,50 +136,162 @@ impl Severity {
     }
 }
 
+/// Syslog message borrowing its fields from the parsed frame where possible
+///
+/// Parsers return events referencing the input so that a message can be serialized without
+/// copying it. Use into_owned() to get an OwnedSyslogEvent that is Send and can outlive the
+/// input buffer (e.g. to pass it to write_threaded). SyslogCodec produces owned events as
+/// decoded items need to outlive the read buffer; use SyslogCodec::serialized or
+/// SyslogCodec::decode_with to work with the borrowed event instead.
 #[derive(Debug, Clone)]
-pub struct SyslogEvent {
+pub struct SyslogEvent<'a> {
     pub facility: Facility,
     pub severity: Severity,
     pub timestamp: DateTime<FixedOffset>,
-    pub hostname: String,
-    pub program: Option<String>,
-    pub proc_id: Option<String>,
-    pub msg_id: Option<String>,
-    pub structured_data: Option<StructuredData>,
-    pub message: Option<Message>,
+    /// Empty if RFC 3164 message did not carry hostname
+    pub hostname: Cow<'a, str>,
+    pub program: Option<Cow<'a, str>>,
+    pub proc_id: Option<Cow<'a, str>>,
+    pub msg_id: Option<Cow<'a, str>>,
+    pub structured_data: Option<StructuredData<'a>>,
+    pub message: Option<Message<'a>>,
     //TODO: shold this be required in Event format?
     pub processed: DateTime<UTC>,
+    pub protocol: Protocol,
+    /// None if message was not received in a stream
+    pub framing: Option<Framing>,
+    /// Assigned once by SyslogCodec and syslog inputs (see SyslogEvent::identified); empty when
+    /// event came straight from a parser
+    pub id: Cow<'a, str>,
 }
 
-impl SyslogEvent {
+/// SyslogEvent not borrowing from the input
+pub type OwnedSyslogEvent = SyslogEvent<'static>;
+
+/// How event ID is assigned to parsed message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdStrategy {
+    /// Random UUID v4; re-delivered message gets new ID
+    Random,
+    /// UUID v5 of source and raw message so that re-delivered message gets the same ID
+    ContentHash,
+    /// Message ID if provided (RFC 5424) or ContentHash
+    ///
+    /// RFC 5424 MSGID identifies type of the message, not the message, so this is only safe
+    /// when senders put unique value in it; otherwise different messages get the same ID and
+    /// overwrite each other in Elasticsearch (document _id).
+    MsgId,
+}
+
+impl Default for IdStrategy {
+    fn default() -> IdStrategy {
+        IdStrategy::ContentHash
+    }
+}
+
+// UUID v5 namespace of content hash IDs
+const CONTENT_HASH_NAMESPACE: [u8; 16] = [0x6c, 0x6f, 0x67, 0x73, 0x6c, 0x61, 0x5f, 0x68, 0x82, 0x5e, 0x1a, 0x3d, 0x9b, 0x07, 0xc4, 0x11];
+
+const HEX_DIGITS: &'static [u8] = b"0123456789abcdef";
+
+// raw message may not be UTF-8 so it is hashed hex encoded
+fn content_hash_id(source: &str, raw: &[u8]) -> String {
+    let namespace = Uuid::from_bytes(&CONTENT_HASH_NAMESPACE).expect("namespace is 16 bytes long");
+    let mut name = String::with_capacity(source.len() + 1 + raw.len() * 2);
+    name.push_str(source);
+    name.push(' ');
+    for &byte in raw {
+        name.push(HEX_DIGITS[(byte >> 4) as usize] as char);
+        name.push(HEX_DIGITS[(byte & 0xf) as usize] as char);
+    }
+    Uuid::new_v5(&namespace, &name).simple().to_string()
+}
+
+impl<'a> SyslogEvent<'a> {
     //TODO: in \n separated TCP stream the ctrl chars are be escaped using # + octal encoding by
     //popular log agents
-    fn decode_newlines(self) -> SyslogEvent {
-        if let Some(Message::String(s)) = self.message {
-            SyslogEvent { message: Some(Message::String(s.replace("#012", "\n"))), .. self }
-        } else {
-            self
+    fn decode_newlines(self) -> SyslogEvent<'a> {
+        // message stays borrowed unless there is something to replace
+        let decoded = match self.message {
+            Some(Message::String(ref s)) if s.contains("#012") => Some(s.replace("#012", "\n")),
+            _ => None
+        };
+
+        match decoded {
+            Some(s) => SyslogEvent { message: Some(Message::String(Cow::Owned(s))), .. self },
+            None => self
+        }
+    }
+
+    /// Assign event ID using given strategy; raw is the message as parsed (without framing)
+    ///
+    /// Parsers leave ID empty; SyslogCodec and syslog inputs call this once for each message.
+    /// Events that were not identified get content hash of their RFC 5424 form from Event::id.
+    pub fn identified(self, strategy: IdStrategy, raw: &[u8]) -> SyslogEvent<'a> {
+        let id = match (strategy, &self.msg_id) {
+            (IdStrategy::MsgId, &Some(ref msg_id)) => msg_id.clone(),
+            (IdStrategy::Random, _) => Cow::Owned(Uuid::new_v4().simple().to_string()),
+            (IdStrategy::MsgId, &None) | (IdStrategy::ContentHash, _) => Cow::Owned(content_hash_id(&self.hostname, raw)),
+        };
+        SyslogEvent { id: id, .. self }
+    }
+
+    /// Assigned ID or, if there is none, content hash of the event written as RFC 5424 message
+    /// so that events of bare parsers still get stable non-empty ID
+    fn event_id(&self) -> Cow<str> {
+        if !self.id.is_empty() {
+            return Cow::Borrowed(&self.id)
+        }
+        let mut raw = Vec::with_capacity(128);
+        write_rfc5424(self, Framing::OctetCounting, &mut raw).expect("writing to Vec does not fail");
+        Cow::Owned(content_hash_id(&self.hostname, &raw))
+    }
+
+    fn framed(self, framing: Framing) -> SyslogEvent<'a> {
+        let event = SyslogEvent { framing: Some(framing), .. self };
+        match framing {
+            Framing::Newline => event.decode_newlines(),
+            Framing::OctetCounting => event,
+        }
+    }
+
+    /// Copy borrowed fields so the event can be sent to other threads
+    pub fn into_owned(self) -> OwnedSyslogEvent {
+        SyslogEvent {
+            facility: self.facility,
+            severity: self.severity,
+            timestamp: self.timestamp,
+            hostname: owned(self.hostname),
+            program: self.program.map(owned),
+            proc_id: self.proc_id.map(owned),
+            msg_id: self.msg_id.map(owned),
+            structured_data: self.structured_data.map(StructuredData::into_owned),
+            message: self.message.map(Message::into_owned),
+            processed: self.processed,
+            protocol: self.protocol,
+            framing: self.framing,
+            id: owned(self.id),
         }
     }
 }
 
 struct FieldIterator<'i> {
-    event: &'i SyslogEvent,
-    fields: slice::Iter<'i, (&'static str, fn(&'i SyslogEvent) -> Option<MetaValue<'i>>)>
+    event: &'i SyslogEvent<'i>,
+    fields: slice::Iter<'i, (&'static str, fn(&'i SyslogEvent<'i>) -> Option<MetaValue<'i>>)>
 }
 
 impl<'i> FieldIterator<'i> {
-    fn new(event: &'i SyslogEvent) -> FieldIterator<'i> {
-        fn program(event: &SyslogEvent) -> Option<MetaValue> {
-            event.program.as_ref().map(|v| MetaValue::String(v))
+    fn new(event: &'i SyslogEvent<'i>) -> FieldIterator<'i> {
+        fn program<'e>(event: &'e SyslogEvent<'e>) -> Option<MetaValue<'e>> {
+            event.program.as_ref().map(|v| MetaValue::String(Cow::Borrowed(&**v)))
         }
 
-        fn proc_id(event: &SyslogEvent) -> Option<MetaValue> {
-            event.proc_id.as_ref().map(|v| MetaValue::String(v))
+        fn proc_id<'e>(event: &'e SyslogEvent<'e>) -> Option<MetaValue<'e>> {
+            event.proc_id.as_ref().map(|v| MetaValue::String(Cow::Borrowed(&**v)))
         }
 
-        fn facility(event: &SyslogEvent) -> Option<MetaValue> {
-            Some(MetaValue::String(match event.facility {
+        fn facility<'e>(event: &'e SyslogEvent<'e>) -> Option<MetaValue<'e>> {
+            Some(MetaValue::String(Cow::Borrowed(match event.facility {
                 Facility::KernelMessages => "kernel",
                 Facility::UserLevelMessages => "user-level",
                 Facility::MailSystem => "mail",
@@ -168,11 +316,11 @@ impl<'i> FieldIterator<'i> {
                 Facility::Local5 => "local5",
                 Facility::Local6 => "local6",
                 Facility::Local7 => "local7",
-            }))
+            })))
         }
 
-        fn severity(event: &SyslogEvent) -> Option<MetaValue> {
-            Some(MetaValue::String(match event.severity {
+        fn severity<'e>(event: &'e SyslogEvent<'e>) -> Option<MetaValue<'e>> {
+            Some(MetaValue::String(Cow::Borrowed(match event.severity {
                 Severity::Emergency => "Emergency",
                 Severity::Alert => "Alert",
                 Severity::Critical => "Critical",
@@ -181,14 +329,30 @@ impl<'i> FieldIterator<'i> {
                 Severity::Notice => "Notice",
                 Severity::Informational => "Informational",
                 Severity::Debug => "Debug",
-            }))
+            })))
+        }
+
+        fn protocol<'e>(event: &'e SyslogEvent<'e>) -> Option<MetaValue<'e>> {
+            Some(MetaValue::String(Cow::Borrowed(match event.protocol {
+                Protocol::Rfc5424 => "rfc5424",
+                Protocol::Rfc3164 => "rfc3164",
+            })))
         }
 
-        static FIELDS: [(&'static str, fn(&SyslogEvent) -> Option<MetaValue>); 4] = [
+        fn framing<'e>(event: &'e SyslogEvent<'e>) -> Option<MetaValue<'e>> {
+            event.framing.map(|framing| MetaValue::String(Cow::Borrowed(match framing {
+                Framing::Newline => "newline",
+                Framing::OctetCounting => "octet-counting",
+            })))
+        }
+
+        static FIELDS: [(&'static str, for<'e> fn(&'e SyslogEvent<'e>) -> Option<MetaValue<'e>>); 6] = [
             ("program", program),
             ("proc_id", proc_id),
             ("facility", facility),
             ("severity", severity),
+            ("syslog_protocol", protocol),
+            ("syslog_framing", framing),
         ];
 
         FieldIterator {
@@ -214,14 +378,40 @@ impl<'i> Iterator for FieldIterator<'i> {
      }
 }
 
+/// Structured data parameter value as boolean or number if it reads back as the same text (so
+/// "3" is a number but "03" or "1e3" stay strings); string otherwise
+fn param_value(value: &str) -> MetaValue {
+    match value {
+        "true" => return MetaValue::Bool(true),
+        "false" => return MetaValue::Bool(false),
+        _ => ()
+    }
+    if let Ok(number) = value.parse::<i64>() {
+        if number.to_string() == value {
+            return MetaValue::I64(number)
+        }
+    }
+    if let Ok(number) = value.parse::<u64>() {
+        if number.to_string() == value {
+            return MetaValue::U64(number)
+        }
+    }
+    if let Ok(number) = value.parse::<f64>() {
+        if number.is_finite() && number.to_string() == value {
+            return MetaValue::F64(number)
+        }
+    }
+    MetaValue::String(Cow::Borrowed(value))
+}
+
 struct StructuredElementsIterator<'i> {
-    inner: iter::Map<slice::Iter<'i, StructuredElement>, fn(&'i StructuredElement) -> (&'i str, slice::Iter<'i, (String, String)>)>
+    inner: iter::Map<slice::Iter<'i, StructuredElement<'i>>, fn(&'i StructuredElement<'i>) -> (&'i str, slice::Iter<'i, (Cow<'i, str>, Cow<'i, str>)>)>
 }
 
 impl<'i> StructuredElementsIterator<'i> {
-    fn new(structured_elements: &'i[StructuredElement]) -> StructuredElementsIterator<'i> {
-        fn params<'i>(se: &'i StructuredElement) -> (&'i str, slice::Iter<'i, (String, String)>) {
-            (se.id.as_str(), se.params.iter())
+    fn new(structured_elements: &'i[StructuredElement<'i>]) -> StructuredElementsIterator<'i> {
+        fn params<'i>(se: &'i StructuredElement<'i>) -> (&'i str, slice::Iter<'i, (Cow<'i, str>, Cow<'i, str>)>) {
+            (&se.id, se.params.iter())
         }
 
         StructuredElementsIterator {
@@ -235,18 +425,14 @@ impl<'i> Iterator for StructuredElementsIterator<'i> {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next().map(|(id, params)|
-            (id, MetaValue::Object(Box::new(params.map(|&(ref key, ref value)| (key.as_str(), MetaValue::String(value.as_str()))))))
+            (id, MetaValue::Object(Box::new(params.map(|&(ref key, ref value)| (&**key, param_value(value))))))
         )
     }
 }
 
-impl Event for SyslogEvent {
+impl<'a> Event for SyslogEvent<'a> {
     fn id(&self) -> Cow<str> {
-        if let Some(ref msg_id) = self.msg_id {
-            Cow::Borrowed(msg_id)
-        } else {
-            Cow::Owned(Uuid::new_v4().simple().to_string())
-        }
+        self.event_id()
     }
 
     fn source(&self) -> Cow<str> {
@@ -278,7 +464,7 @@ impl Event for SyslogEvent {
     //TODO: way to attach meta iterator chains e.g. process_meta(|m| m.filter(foo).map(bar))
 }
 
-impl LogstashEvent for SyslogEvent {
+impl<'a> LogstashEvent for SyslogEvent<'a> {
     fn timestamp(&self) -> DateTime<UTC> {
         self.timestamp.with_timezone(&UTC)
     }
@@ -286,7 +472,7 @@ impl LogstashEvent for SyslogEvent {
     fn message(&self) -> Option<Cow<str>> {
         match self.message {
             Some(Message::String(ref s)) => Some(Cow::Borrowed(s)),
-            Some(Message::MaybeString(ref ms)) => Some(Cow::Owned(ms.as_maybe_str().to_lossy_string())),
+            Some(Message::MaybeString(ref ms)) => Some(Cow::Owned(ms.to_lossy_string())),
             None => None
         }
     }
@@ -295,7 +481,7 @@ impl LogstashEvent for SyslogEvent {
         "syslog"
     }
 
-    fn tags(&self) -> Vec<&'static str> {
+    fn tags(&self) -> Vec<&str> {
         vec!["class:syslog"]
     }
 
@@ -304,11 +490,7 @@ impl LogstashEvent for SyslogEvent {
     }
 
     fn id(&self) -> Cow<str> {
-        if let Some(ref msg_id) = self.msg_id {
-            Cow::Borrowed(msg_id)
-        } else {
-            Cow::Owned(Uuid::new_v4().simple().to_string())
-        }
+        self.event_id()
     }
 
     fn fields<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
@@ -323,7 +505,7 @@ impl LogstashEvent for SyslogEvent {
 }
 
 named!(priority<&[u8], u8>, return_error!(ErrorKind::Custom(1),
-    complete!(delimited!(tag!(b"<"), map_res!(take_until!(">1"), parse::int_u8), tag!(b">")))));
+    complete!(delimited!(tag!(b"<"), map_res!(take_until!(">"), parse::int_u8), tag!(b">")))));
 
 named!(timestamp<&[u8], DateTime<FixedOffset> >, return_error!(ErrorKind::Custom(2),
     complete!(terminated!(map_res!(take_until!(" "), parse::timestamp), tag!(b" ")))));
@@ -344,15 +526,23 @@ named!(program<&[u8], Option<&str> >, return_error!(ErrorKind::Custom(4), opt_st
 named!(proc_id<&[u8], Option<&str> >, return_error!(ErrorKind::Custom(5), opt_str));
 named!(msg_id<&[u8], Option<&str> >, return_error!(ErrorKind::Custom(6), opt_str));
 
-named!(structured_data_param<&[u8], (&str, String)>, return_error!(ErrorKind::Custom(12), complete!(do_parse!(
+// value stays borrowed unless it contains escape sequences
+fn unescape_param_value(value: &str) -> Cow<str> {
+    if value.contains('\\') {
+        Cow::Owned(value.replace("\\\"", "\"")
+            .replace("\\\\", "\\")
+            .replace("\\]", "]"))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+named!(structured_data_param<&[u8], (&str, Cow<str>)>, return_error!(ErrorKind::Custom(12), complete!(do_parse!(
         name: map_res!(take_until!("="), parse::string) >>
         tag!(b"=\"") >>
         value: map!(
             map_res!(escaped!(is_not!("\"\\"), '\\', is_a!("\"\\]")), parse::string),
-            |s: &str|
-                s.replace("\\\"", "\"")
-                .replace("\\\\", "\\")
-                .replace("\\]", "]")) >>
+            unescape_param_value) >>
         tag!(b"\"") >>
         (name, value)
     ))));
@@ -364,8 +554,8 @@ named!(structured_data_element<&[u8], StructuredElement>, return_error!(ErrorKin
         params: separated_list!(tag!(b" "), call!(structured_data_param)) >>
         tag!(b"]") >>
         (StructuredElement {
-            id: id.to_owned(),
-            params: params.into_iter().map(|(k, v)| (k.to_owned(), v)).collect()
+            id: Cow::Borrowed(id),
+            params: params.into_iter().map(|(k, v)| (Cow::Borrowed(k), v)).collect()
         })
     ))));
 
@@ -388,48 +578,57 @@ named!(message<&[u8], Option<Message> >, return_error!(ErrorKind::Custom(20), do
             bom: opt!(tag!(BOM)) >>
             ret: map_res!(rest,
                 |bytes| if bom.is_some() {
-                    parse::string(bytes).map(|s| Message::String(s.to_owned()))
+                    parse::string(bytes).map(|s| Message::String(Cow::Borrowed(s)))
                 } else {
-                    Ok(Message::MaybeString(MaybeStr::from_bytes(bytes).to_maybe_string()))
+                    Ok(Message::MaybeString(Cow::Borrowed(MaybeStr::from_bytes(bytes))))
                 }) >>
             (ret)
         )) >>
         (ret)
     )));
 
-named!(pub syslog_rfc5424<&[u8], SyslogEvent>, complete!(do_parse!(
-    facility: map_res!(peek!(priority), |p| Facility::from_priority(p)) >>
-    severity: map!(priority, |p| Severity::from_priority(p)) >>
-    tag!(b"1 ") >> // Fromat version 1
-    timestamp: timestamp >>
-    hostname: hostname >>
-    program: program >>
-    proc_id: proc_id >>
-    msg_id: msg_id >>
-    structured_data: structured_data >>
-    message: message >>
-    (SyslogEvent {
-        facility: facility,
-        severity: severity,
-        timestamp: timestamp,
-        hostname: hostname.to_owned(),
-        program: program.map(|s| s.to_owned()),
-        proc_id: proc_id.map(|s| s.to_owned()),
-        msg_id: msg_id.map(|s| s.to_owned()),
-        structured_data: structured_data,
-        message: message,
-        processed: UTC::now(),
-    }))));
+/// Event ID is left empty (see SyslogEvent::identified)
+pub fn syslog_rfc5424(input: &[u8]) -> IResult<&[u8], SyslogEvent> {
+    complete!(input, do_parse!(
+        facility: map_res!(peek!(priority), |p| Facility::from_priority(p)) >>
+        severity: map!(priority, |p| Severity::from_priority(p)) >>
+        tag!(b"1 ") >> // Fromat version 1
+        timestamp: timestamp >>
+        hostname: hostname >>
+        program: program >>
+        proc_id: proc_id >>
+        msg_id: msg_id >>
+        structured_data: structured_data >>
+        message: message >>
+        (SyslogEvent {
+            facility: facility,
+            severity: severity,
+            timestamp: timestamp,
+            hostname: Cow::Borrowed(hostname),
+            program: program.map(Cow::Borrowed),
+            proc_id: proc_id.map(Cow::Borrowed),
+            msg_id: msg_id.map(Cow::Borrowed),
+            structured_data: structured_data,
+            message: message,
+            processed: UTC::now(),
+            protocol: Protocol::Rfc5424,
+            framing: None,
+            id: Cow::Borrowed(""),
+        })))
+}
+
+named!(pub syslog_rfc5425_frame_length<&[u8], usize>, return_error!(ErrorKind::Custom(1),
+        terminated!(map_res!(take_until!(" "), parse::int_usize), tag!(" "))));
 
 named!(pub syslog_rfc5425_frame<&[u8], &[u8]>, do_parse!(
-        msg_len: return_error!(ErrorKind::Custom(1),
-           terminated!(map_res!(take_until!(" "), parse::int_u8), tag!(" "))) >>
+        msg_len: syslog_rfc5425_frame_length >>
         syslog_msg: take!(msg_len) >>
         (syslog_msg)
     ));
 
-named!(pub syslog_rfc5424_in_rfc5425_frame<&[u8], SyslogEvent>,
-       flat_map!(call!(syslog_rfc5425_frame), call!(syslog_rfc5424)));
+named!(pub syslog_rfc5424_in_rfc5425_frame<&[u8], SyslogEvent>, map!(
+       flat_map!(call!(syslog_rfc5425_frame), call!(syslog_rfc5424)),
+       |m: SyslogEvent| m.framed(Framing::OctetCounting)));
 
 // framing not allowing to use \n in messages - use #012 to represent \n and replace in final
 // message
@@ -437,25 +636,137 @@ named!(pub syslog_newline_frame<&[u8], &[u8]>, terminated!(take_until!("\n"), ta
 
 named!(pub syslog_rfc5424_in_newline_frame<&[u8], SyslogEvent>, map!(
        flat_map!(call!(syslog_newline_frame), call!(syslog_rfc5424)),
-       |m: SyslogEvent| m.decode_newlines()));
+       |m: SyslogEvent| m.framed(Framing::Newline)));
+
+/// Configuration needed to interpret RFC 3164 messages
+#[derive(Debug, Clone, Copy)]
+pub struct Rfc3164Config {
+    /// Time zone of senders as RFC 3164 timestamps carry no offset
+    pub timezone: FixedOffset,
+    /// Current time used to infer year of RFC 3164 timestamps
+    pub clock: fn() -> DateTime<UTC>,
+}
+
+impl Default for Rfc3164Config {
+    fn default() -> Rfc3164Config {
+        Rfc3164Config {
+            timezone: FixedOffset::east(0),
+            clock: UTC::now,
+        }
+    }
+}
+
+fn bsd_timestamp<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], DateTime<FixedOffset>> {
+    return_error!(input, ErrorKind::Custom(2), complete!(alt!(
+        terminated!(map_res!(take_until!(" "), parse::timestamp), tag!(b" ")) |
+        terminated!(map_res!(take!(15), |bytes| parse::bsd_timestamp(bytes, &config.timezone, (config.clock)())), tag!(b" "))
+    )))
+}
+
+fn not_tag(s: &str) -> Result<&str, &'static str> {
+    if s.ends_with(':') || s.contains('[') {
+        Err("not a hostname")
+    } else {
+        Ok(s)
+    }
+}
+
+named!(bsd_word<&[u8], &str>, complete!(terminated!(
+        map_res!(map_res!(take_until!(" "), parse::string), not_tag),
+        tag!(b" ")
+    )));
+
+// IP address or domain name
+fn is_address(s: &str) -> bool {
+    s.parse::<IpAddr>().is_ok() ||
+        (s.contains('.') && !s.starts_with('.') && !s.ends_with('.') && s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '.'))
+}
+
+// messages sent to local syslog socket have no hostname; first word is taken for hostname only
+// if it is followed by a tag or looks like an address so that "Use the BFG!" stays a message
+fn bsd_hostname(input: &[u8]) -> IResult<&[u8], Option<&str>> {
+    if let IResult::Done(rest, word) = bsd_word(input) {
+        let tag_follows = match bsd_tag(rest) {
+            IResult::Done(_, tag) => tag.is_some(),
+            _ => false
+        };
+        if tag_follows || is_address(word) {
+            return IResult::Done(rest, Some(word))
+        }
+    }
+    IResult::Done(input, None)
+}
+
+fn is_tag_char(c: u8) -> bool {
+    c != b'[' && c != b':' && c != b' '
+}
+
+// TAG[PID]: - both program and process ID are optional
+named!(bsd_tag<&[u8], Option<(&str, Option<&str>)> >, opt!(complete!(do_parse!(
+        program: map_res!(take_while1!(is_tag_char), parse::string) >>
+        proc_id: opt!(complete!(delimited!(tag!(b"["), map_res!(take_until!("]"), parse::string), tag!(b"]")))) >>
+        tag!(b":") >>
+        (program, proc_id)
+    ))));
+
+// RFC 3164 does not specify encoding; use string if it is valid UTF-8
+named!(bsd_message<&[u8], Option<Message> >, do_parse!(
+        opt!(complete!(tag!(b" "))) >>
+        bytes: rest >>
+        (if bytes.is_empty() {
+            None
+        } else {
+            Some(match parse::string(bytes) {
+                Ok(s) => Message::String(Cow::Borrowed(s)),
+                Err(_) => Message::MaybeString(Cow::Borrowed(MaybeStr::from_bytes(bytes)))
+            })
+        })
+    ));
+
+/// Event ID is left empty (see SyslogEvent::identified)
+pub fn syslog_rfc3164<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>> {
+    complete!(input, do_parse!(
+        facility: map_res!(peek!(priority), |p| Facility::from_priority(p)) >>
+        severity: map!(priority, |p| Severity::from_priority(p)) >>
+        timestamp: call!(bsd_timestamp, config) >>
+        hostname: bsd_hostname >>
+        tag: bsd_tag >>
+        message: bsd_message >>
+        (SyslogEvent {
+            facility: facility,
+            severity: severity,
+            timestamp: timestamp,
+            hostname: Cow::Borrowed(hostname.unwrap_or("")),
+            program: tag.map(|(program, _)| Cow::Borrowed(program)),
+            proc_id: tag.and_then(|(_, proc_id)| proc_id).map(Cow::Borrowed),
+            msg_id: None,
+            structured_data: None,
+            message: message,
+            processed: UTC::now(),
+            protocol: Protocol::Rfc3164,
+            framing: None,
+            id: Cow::Borrowed(""),
+        })))
+}
+
+pub fn syslog_rfc3164_in_newline_frame<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>> {
+    map!(input,
+         flat_map!(call!(syslog_newline_frame), call!(syslog_rfc3164, config)),
+         |m: SyslogEvent| m.framed(Framing::Newline))
+}
 
 pub mod simple_errors {
-    use super::SyslogEvent;
+    use super::{SyslogEvent, Rfc3164Config, Framing, Protocol};
     use nom::{IResult, ErrorKind};
 
     pub fn syslog_rfc5424(input: &[u8]) -> IResult<&[u8], SyslogEvent, &'static str> {
-        super::syslog_rfc5424(input).map_err(|err| ErrorKind::Custom(match err {
-            ErrorKind::Custom(1) => "Bad syslog priority tag format",
-            ErrorKind::Custom(2) => "Unrecognized syslog timestamp format",
-            ErrorKind::Custom(3) => "Expected syslog hostname",
-            ErrorKind::Custom(4) => "Expected syslog application name",
-            ErrorKind::Custom(5) => "Expected syslog process ID",
-            ErrorKind::Custom(6) => "Expected syslog message ID",
-            ErrorKind::Custom(7) => "Expected valid syslog structured data",
-            ErrorKind::Custom(11) => "Failed to parse structured data element",
-            ErrorKind::Custom(12) => "Failed to parse structured data parameter",
-            ErrorKind::Custom(20) => "Bad syslog message payload encoding",
-            _ => "Syslog parser did not match"
+        super::syslog_rfc5424(input).map_err(|err| ErrorKind::Custom(super::rfc5424_message(&err)))
+    }
+
+    pub fn syslog_rfc5425_frame_length(input: &[u8]) -> IResult<&[u8], usize, &'static str> {
+        super::syslog_rfc5425_frame_length(input).map_err(|err| ErrorKind::Custom(match err {
+            ErrorKind::Custom(1) => "Expected syslog RFC5425 frame message length",
+            _ => "Syslog RFC5425 frame length parser did not match"
         }))
     }
 
@@ -466,8 +777,9 @@ pub mod simple_errors {
         }))
     }
 
-    named!(pub syslog_rfc5424_in_rfc5425_frame<&[u8], SyslogEvent, &'static str>,
-           flat_map!(call!(syslog_rfc5425_frame), call!(syslog_rfc5424)));
+    named!(pub syslog_rfc5424_in_rfc5425_frame<&[u8], SyslogEvent, &'static str>, map!(
+           flat_map!(call!(syslog_rfc5425_frame), call!(syslog_rfc5424)),
+           |m: SyslogEvent| m.framed(Framing::OctetCounting)));
 
     pub fn syslog_newline_frame(input: &[u8]) -> IResult<&[u8], &[u8], &'static str> {
         super::syslog_newline_frame(input).map_err(|err| ErrorKind::Custom(match err {
@@ -477,27 +789,617 @@ pub mod simple_errors {
 
     named!(pub syslog_rfc5424_in_newline_frame<&[u8], SyslogEvent, &'static str>, map!(
            flat_map!(call!(syslog_newline_frame), call!(syslog_rfc5424)),
-           |m: SyslogEvent| m.decode_newlines()));
+           |m: SyslogEvent| m.framed(Framing::Newline)));
+
+    pub fn syslog_rfc3164<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>, &'static str> {
+        super::syslog_rfc3164(input, config).map_err(|err| ErrorKind::Custom(match err {
+            ErrorKind::Custom(1) => "Bad syslog priority tag format",
+            ErrorKind::Custom(2) => "Unrecognized syslog timestamp format",
+            _ => "Syslog RFC3164 parser did not match"
+        }))
+    }
+
+    pub fn syslog_rfc3164_in_newline_frame<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>, &'static str> {
+        map!(input,
+             flat_map!(call!(syslog_newline_frame), call!(syslog_rfc3164, config)),
+             |m: SyslogEvent| m.framed(Framing::Newline))
+    }
+
+    /// Use RFC 5424 or RFC 3164 parser depending on message
+    pub fn syslog_auto<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>, &'static str> {
+        match Protocol::detect(input) {
+            Protocol::Rfc5424 => syslog_rfc5424(input),
+            Protocol::Rfc3164 => syslog_rfc3164(input, config),
+        }
+    }
+}
+
+fn done<'a, O>(result: IResult<&'a [u8], O>) -> Option<&'a [u8]> {
+    match result {
+        IResult::Done(input_left, _) => Some(input_left),
+        _ => None
+    }
+}
+
+// up to next space or end of input
+fn token(input: &[u8]) -> &[u8] {
+    &input[..input.iter().position(|&c| c == b' ').unwrap_or(input.len())]
+}
+
+fn caused_by<T>(error: ParseError, result: parse::Result<T>) -> ParseError {
+    match result {
+        Err(cause) => error.caused_by(cause.into_cause()),
+        Ok(_) => error
+    }
+}
+
+fn priority_error(input: &[u8], message: &'static str) -> ParseError {
+    let error = ParseError::new("priority", message, input, 0);
+    match (input.first(), input.iter().position(|&c| c == b'>')) {
+        (Some(&b'<'), Some(end)) => caused_by(error, parse::int_u8(&input[1..end])),
+        _ => error
+    }
+}
+
+// locate failing element and parameter within structured data that failed to parse
+fn structured_data_error(input: &[u8], structured_data: &[u8], message: &'static str) -> ParseError {
+    let at = |input_left: &[u8]| input.len() - input_left.len();
+
+    let mut element = structured_data;
+    loop {
+        if element.first() != Some(&b'[') {
+            return ParseError::new("structured_data", message, input, at(element))
+        }
+        match done(structured_data_element(element)) {
+            Some(input_left) => element = input_left,
+            None => break
+        }
+    }
+
+    let id = token(&element[1..]);
+    if id.len() + 1 == element.len() {
+        return caused_by(ParseError::new("structured_data_id", message, input, at(element) + 1), parse::string(id))
+    }
+
+    let mut param = &element[id.len() + 2..];
+    loop {
+        match done(structured_data_param(param)) {
+            Some(input_left) if input_left.first() == Some(&b' ') => param = &input_left[1..],
+            Some(input_left) => return ParseError::new("structured_data_element", message, input, at(input_left)),
+            None => return caused_by(ParseError::new("structured_data_param", message, input, at(param)), parse::string(token(param)))
+        }
+    }
+}
+
+/// Message for syslog_rfc5424 parser error code
+fn rfc5424_message(err: &ErrorKind) -> &'static str {
+    match *err {
+        ErrorKind::Custom(1) => "Bad syslog priority tag format",
+        ErrorKind::Custom(2) => "Unrecognized syslog timestamp format",
+        ErrorKind::Custom(3) => "Expected syslog hostname",
+        ErrorKind::Custom(4) => "Expected syslog application name",
+        ErrorKind::Custom(5) => "Expected syslog process ID",
+        ErrorKind::Custom(6) => "Expected syslog message ID",
+        ErrorKind::Custom(7) => "Expected valid syslog structured data",
+        ErrorKind::Custom(11) => "Failed to parse structured data element",
+        ErrorKind::Custom(12) => "Failed to parse structured data parameter",
+        ErrorKind::Custom(20) => "Bad syslog message payload encoding",
+        _ => "Syslog parser did not match"
+    }
+}
+
+// skip field that is known to have parsed fine
+fn skip<'a, O, F>(parser: F, input: &'a [u8]) -> &'a [u8] where F: Fn(&'a [u8]) -> IResult<&'a [u8], O> {
+    done(parser(input)).unwrap_or(input)
+}
+
+/// Locate RFC 5424 field that failed to parse as identified by syslog_rfc5424 error code; all
+/// fields before it have parsed fine
+fn rfc5424_error(input: &[u8], err: ErrorKind) -> ParseError {
+    let at = |input_left: &[u8]| input.len() - input_left.len();
+    let message = rfc5424_message(&err);
+
+    let field = match err {
+        ErrorKind::Custom(1) | ErrorKind::MapRes => return priority_error(input, message),
+        ErrorKind::Custom(2) => "timestamp",
+        ErrorKind::Custom(3) => "hostname",
+        ErrorKind::Custom(4) => "app_name",
+        ErrorKind::Custom(5) => "proc_id",
+        ErrorKind::Custom(6) => "msg_id",
+        ErrorKind::Custom(7) | ErrorKind::Custom(11) | ErrorKind::Custom(12) => "structured_data",
+        ErrorKind::Custom(20) => "message",
+        _ => "version"
+    };
+
+    let mut i = skip(priority, input);
+    if field == "version" {
+        return ParseError::new("version", message, input, at(i))
+    }
+    i = if i.starts_with(b"1 ") { &i[2..] } else { i };
+
+    if field == "timestamp" {
+        return caused_by(ParseError::new("timestamp", message, input, at(i)), parse::timestamp(token(i)))
+    }
+    i = skip(timestamp, i);
+
+    for &(name, parser) in [("hostname", hostname_field as fn(&[u8]) -> IResult<&[u8], Option<&str>>),
+                            ("app_name", program), ("proc_id", proc_id), ("msg_id", msg_id)].iter() {
+        if name == field {
+            return caused_by(ParseError::new(name, message, input, at(i)), parse::string(token(i)))
+        }
+        i = skip(parser, i);
+    }
+
+    if field == "structured_data" {
+        return structured_data_error(input, i, message)
+    }
+    i = skip(structured_data, i);
+
+    // message follows single space and optional BOM
+    let payload = if i.is_empty() { i } else { &i[1..] };
+    let text = if payload.starts_with(BOM) { &payload[BOM.len()..] } else { payload };
+    caused_by(ParseError::new("message", message, input, at(payload)), parse::string(text))
 }
 
+fn hostname_field(input: &[u8]) -> IResult<&[u8], Option<&str>> {
+    hostname(input).map(Some)
+}
+
+/// Find RFC 3164 field that failed to parse; only priority and timestamp are mandatory
+fn rfc3164_error(input: &[u8], config: &Rfc3164Config, message: &'static str) -> ParseError {
+    let at = |input_left: &[u8]| input.len() - input_left.len();
+
+    let i = match done(priority(input)) {
+        Some(input_left) => input_left,
+        None => return priority_error(input, message)
+    };
+
+    match done(bsd_timestamp(i, config)) {
+        Some(input_left) => ParseError::new("message", message, input, at(input_left)),
+        None => {
+            let bsd = &i[..if i.len() < 15 { i.len() } else { 15 }];
+            caused_by(ParseError::new("timestamp", message, input, at(i)), parse::bsd_timestamp(bsd, &config.timezone, (config.clock)()))
+        }
+    }
+}
+
+/// Like simple_errors but with location of the failing field, excerpt of the input and
+/// underlying cause of the failure
+pub mod parse_errors {
+    use super::{SyslogEvent, Rfc3164Config, Framing, Protocol, simple_errors};
+    use codec::parse;
+    use codec::error::ParseError;
+    use nom::{IResult, ErrorKind};
+
+    fn message(err: ErrorKind<&'static str>) -> &'static str {
+        match err {
+            ErrorKind::Custom(message) => message,
+            _ => "Syslog parser did not match"
+        }
+    }
+
+    pub fn syslog_rfc5424(input: &[u8]) -> IResult<&[u8], SyslogEvent, ParseError> {
+        super::syslog_rfc5424(input).map_err(|err| ErrorKind::Custom(super::rfc5424_error(input, err)))
+    }
+
+    pub fn syslog_rfc5425_frame_length(input: &[u8]) -> IResult<&[u8], usize, ParseError> {
+        simple_errors::syslog_rfc5425_frame_length(input).map_err(|err|
+            ErrorKind::Custom(super::caused_by(ParseError::new("frame_length", message(err), input, 0), parse::int_usize(super::token(input)))))
+    }
+
+    pub fn syslog_rfc5425_frame(input: &[u8]) -> IResult<&[u8], &[u8], ParseError> {
+        match syslog_rfc5425_frame_length(input) {
+            IResult::Done(_, _) => simple_errors::syslog_rfc5425_frame(input).map_err(|err| ErrorKind::Custom(ParseError::new("frame", message(err), input, 0))),
+            IResult::Error(err) => IResult::Error(err),
+            IResult::Incomplete(needed) => IResult::Incomplete(needed),
+        }
+    }
+
+    pub fn syslog_newline_frame(input: &[u8]) -> IResult<&[u8], &[u8], ParseError> {
+        simple_errors::syslog_newline_frame(input).map_err(|err| ErrorKind::Custom(ParseError::new("frame", message(err), input, input.len())))
+    }
+
+    // parse message within frame reporting errors with offsets relative to the frame
+    fn in_frame<'a, F>(input: &'a [u8], frame: IResult<&'a [u8], &'a [u8], ParseError>, parser: F, framing: Framing) -> IResult<&'a [u8], SyslogEvent<'a>, ParseError> where F: FnOnce(&'a [u8]) -> IResult<&'a [u8], SyslogEvent<'a>, ParseError> {
+        match frame {
+            IResult::Done(input_left, msg) => {
+                let start = msg.as_ptr() as usize - input.as_ptr() as usize;
+                match parser(msg) {
+                    IResult::Done(_, event) => IResult::Done(input_left, event.framed(framing)),
+                    IResult::Error(ErrorKind::Custom(err)) => IResult::Error(ErrorKind::Custom(err.in_frame(input, start))),
+                    IResult::Error(_) | IResult::Incomplete(_) => IResult::Error(ErrorKind::Custom(ParseError::new("message", "Syslog parser did not match", input, start))),
+                }
+            }
+            IResult::Error(err) => IResult::Error(err),
+            IResult::Incomplete(needed) => IResult::Incomplete(needed),
+        }
+    }
+
+    pub fn syslog_rfc5424_in_rfc5425_frame(input: &[u8]) -> IResult<&[u8], SyslogEvent, ParseError> {
+        in_frame(input, syslog_rfc5425_frame(input), syslog_rfc5424, Framing::OctetCounting)
+    }
+
+    pub fn syslog_rfc5424_in_newline_frame(input: &[u8]) -> IResult<&[u8], SyslogEvent, ParseError> {
+        in_frame(input, syslog_newline_frame(input), syslog_rfc5424, Framing::Newline)
+    }
+
+    pub fn syslog_rfc3164<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>, ParseError> {
+        simple_errors::syslog_rfc3164(input, config).map_err(|err| ErrorKind::Custom(super::rfc3164_error(input, config, message(err))))
+    }
+
+    pub fn syslog_rfc3164_in_newline_frame<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>, ParseError> {
+        in_frame(input, syslog_newline_frame(input), |msg| syslog_rfc3164(msg, config), Framing::Newline)
+    }
+
+    /// Use RFC 5424 or RFC 3164 parser depending on message
+    pub fn syslog_auto<'a>(input: &'a [u8], config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>, ParseError> {
+        match Protocol::detect(input) {
+            Protocol::Rfc5424 => syslog_rfc5424(input),
+            Protocol::Rfc3164 => syslog_rfc3164(input, config),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Rfc5424,
+    Rfc3164,
+}
+
+impl Protocol {
+    /// RFC 5424 messages have version 1 right after the priority tag
+    pub fn detect(input: &[u8]) -> Protocol {
+        match input.iter().take(5).position(|&c| c == b'>') {
+            Some(pos) if input[pos + 1..].starts_with(b"1 ") => Protocol::Rfc5424,
+            _ => Protocol::Rfc3164
+        }
+    }
+}
+
+/// Message carried in the frame at the start of input
+pub fn syslog_frame(input: &[u8], framing: Framing) -> IResult<&[u8], &[u8], ParseError> {
+    match framing {
+        Framing::Newline => parse_errors::syslog_newline_frame(input),
+        Framing::OctetCounting => parse_errors::syslog_rfc5425_frame(input),
+    }
+}
+
+fn syslog_message<'a>(input: &'a [u8], protocol: Option<Protocol>, config: &Rfc3164Config) -> IResult<&'a [u8], SyslogEvent<'a>, ParseError> {
+    match protocol {
+        Some(Protocol::Rfc5424) => parse_errors::syslog_rfc5424(input),
+        Some(Protocol::Rfc3164) => parse_errors::syslog_rfc3164(input, config),
+        None => parse_errors::syslog_auto(input, config),
+    }
+}
+
+/// Default limit of single message size in bytes (excluding framing)
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+// usize::MAX has 20 digits
+const MAX_FRAME_LENGTH_DIGITS: usize = 20;
+
 #[derive(Clone)]
-pub struct SyslogCodec(NomCodec<SyslogEvent>);
+pub struct SyslogCodec {
+    /// None to detect on first frame
+    framing: Option<Framing>,
+    /// None to detect for each message
+    protocol: Option<Protocol>,
+    rfc3164: Rfc3164Config,
+    max_message_size: usize,
+    id_strategy: IdStrategy,
+    /// Bytes of the buffered frame that are known not to contain new line
+    scanned: usize,
+}
 
 impl SyslogCodec {
     pub fn rfc5424_in_newline_frame() -> SyslogCodec {
-        SyslogCodec(NomCodec::new(simple_errors::syslog_rfc5424_in_newline_frame))
+        SyslogCodec {
+            framing: Some(Framing::Newline),
+            protocol: Some(Protocol::Rfc5424),
+            rfc3164: Rfc3164Config::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            id_strategy: IdStrategy::default(),
+            scanned: 0,
+        }
+    }
+
+    pub fn rfc5424_in_octet_counting_frame() -> SyslogCodec {
+        SyslogCodec {
+            framing: Some(Framing::OctetCounting),
+            protocol: Some(Protocol::Rfc5424),
+            rfc3164: Rfc3164Config::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            id_strategy: IdStrategy::default(),
+            scanned: 0,
+        }
+    }
+
+    pub fn rfc3164_in_newline_frame() -> SyslogCodec {
+        SyslogCodec::rfc3164_in_newline_frame_with(Rfc3164Config::default())
+    }
+
+    pub fn rfc3164_in_newline_frame_with(config: Rfc3164Config) -> SyslogCodec {
+        SyslogCodec {
+            framing: Some(Framing::Newline),
+            protocol: Some(Protocol::Rfc3164),
+            rfc3164: config,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            id_strategy: IdStrategy::default(),
+            scanned: 0,
+        }
+    }
+
+    /// Framing is detected once per connection (codec is cloned for each) and protocol for each
+    /// message
+    pub fn auto() -> SyslogCodec {
+        SyslogCodec::auto_with(Rfc3164Config::default())
+    }
+
+    pub fn auto_with(config: Rfc3164Config) -> SyslogCodec {
+        SyslogCodec {
+            framing: None,
+            protocol: None,
+            rfc3164: config,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            id_strategy: IdStrategy::default(),
+            scanned: 0,
+        }
     }
+
+    /// Frames bigger than this will fail to decode instead of being buffered
+    pub fn with_max_message_size(self, max_message_size: usize) -> SyslogCodec {
+        SyslogCodec { max_message_size: max_message_size, .. self }
+    }
+
+    /// How event IDs are assigned; IdStrategy::ContentHash by default
+    pub fn with_id_strategy(self, id_strategy: IdStrategy) -> SyslogCodec {
+        SyslogCodec { id_strategy: id_strategy, .. self }
+    }
+
+    fn check_frame_size(&mut self, framing: Framing, buf: &[u8]) -> IoResult<()> {
+        match framing {
+            Framing::OctetCounting => match simple_errors::syslog_rfc5425_frame_length(buf) {
+                IResult::Done(_, msg_len) if msg_len > self.max_message_size => Err(IoError::new(IoErrorKind::InvalidInput,
+                    format!("Syslog octet-counting frame announced length of {} bytes exceeds maximum message size of {} bytes", msg_len, self.max_message_size))),
+                IResult::Incomplete(_) if buf.len() > MAX_FRAME_LENGTH_DIGITS => Err(IoError::new(IoErrorKind::InvalidInput,
+                    "Syslog octet-counting frame length is too long")),
+                _ => Ok(())
+            },
+            Framing::Newline => {
+                let end = cmp::min(buf.len(), self.max_message_size.saturating_add(1));
+                // don't rescan what previous calls have seen
+                if buf[cmp::min(self.scanned, end)..end].contains(&b'\n') {
+                    return Ok(())
+                }
+                self.scanned = end;
+
+                if buf.len() > self.max_message_size {
+                    Err(IoError::new(IoErrorKind::InvalidInput,
+                        format!("Syslog new line frame exceeds maximum message size of {} bytes", self.max_message_size)))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+// RFC 5424 limits on header field lengths
+const MAX_HOSTNAME_LEN: usize = 255;
+const MAX_APP_NAME_LEN: usize = 48;
+const MAX_PROC_ID_LEN: usize = 128;
+const MAX_MSG_ID_LEN: usize = 32;
+const MAX_SD_NAME_LEN: usize = 32;
+
+// header fields are printable US-ASCII without spaces; NILVALUE if missing
+fn write_header_field(out: &mut Vec<u8>, value: Option<&str>, max_len: usize) {
+    match value {
+        Some(value) if !value.is_empty() => out.extend(value.bytes().take(max_len).map(|c| if c > b' ' && c < 0x7f { c } else { b'_' })),
+        _ => out.push(b'-')
+    }
+}
+
+// SD-NAME can't contain '=', ']', '"' nor space
+fn write_sd_name(out: &mut Vec<u8>, name: &str) {
+    out.extend(name.bytes().take(MAX_SD_NAME_LEN).map(|c| match c {
+        b'=' | b']' | b'"' => b'_',
+        c if c > b' ' && c < 0x7f => c,
+        _ => b'_'
+    }));
+}
+
+fn write_param_value(out: &mut Vec<u8>, value: &str) {
+    for c in value.bytes() {
+        if c == b'"' || c == b'\\' || c == b']' {
+            out.push(b'\\');
+        }
+        out.push(c);
+    }
+}
+
+fn write_timestamp(out: &mut Vec<u8>, timestamp: &DateTime<FixedOffset>) -> IoResult<()> {
+    write!(out, "{}", timestamp.format("%Y-%m-%dT%H:%M:%S"))?;
+    // up to microseconds
+    let fraction = format!("{:06}", timestamp.nanosecond() / 1000);
+    let fraction = fraction.trim_right_matches('0');
+    if !fraction.is_empty() {
+        write!(out, ".{}", fraction)?;
+    }
+    write!(out, "{}", timestamp.format("%:z"))
+}
+
+/// Write event as RFC 5424 message framed with new line (new lines in the message written as
+/// #012) or by octet-counting (RFC 6587)
+pub fn encode_rfc5424(event: &SyslogEvent, framing: Framing, out: &mut Vec<u8>) -> IoResult<()> {
+    let mut msg = Vec::with_capacity(128);
+    write_rfc5424(event, framing, &mut msg)?;
+    framing.frame(&msg, out);
+    Ok(())
+}
+
+/// Write event as RFC 5424 message without framing; for new line framing new lines in the
+/// message are written as #012
+///
+/// Like rsyslog we do not escape '#' so new line framed round trip is lossy: literal #012 in the
+/// message is decoded as new line.
+pub fn write_rfc5424(event: &SyslogEvent, framing: Framing, out: &mut Vec<u8>) -> IoResult<()> {
+    let mut msg = Vec::with_capacity(128);
+
+    write!(msg, "<{}>1 ", (event.facility as u8) * 8 + event.severity as u8)?;
+    write_timestamp(&mut msg, &event.timestamp)?;
+    msg.push(b' ');
+    write_header_field(&mut msg, Some(&*event.hostname), MAX_HOSTNAME_LEN);
+    msg.push(b' ');
+    write_header_field(&mut msg, event.program.as_ref().map(|s| s.as_ref()), MAX_APP_NAME_LEN);
+    msg.push(b' ');
+    write_header_field(&mut msg, event.proc_id.as_ref().map(|s| s.as_ref()), MAX_PROC_ID_LEN);
+    msg.push(b' ');
+    write_header_field(&mut msg, event.msg_id.as_ref().map(|s| s.as_ref()), MAX_MSG_ID_LEN);
+    msg.push(b' ');
+
+    match event.structured_data {
+        Some(ref structured_data) if !structured_data.elements.is_empty() => for element in structured_data.elements.iter() {
+            msg.push(b'[');
+            write_sd_name(&mut msg, &element.id);
+            for &(ref key, ref value) in element.params.iter() {
+                msg.push(b' ');
+                write_sd_name(&mut msg, key);
+                msg.extend_from_slice(b"=\"");
+                write_param_value(&mut msg, value);
+                msg.push(b'"');
+            }
+            msg.push(b']');
+        },
+        _ => msg.push(b'-')
+    }
+
+    match event.message {
+        Some(Message::String(ref s)) => {
+            msg.push(b' ');
+            msg.extend_from_slice(BOM);
+            msg.extend_from_slice(s.as_bytes());
+        }
+        Some(Message::MaybeString(ref ms)) => {
+            msg.push(b' ');
+            msg.extend_from_slice(ms.as_bytes());
+        }
+        None => ()
+    }
+
+    match framing {
+        Framing::Newline => for &c in msg.iter() {
+            if c == b'\n' {
+                out.extend_from_slice(b"#012");
+            } else {
+                out.push(c);
+            }
+        },
+        Framing::OctetCounting => out.extend_from_slice(&msg)
+    }
+    Ok(())
 }
 
 impl Codec for SyslogCodec {
-    type In = <NomCodec<SyslogEvent> as Codec>::In;
-    type Out = <NomCodec<SyslogEvent> as Codec>::Out;
+    type In = OwnedSyslogEvent;
+    type Out = OwnedSyslogEvent;
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> IoResult<Option<Self::In>> {
+        self.decode_with(buf, SyslogEvent::into_owned)
+    }
+
+    /// Encode as RFC 5424 message using framing of the codec (new line if not yet detected)
+    fn encode(&mut self, event: Self::Out, buf: &mut Vec<u8>) -> IoResult<()> {
+        encode_rfc5424(&event, self.framing.unwrap_or(Framing::Newline), buf)
+    }
+}
+
+impl SyslogCodec {
+    /// Decode next frame handing the event, still borrowing from the buffer, to given function
+    /// before the frame is drained
+    pub fn decode_with<T, F>(&mut self, buf: &mut EasyBuf, f: F) -> IoResult<Option<T>> where F: FnOnce(SyslogEvent) -> T {
+        let framing = match self.framing.or_else(|| Framing::detect(buf.as_slice())) {
+            Some(framing) => framing,
+            None => return Ok(None)
+        };
+        self.framing = Some(framing);
+
+        self.check_frame_size(framing, buf.as_slice())?;
+
+        let protocol = self.protocol;
+        let config = &self.rfc3164;
+        let id_strategy = self.id_strategy;
+
+        // cut the frame first so that message that fails to parse can be skipped
+        let (frame_len, message) = match syslog_frame(buf.as_slice(), framing) {
+            IResult::Done(input_left, message) => {
+                let frame_len = buf.len() - input_left.len();
+                let start = match framing {
+                    Framing::Newline => 0,
+                    Framing::OctetCounting => frame_len - message.len(),
+                };
+                (frame_len, match syslog_message(message, protocol, config) {
+                    // done with the event before the frame gets drained
+                    IResult::Done(_, event) => Ok(f(event.identified(id_strategy, message).framed(framing))),
+                    IResult::Error(ErrorKind::Custom(err)) => Err((err, start)),
+                    IResult::Error(_) | IResult::Incomplete(_) => Err((ParseError::new("message", "Failed to apply syslog message parser", message, 0), start)),
+                })
+            }
+            IResult::Error(ErrorKind::Custom(err)) => return Err(DecodeError {
+                frame: buf.as_slice().to_vec(),
+                error: err,
+                resumable: false,
+            }.into()),
+            IResult::Error(_) => return Err(IoError::new(IoErrorKind::InvalidData, "unexpected parser error")),
+            IResult::Incomplete(_) => return Ok(None),
+        };
+
+        let frame = buf.drain_to(frame_len);
+        self.scanned = 0;
+        match message {
+            Ok(event) => Ok(Some(event)),
+            Err((err, start)) => Err(DecodeError {
+                error: err.in_frame(frame.as_slice(), start),
+                frame: frame.as_slice().to_vec(),
+                resumable: true,
+            }.into())
+        }
+    }
 
+    /// Serialize events while they still borrow from the received data so that nothing but
+    /// the serialized bytes is copied (e.g. before they are sent to output::write::write_threaded)
+    pub fn serialized<S>(self, serializer: S) -> SerializingSyslogCodec<S> where S: for<'a> Serializer<SyslogEvent<'a>> {
+        SerializingSyslogCodec {
+            codec: self,
+            serializer: serializer,
+        }
+    }
+}
+
+/// Decodes syslog frames directly into serialized events (see SyslogCodec::serialized)
+#[derive(Clone)]
+pub struct SerializingSyslogCodec<S> {
+    codec: SyslogCodec,
+    serializer: S,
+}
+
+impl<S> Codec for SerializingSyslogCodec<S> where S: for<'a> Serializer<SyslogEvent<'a>> {
+    type In = Vec<u8>;
+    type Out = Vec<u8>;
+
+    /// Event that fails to serialize fails the stream
     fn decode(&mut self, buf: &mut EasyBuf) -> IoResult<Option<Self::In>> {
-        self.0.decode(buf)
+        let serializer = &self.serializer;
+        match self.codec.decode_with(buf, |event| serializer.serialize(&event, Vec::new()).map_err(|err| err.to_string())) {
+            Ok(Some(Ok(data))) => Ok(Some(data)),
+            Ok(Some(Err(err))) => Err(IoError::new(IoErrorKind::InvalidData, format!("Syslog event failed to serialize: {}", err))),
+            Ok(None) => Ok(None),
+            Err(err) => Err(err)
+        }
     }
-    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> IoResult<()> {
-        self.0.encode(msg, buf)
+
+    fn encode(&mut self, data: Self::Out, buf: &mut Vec<u8>) -> IoResult<()> {
+        buf.extend_from_slice(&data);
+        Ok(())
     }
 }
 
@@ -511,6 +1413,16 @@ mod syslog_rfc5425_frame_tests {
         assert_eq!(o, &b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\nbar"[..]);
         assert_eq!(i, &b"EOF"[..]);
     }
+
+    #[test]
+    fn length_over_255() {
+        let mut frame = b"1000 ".to_vec();
+        frame.extend(vec![b'x'; 1000]);
+        frame.extend(b"EOF");
+        let (i, o) = syslog_rfc5425_frame(&frame).unwrap();
+        assert_eq!(o.len(), 1000);
+        assert_eq!(i, &b"EOF"[..]);
+    }
 }
 
 #[cfg(test)]
@@ -543,7 +1455,8 @@ mod syslog_newline_frame_tests {
 #[cfg(test)]
 mod syslog_rfc5424_tests {
     pub use super::{Message, Facility, Severity};
-    pub use maybe_string::MaybeString;
+    pub use maybe_string::MaybeStr;
+    pub use std::borrow::Cow;
     pub use nom::ErrorKind;
     use super::simple_errors::syslog_rfc5424;
 
@@ -598,7 +1511,7 @@ mod syslog_rfc5424_tests {
     fn program() {
         let (i, o) = syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foobar\n").unwrap();
         assert!(i.is_empty());
-        assert_eq!(o.program, Some("evntslog".to_owned()));
+        assert_eq!(o.program, Some("evntslog".into()));
     }
 
     #[test]
@@ -624,11 +1537,11 @@ mod syslog_rfc5424_tests {
         let e = o.structured_data.as_ref().unwrap().elements.get(0).unwrap();
         assert_eq!(e.id, "exampleSDID@32473");
         assert_eq!(e.params.len(), 3);
-        assert_eq!(e.params[0], ("iut".to_owned(), "3".to_owned()));
-        assert_eq!(e.params[1], ("eventSource".to_owned(), "Application".to_owned()));
-        assert_eq!(e.params[2], ("eventID".to_owned(), "1011".to_owned()));
+        assert_eq!(e.params[0], ("iut".into(), "3".into()));
+        assert_eq!(e.params[1], ("eventSource".into(), "Application".into()));
+        assert_eq!(e.params[2], ("eventID".into(), "1011".into()));
 
-        assert_eq!(o.message, Some(Message::String("foo\nbar".to_owned())));
+        assert_eq!(o.message, Some(Message::String("foo\nbar".into())));
     }
 
     #[test]
@@ -640,16 +1553,16 @@ mod syslog_rfc5424_tests {
         let e = o.structured_data.as_ref().unwrap().elements.get(0).unwrap();
         assert_eq!(e.id, "exampleSDID@32473");
         assert_eq!(e.params.len(), 3);
-        assert_eq!(e.params[0], ("iut".to_owned(), "3".to_owned()));
-        assert_eq!(e.params[1], ("eventSource".to_owned(), "Application".to_owned()));
-        assert_eq!(e.params[2], ("eventID".to_owned(), "1011".to_owned()));
+        assert_eq!(e.params[0], ("iut".into(), "3".into()));
+        assert_eq!(e.params[1], ("eventSource".into(), "Application".into()));
+        assert_eq!(e.params[2], ("eventID".into(), "1011".into()));
 
         let e = o.structured_data.as_ref().unwrap().elements.get(1).unwrap();
         assert_eq!(e.id, "examplePriority@32473");
         assert_eq!(e.params.len(), 1);
-        assert_eq!(e.params[0], ("class".to_owned(), "high".to_owned()));
+        assert_eq!(e.params[0], ("class".into(), "high".into()));
 
-        assert_eq!(o.message, Some(Message::String("foo\nbar".to_owned())));
+        assert_eq!(o.message, Some(Message::String("foo\nbar".into())));
     }
 
     #[test]
@@ -662,16 +1575,16 @@ mod syslog_rfc5424_tests {
         let e = o.structured_data.as_ref().unwrap().elements.get(0).unwrap();
         assert_eq!(e.id, "exampleSDID@32473");
         assert_eq!(e.params.len(), 3);
-        assert_eq!(e.params[0], ("iut".to_owned(), "3".to_owned()));
-        assert_eq!(e.params[1], ("eventSource".to_owned(), "Appli\\catio]n".to_owned()));
-        assert_eq!(e.params[2], ("eventID".to_owned(), "1011".to_owned()));
+        assert_eq!(e.params[0], ("iut".into(), "3".into()));
+        assert_eq!(e.params[1], ("eventSource".into(), "Appli\\catio]n".into()));
+        assert_eq!(e.params[2], ("eventID".into(), "1011".into()));
 
         let e = o.structured_data.as_ref().unwrap().elements.get(1).unwrap();
         assert_eq!(e.id, "examplePriority@32473");
         assert_eq!(e.params.len(), 1);
-        assert_eq!(e.params[0], ("class".to_owned(), "hi\"gh".to_owned()));
+        assert_eq!(e.params[0], ("class".into(), "hi\"gh".into()));
 
-        assert_eq!(o.message, Some(Message::String("foo\nbar".to_owned())));
+        assert_eq!(o.message, Some(Message::String("foo\nbar".into())));
     }
 
     #[test]
@@ -684,14 +1597,14 @@ mod syslog_rfc5424_tests {
         let e = o.structured_data.as_ref().unwrap().elements.get(0).unwrap();
         assert_eq!(e.id, "exampleSDID@32473");
         assert_eq!(e.params.len(), 3);
-        assert_eq!(e.params[0], ("iut".to_owned(), "3".to_owned()));
-        assert_eq!(e.params[1], ("eventSource".to_owned(), "Application".to_owned()));
-        assert_eq!(e.params[2], ("eventID".to_owned(), "1011".to_owned()));
+        assert_eq!(e.params[0], ("iut".into(), "3".into()));
+        assert_eq!(e.params[1], ("eventSource".into(), "Application".into()));
+        assert_eq!(e.params[2], ("eventID".into(), "1011".into()));
 
         let e = o.structured_data.as_ref().unwrap().elements.get(1).unwrap();
         assert_eq!(e.id, "examplePriority@32473");
         assert_eq!(e.params.len(), 1);
-        assert_eq!(e.params[0], ("class".to_owned(), "high".to_owned()));
+        assert_eq!(e.params[0], ("class".into(), "high".into()));
 
         assert_eq!(o.message, None);
     }
@@ -700,14 +1613,14 @@ mod syslog_rfc5424_tests {
     fn message_maybe_string() {
         let (i, o) = syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\nbar").unwrap();
         assert!(i.is_empty());
-        assert_eq!(o.message, Some(Message::MaybeString(MaybeString::from_bytes(b"foo\nbar".as_ref().to_owned()))));
+        assert_eq!(o.message, Some(Message::MaybeString(Cow::Borrowed(MaybeStr::from_bytes(b"foo\nbar")))));
     }
 
     #[test]
     fn message_bom() {
         let (i, o) = syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo\nbar").unwrap();
         assert!(i.is_empty());
-        assert_eq!(o.message, Some(Message::String("foo\nbar".to_owned())));
+        assert_eq!(o.message, Some(Message::String("foo\nbar".into())));
     }
 
     #[test]
@@ -724,11 +1637,11 @@ mod syslog_rfc5424_tests {
         #[test]
         fn framing() {
             let (i, o) = syslog_rfc5424_in_newline_frame(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo\n<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n").unwrap();
-            assert_eq!(o.message, Some(Message::String("foo".to_owned())));
+            assert_eq!(o.message, Some(Message::String("foo".into())));
 
             let (i, o) = syslog_rfc5424_in_newline_frame(i).unwrap();
             assert!(i.is_empty());
-            assert_eq!(o.message, Some(Message::MaybeString(MaybeString::from_bytes(b"foo".as_ref().to_owned()))));
+            assert_eq!(o.message, Some(Message::MaybeString(Cow::Borrowed(MaybeStr::from_bytes(b"foo")))));
         }
 
         #[test]
@@ -741,11 +1654,455 @@ mod syslog_rfc5424_tests {
         #[test]
         fn octal_newline_excapes_when_message_is_string() {
             let (i, o) = syslog_rfc5424_in_newline_frame(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo#012bar\n<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo#012bar\n").unwrap();
-            assert_eq!(o.message, Some(Message::String("foo\nbar".to_owned())));
+            assert_eq!(o.message, Some(Message::String("foo\nbar".into())));
 
             let (i, o) = syslog_rfc5424_in_newline_frame(i).unwrap();
             assert!(i.is_empty());
-            assert_eq!(o.message, Some(Message::MaybeString(MaybeString::from_bytes(b"foo#012bar".as_ref().to_owned()))));
+            assert_eq!(o.message, Some(Message::MaybeString(Cow::Borrowed(MaybeStr::from_bytes(b"foo#012bar")))));
         }
     }
 }
+
+#[cfg(test)]
+mod syslog_rfc3164_tests {
+    pub use super::{Message, Facility, Severity, Rfc3164Config};
+    pub use nom::ErrorKind;
+    use super::simple_errors::syslog_rfc3164;
+    use chrono::{Timelike, Datelike, FixedOffset, DateTime, UTC, TimeZone};
+
+    #[test]
+    fn priority() {
+        let (i, o) = syslog_rfc3164(b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8", &Rfc3164Config::default()).unwrap();
+        assert!(i.is_empty());
+        assert_eq!(o.facility, Facility::SecurityMessages);
+        assert_eq!(o.severity, Severity::Critical);
+    }
+
+    #[test]
+    fn timestamp() {
+        let (_i, o) = syslog_rfc3164(b"<34>Oct  1 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.timestamp.month(), 10);
+        assert_eq!(o.timestamp.day(), 1);
+        assert_eq!(o.timestamp.hour(), 22);
+        assert_eq!(o.timestamp.minute(), 14);
+        assert_eq!(o.timestamp.second(), 15);
+    }
+
+    #[test]
+    fn timestamp_timezone() {
+        let config = Rfc3164Config { timezone: FixedOffset::east(2 * 3600), .. Rfc3164Config::default() };
+        let (_i, o) = syslog_rfc3164(b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8", &config).unwrap();
+        assert_eq!(o.timestamp.offset(), &FixedOffset::east(2 * 3600));
+        assert_eq!(o.timestamp.hour(), 22);
+    }
+
+    #[test]
+    fn timestamp_rfc3339() {
+        let (_i, o) = syslog_rfc3164(b"<34>2003-10-11T22:14:15.003Z mymachine su: 'su root' failed for lonvick on /dev/pts/8", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.timestamp, DateTime::parse_from_rfc3339("2003-10-11T22:14:15.003Z").unwrap());
+    }
+
+    #[test]
+    fn timestamp_error() {
+        let err = syslog_rfc3164(b"<34>Foo 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8", &Rfc3164Config::default()).unwrap_err();
+        assert_matches!(err, ErrorKind::Custom("Unrecognized syslog timestamp format"));
+    }
+
+    #[test]
+    fn year_inference() {
+        use codec::parse::infer_year;
+        assert_eq!(infer_year(10, 2016, 10), 2016);
+        assert_eq!(infer_year(11, 2016, 10), 2016);
+        assert_eq!(infer_year(12, 2017, 1), 2016);
+        assert_eq!(infer_year(1, 2016, 12), 2017);
+    }
+
+    fn december() -> DateTime<UTC> {
+        UTC.ymd(2016, 12, 31).and_hms(23, 50, 0)
+    }
+
+    fn january() -> DateTime<UTC> {
+        UTC.ymd(2017, 1, 1).and_hms(0, 10, 0)
+    }
+
+    #[test]
+    fn timestamp_year_in_december() {
+        let config = Rfc3164Config { clock: december, .. Rfc3164Config::default() };
+
+        let (_i, o) = syslog_rfc3164(b"<34>Dec 31 23:49:00 mymachine su: foo", &config).unwrap();
+        assert_eq!(o.timestamp.year(), 2016);
+
+        // sender clock slightly ahead of ours
+        let (_i, o) = syslog_rfc3164(b"<34>Jan  1 00:01:00 mymachine su: foo", &config).unwrap();
+        assert_eq!(o.timestamp.year(), 2017);
+    }
+
+    #[test]
+    fn timestamp_year_in_january() {
+        let config = Rfc3164Config { clock: january, .. Rfc3164Config::default() };
+
+        let (_i, o) = syslog_rfc3164(b"<34>Jan  1 00:09:00 mymachine su: foo", &config).unwrap();
+        assert_eq!(o.timestamp.year(), 2017);
+
+        // delayed message from last year
+        let (_i, o) = syslog_rfc3164(b"<34>Dec 31 23:59:00 mymachine su: foo", &config).unwrap();
+        assert_eq!(o.timestamp.year(), 2016);
+    }
+
+    #[test]
+    fn hostname() {
+        let (_i, o) = syslog_rfc3164(b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.hostname, "mymachine");
+    }
+
+    #[test]
+    fn tag() {
+        let (_i, o) = syslog_rfc3164(b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.program, Some("su".into()));
+        assert_eq!(o.proc_id, None);
+        assert_eq!(o.message, Some(Message::String("'su root' failed for lonvick on /dev/pts/8".into())));
+    }
+
+    #[test]
+    fn tag_proc_id() {
+        let (_i, o) = syslog_rfc3164(b"<13>Feb  5 17:32:18 10.0.0.99 sshd[4321]: Accepted publickey for root", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.hostname, "10.0.0.99");
+        assert_eq!(o.program, Some("sshd".into()));
+        assert_eq!(o.proc_id, Some("4321".into()));
+        assert_eq!(o.message, Some(Message::String("Accepted publickey for root".into())));
+    }
+
+    #[test]
+    fn no_hostname() {
+        let (_i, o) = syslog_rfc3164(b"<13>Feb  5 17:32:18 sshd[4321]: Accepted publickey for root", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.hostname, "");
+        assert_eq!(o.program, Some("sshd".into()));
+        assert_eq!(o.proc_id, Some("4321".into()));
+        assert_eq!(o.message, Some(Message::String("Accepted publickey for root".into())));
+    }
+
+    #[test]
+    fn no_tag() {
+        let (_i, o) = syslog_rfc3164(b"<13>Feb  5 17:32:18 10.0.0.99 Use the BFG!", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.hostname, "10.0.0.99");
+        assert_eq!(o.program, None);
+        assert_eq!(o.proc_id, None);
+        assert_eq!(o.message, Some(Message::String("Use the BFG!".into())));
+    }
+
+    #[test]
+    fn no_hostname_no_tag() {
+        let (_i, o) = syslog_rfc3164(b"<13>Feb  5 17:32:18 Use the BFG!", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.hostname, "");
+        assert_eq!(o.program, None);
+        assert_eq!(o.message, Some(Message::String("Use the BFG!".into())));
+
+        let (_i, o) = syslog_rfc3164(b"<13>Feb  5 17:32:18 host.example.com Use the BFG!", &Rfc3164Config::default()).unwrap();
+        assert_eq!(o.hostname, "host.example.com");
+        assert_eq!(o.message, Some(Message::String("Use the BFG!".into())));
+    }
+
+    #[cfg(test)]
+    mod in_syslog_newline_frame_tests {
+        use super::*;
+        use super::super::simple_errors::syslog_rfc3164_in_newline_frame;
+
+        #[test]
+        fn framing() {
+            let (i, o) = syslog_rfc3164_in_newline_frame(b"<34>Oct 11 22:14:15 mymachine su: foo#012bar\n<13>Feb  5 17:32:18 10.0.0.99 sshd[4321]: baz\n", &Rfc3164Config::default()).unwrap();
+            assert_eq!(o.message, Some(Message::String("foo\nbar".into())));
+
+            let (i, o) = syslog_rfc3164_in_newline_frame(i, &Rfc3164Config::default()).unwrap();
+            assert!(i.is_empty());
+            assert_eq!(o.message, Some(Message::String("baz".into())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod parse_errors_tests {
+    use super::parse_errors::*;
+    use std::error::Error;
+    use nom::{IResult, ErrorKind};
+    use codec::error::ParseError;
+
+    fn error<O>(result: IResult<&[u8], O, ParseError>) -> ParseError {
+        match result {
+            IResult::Error(ErrorKind::Custom(err)) => err,
+            _ => panic!("expected parse error")
+        }
+    }
+
+    #[test]
+    fn timestamp() {
+        let err = error(syslog_rfc5424(b"<165>1 2003-13-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo"));
+        assert_eq!(err.field, "timestamp");
+        assert_eq!(err.offset, 7);
+        assert_eq!(err.message, "Unrecognized syslog timestamp format");
+        assert!(err.cause().is_some());
+        assert!(err.excerpt.ends_with("\n       ^"));
+    }
+
+    #[test]
+    fn structured_data_param() {
+        let err = error(syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z host app - ID47 [id@1 a=\"1\" b=2] foo"));
+        assert_eq!(err.field, "structured_data_param");
+        assert_eq!(err.offset, 60);
+    }
+
+    #[test]
+    fn field_from_error_code() {
+        let err = error(syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z - app - ID47 - foo"));
+        assert_eq!(err.field, "hostname");
+        assert_eq!(err.offset, 32);
+
+        let err = error(syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z host app - \xff - foo"));
+        assert_eq!(err.field, "msg_id");
+        assert_eq!(err.offset, 43);
+        assert_eq!(err.message, "Expected syslog message ID");
+        assert!(err.cause().is_some());
+
+        let err = error(syslog_rfc5424(b"<165>2 2003-10-11T22:14:15.003Z host app - ID47 - foo"));
+        assert_eq!(err.field, "version");
+        assert_eq!(err.offset, 5);
+    }
+
+    #[test]
+    fn offset_within_frame() {
+        let err = error(syslog_rfc5424_in_rfc5425_frame(b"10 <165>1 bad"));
+        assert_eq!(err.field, "timestamp");
+        assert_eq!(err.offset, 10);
+    }
+
+    #[test]
+    fn frame_length() {
+        let err = error(syslog_rfc5425_frame_length(b"1x0 <165>1"));
+        assert_eq!(err.field, "frame_length");
+        assert!(err.cause().is_some());
+    }
+}
+
+#[cfg(test)]
+mod syslog_codec_tests {
+    use super::{SyslogCodec, Protocol, Framing, Message, IdStrategy, parse_errors};
+    use event::{Event, LogstashEvent};
+    use codec::DecodeError;
+    use serialize::{Serializer, JsonLogstashEventSerializer};
+    use tokio_core::io::{Codec, EasyBuf};
+
+    #[test]
+    fn detect_protocol() {
+        assert_eq!(Protocol::detect(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo"), Protocol::Rfc5424);
+        assert_eq!(Protocol::detect(b"<34>Oct 11 22:14:15 mymachine su: foo"), Protocol::Rfc3164);
+        assert_eq!(Protocol::detect(b"<1>1 "), Protocol::Rfc5424);
+        assert_eq!(Protocol::detect(b"<1>"), Protocol::Rfc3164);
+    }
+
+    #[test]
+    fn auto_newline_frame() {
+        let mut codec = SyslogCodec::auto();
+        let mut buf = EasyBuf::from(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo\n<34>Oct 11 22:14:15 mymachine su: bar\n<34>Oct".to_vec());
+
+        let o = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(o.protocol, Protocol::Rfc5424);
+        assert_eq!(o.framing, Some(Framing::Newline));
+        assert_eq!(o.message, Some(Message::String("foo".into())));
+
+        let o = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(o.protocol, Protocol::Rfc3164);
+        assert_eq!(o.framing, Some(Framing::Newline));
+        assert_eq!(o.message, Some(Message::String("bar".into())));
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn auto_octet_counting_frame() {
+        let mut codec = SyslogCodec::auto();
+        let mut buf = EasyBuf::from(b"82 <165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo\nbar37 <34>Oct 11 22:14:15 mymachine su: baz".to_vec());
+
+        let o = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(o.protocol, Protocol::Rfc5424);
+        assert_eq!(o.framing, Some(Framing::OctetCounting));
+        assert_eq!(o.message, Some(Message::String("foo\nbar".into())));
+
+        let o = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(o.protocol, Protocol::Rfc3164);
+        assert_eq!(o.framing, Some(Framing::OctetCounting));
+        assert_eq!(o.message, Some(Message::String("baz".into())));
+        assert!(buf.as_slice().is_empty());
+    }
+
+    #[test]
+    fn octet_counting_frame_too_big() {
+        let mut codec = SyslogCodec::rfc5424_in_octet_counting_frame().with_max_message_size(100);
+        let mut buf = EasyBuf::from(b"101 <165>1 2003-10-11T22:14:15.003Z".to_vec());
+
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(err.to_string(), "Syslog octet-counting frame announced length of 101 bytes exceeds maximum message size of 100 bytes");
+    }
+
+    #[test]
+    fn newline_frame_too_big() {
+        let mut codec = SyslogCodec::auto().with_max_message_size(10);
+        let mut buf = EasyBuf::from(b"<34>Oct 11 22:14:15 mymachine su: baz".to_vec());
+
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn newline_frame_size_scanned_once() {
+        let mut codec = SyslogCodec::auto().with_max_message_size(40);
+        let mut buf = EasyBuf::from(b"<34>Oct 11 22:14:15 mymachine su: ".to_vec());
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(codec.scanned, 34);
+
+        buf.get_mut().extend_from_slice(b"baz\n<34>");
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().message, Some(Message::String("baz".into())));
+        assert_eq!(codec.scanned, 0);
+
+        buf.get_mut().extend_from_slice(b"Oct 11 22:14:15 mymachine su: too long");
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn unlimited_message_size() {
+        let mut codec = SyslogCodec::auto().with_max_message_size(::std::usize::MAX);
+        let mut buf = EasyBuf::from(b"<34>Oct 11 22:14:15 mymachine su: baz\n".to_vec());
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().message, Some(Message::String("baz".into())));
+    }
+
+    #[test]
+    fn skip_message_that_failed_to_parse() {
+        let mut codec = SyslogCodec::rfc5424_in_newline_frame();
+        let mut buf = EasyBuf::from(b"<165>1 bad\n<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n".to_vec());
+
+        let err = codec.decode(&mut buf).unwrap_err();
+        let failure = DecodeError::from_io_error(&err).unwrap();
+        assert_eq!(failure.frame, b"<165>1 bad\n".to_vec());
+        assert!(failure.resumable);
+
+        let o = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(o.message, Some(Message::String("foo".into())));
+    }
+
+    #[test]
+    fn serialized() {
+        let frames = b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n<165>1 bad\n".to_vec();
+        let serializer = JsonLogstashEventSerializer::default();
+
+        let mut buf = EasyBuf::from(frames.clone());
+        let event = SyslogCodec::rfc5424_in_newline_frame().decode(&mut buf).unwrap().unwrap();
+
+        let mut codec = SyslogCodec::rfc5424_in_newline_frame().serialized(JsonLogstashEventSerializer::default());
+        let mut buf = EasyBuf::from(frames);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), serializer.serialize(&event, Vec::new()).unwrap());
+        assert!(DecodeError::from_io_error(&codec.decode(&mut buf).unwrap_err()).unwrap().resumable);
+    }
+
+    #[test]
+    fn typed_structured_data_values() {
+        let (_, event) = parse_errors::syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [x@1 a=\"3\" b=\"-1\" c=\"0.5\" d=\"true\" e=\"03\" f=\"1e3\" g=\"18446744073709551615\" h=\"foo\"] bar").unwrap();
+        let json = String::from_utf8(JsonLogstashEventSerializer::default().serialize(&event, Vec::new()).unwrap()).unwrap();
+        assert!(json.contains(r#""structured_data":{"x@1":{"a":3,"b":-1,"c":0.5,"d":true,"e":"03","f":"1e3","g":18446744073709551615,"h":"foo"}}"#));
+    }
+
+    #[test]
+    fn id_strategy() {
+        let frames = b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n<34>Oct 11 22:14:15 mymachine su: bar\n".to_vec();
+        let decode_all = |mut codec: SyslogCodec| {
+            let mut buf = EasyBuf::from(frames.clone());
+            let first = codec.decode(&mut buf).unwrap().unwrap();
+            let second = codec.decode(&mut buf).unwrap().unwrap();
+
+            // ID is assigned once and stored on the event
+            assert_eq!(Event::id(&first), LogstashEvent::id(&first));
+            let serializer = JsonLogstashEventSerializer::default();
+            let json = serializer.serialize(&first, Vec::new()).unwrap();
+            assert_eq!(json, serializer.serialize(&first, Vec::new()).unwrap());
+            assert!(String::from_utf8(json).unwrap().contains(&format!("\"@id\":\"{}\"", first.id)));
+            (first.id, second.id)
+        };
+
+        // re-delivered message gets the same ID
+        let (first, hash) = decode_all(SyslogCodec::auto());
+        assert!(first != "ID47");
+        assert_eq!(hash.len(), 32);
+        assert_eq!(decode_all(SyslogCodec::auto().with_id_strategy(IdStrategy::ContentHash)), (first.clone(), hash.clone()));
+
+        let (msg_id, second) = decode_all(SyslogCodec::auto().with_id_strategy(IdStrategy::MsgId));
+        assert_eq!(msg_id, "ID47");
+        assert_eq!(second, hash);
+
+        let (first, second) = decode_all(SyslogCodec::auto().with_id_strategy(IdStrategy::Random));
+        assert!(first != "ID47");
+        assert!(second != hash);
+    }
+
+    #[test]
+    fn id_of_unidentified_event() {
+        let (_, event) = parse_errors::syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo").unwrap();
+        assert!(event.id.is_empty());
+
+        let id = Event::id(&event).into_owned();
+        assert_eq!(id.len(), 32);
+        assert_eq!(LogstashEvent::id(&event), id);
+        assert_eq!(Event::id(&event.into_owned()), id);
+
+        let (_, other) = parse_errors::syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - bar").unwrap();
+        assert!(Event::id(&other) != id);
+    }
+}
+
+#[cfg(test)]
+mod encode_tests {
+    use super::{SyslogCodec, Framing, Message, encode_rfc5424};
+    use super::parse_errors::{syslog_rfc5424, syslog_rfc3164};
+    use super::Rfc3164Config;
+    use tokio_core::io::{Codec, EasyBuf};
+
+    const MESSAGE: &'static [u8] = b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut=\"3\" eventSource=\"Appli\\\\catio\\]n\"][examplePriority@32473 class=\"hi\\\"gh\"] \xEF\xBB\xBFfoo\nbar";
+
+    #[test]
+    fn newline_frame() {
+        let (_, event) = syslog_rfc5424(MESSAGE).unwrap();
+        let mut buf = Vec::new();
+        encode_rfc5424(&event, Framing::Newline, &mut buf).unwrap();
+        assert_eq!(buf, b"<165>1 2003-10-11T22:14:15.003+00:00 mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut=\"3\" eventSource=\"Appli\\\\catio\\]n\"][examplePriority@32473 class=\"hi\\\"gh\"] \xEF\xBB\xBFfoo#012bar\n".to_vec());
+    }
+
+    #[test]
+    fn newline_round_trip_lossy() {
+        let (_, event) = syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo\nbar#012baz").unwrap();
+        let mut codec = SyslogCodec::rfc5424_in_newline_frame();
+        let mut buf = Vec::new();
+        codec.encode(event.into_owned(), &mut buf).unwrap();
+        assert!(buf.ends_with(b"foo#012bar#012baz\n"));
+
+        let decoded = codec.decode(&mut EasyBuf::from(buf)).unwrap().unwrap();
+        assert_eq!(decoded.message, Some(Message::String("foo\nbar\nbaz".into())));
+    }
+
+    #[test]
+    fn nil_values() {
+        let (_, event) = syslog_rfc3164(b"<34>Oct 11 22:14:15 su: foo", &Rfc3164Config::default()).unwrap();
+        let mut buf = Vec::new();
+        encode_rfc5424(&event, Framing::Newline, &mut buf).unwrap();
+        assert!(buf.ends_with(b"+00:00 - su - - - \xEF\xBB\xBFfoo\n"));
+    }
+
+    #[test]
+    fn octet_counting_round_trip() {
+        let (_, event) = syslog_rfc5424(MESSAGE).unwrap();
+        let mut codec = SyslogCodec::rfc5424_in_octet_counting_frame();
+        let mut buf = Vec::new();
+        codec.encode(event.clone().into_owned(), &mut buf).unwrap();
+
+        let decoded = codec.decode(&mut EasyBuf::from(buf)).unwrap().unwrap();
+        assert_eq!(decoded.message, Some(Message::String("foo\nbar".into())));
+        assert_eq!(decoded.timestamp, event.timestamp);
+        let params: Vec<_> = decoded.structured_data.unwrap().elements.into_iter().flat_map(|e| e.params).collect();
+        assert_eq!(params[1], ("eventSource".into(), "Appli\\catio]n".into()));
+        assert_eq!(params[2], ("class".into(), "hi\"gh".into()));
+    }
+}
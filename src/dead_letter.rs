@@ -0,0 +1,413 @@
+use std::path::{Path, PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write, BufRead, BufReader, BufWriter};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Result as IoResult;
+use std::collections::VecDeque;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use slog::Logger;
+
+use futures::stream::Stream;
+
+use chrono::{DateTime, UTC};
+use serde_json;
+
+use codec::DecodeError;
+use codec::error::ParseError;
+
+/// Message that failed to decode with everything needed to debug and reprocess it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub timestamp: DateTime<UTC>,
+    pub input: String,
+    pub remote_addr: Option<String>,
+    pub error: String,
+    /// Offset in data at which parser failed
+    pub offset: Option<usize>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    timestamp: String,
+    input: String,
+    remote_addr: Option<String>,
+    error: String,
+    offset: Option<usize>,
+    length: usize,
+}
+
+impl DeadLetter {
+    // JSON header line followed by raw data and new line
+    fn write<W: Write>(&self, out: &mut W) -> IoResult<()> {
+        let header = Header {
+            timestamp: self.timestamp.to_rfc3339(),
+            input: self.input.clone(),
+            remote_addr: self.remote_addr.clone(),
+            error: self.error.clone(),
+            offset: self.offset,
+            length: self.data.len(),
+        };
+
+        serde_json::to_writer(&mut *out, &header).map_err(|err| IoError::new(IoErrorKind::Other, err))?;
+        out.write_all(b"\n")?;
+        out.write_all(&self.data)?;
+        out.write_all(b"\n")
+    }
+
+    /// Returns None on end of file
+    fn read<R: BufRead>(input: &mut R) -> IoResult<Option<DeadLetter>> {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None)
+        }
+        if !line.ends_with('\n') {
+            return Err(IoError::new(IoErrorKind::UnexpectedEof, "partial dead letter header"))
+        }
+
+        let header: Header = serde_json::from_str(&line).map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+        let timestamp = DateTime::parse_from_rfc3339(&header.timestamp).map_err(|err| IoError::new(IoErrorKind::InvalidData, err))?;
+
+        let mut data = vec![0; header.length + 1];
+        input.read_exact(&mut data)?;
+        data.pop();
+
+        Ok(Some(DeadLetter {
+            timestamp: timestamp.with_timezone(&UTC),
+            input: header.input,
+            remote_addr: header.remote_addr,
+            error: header.error,
+            offset: header.offset,
+            data: data,
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpoolConfig {
+    /// Start new segment file when current one gets bigger than that
+    pub segment_size: u64,
+    /// Remove oldest segments when spool gets bigger than that
+    pub max_size: u64,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> SpoolConfig {
+        SpoolConfig {
+            segment_size: 16 * 1024 * 1024,
+            max_size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+const SEGMENT_EXTENSION: &'static str = "dead";
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION))
+}
+
+// sequence number and size of each segment file in the directory; oldest first
+fn spooled_segments(dir: &Path) -> IoResult<Vec<(u64, u64)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue
+        }
+        if let Some(seq) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+            segments.push((seq, fs::metadata(&path)?.len()));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Paths of segment files in existing spool directory without opening the spool for appending;
+/// oldest first
+pub fn list_segments(dir: &Path) -> IoResult<Vec<PathBuf>> {
+    Ok(spooled_segments(dir)?.into_iter().map(|(seq, _)| segment_path(dir, seq)).collect())
+}
+
+/// Append-only on-disk store of dead letters split into numbered segment files
+pub struct Spool {
+    dir: PathBuf,
+    config: SpoolConfig,
+    /// Sequence number and size of each segment; oldest first
+    segments: VecDeque<(u64, u64)>,
+    current: Option<File>,
+}
+
+impl Spool {
+    pub fn open(dir: &Path, config: SpoolConfig) -> IoResult<Spool> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Spool {
+            dir: dir.to_owned(),
+            config: config,
+            segments: spooled_segments(dir)?.into_iter().collect(),
+            current: None,
+        })
+    }
+
+    /// Paths of segment files; oldest first
+    pub fn segments(&self) -> Vec<PathBuf> {
+        self.segments.iter().map(|&(seq, _)| segment_path(&self.dir, seq)).collect()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.segments.iter().map(|&(_, size)| size).sum()
+    }
+
+    pub fn append(&mut self, letter: &DeadLetter) -> IoResult<()> {
+        let mut record = Vec::new();
+        letter.write(&mut record)?;
+
+        let rotate = match self.segments.back() {
+            Some(&(_, size)) => size > 0 && size + record.len() as u64 > self.config.segment_size,
+            None => true
+        };
+        if rotate || self.current.is_none() {
+            self.open_segment(rotate)?;
+        }
+
+        self.current.as_mut().expect("open segment").write_all(&record)?;
+        self.segments.back_mut().expect("current segment").1 += record.len() as u64;
+
+        self.enforce_max_size()
+    }
+
+    fn open_segment(&mut self, new: bool) -> IoResult<()> {
+        if new {
+            let seq = self.segments.back().map(|&(seq, _)| seq + 1).unwrap_or(0);
+            self.segments.push_back((seq, 0));
+        }
+
+        let seq = self.segments.back().expect("segment").0;
+        self.current = Some(OpenOptions::new().append(true).create(true).open(segment_path(&self.dir, seq))?);
+        Ok(())
+    }
+
+    // current segment is never removed
+    fn enforce_max_size(&mut self) -> IoResult<()> {
+        while self.segments.len() > 1 && self.size() > self.config.max_size {
+            let (seq, _) = self.segments.pop_front().expect("oldest segment");
+            fs::remove_file(segment_path(&self.dir, seq))?;
+        }
+        Ok(())
+    }
+}
+
+/// Read all dead letters from segment file; partially written last record (including its header
+/// line) is ignored
+pub fn read_segment(path: &Path) -> IoResult<Vec<DeadLetter>> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut letters = Vec::new();
+
+    loop {
+        match DeadLetter::read(&mut input) {
+            Ok(Some(letter)) => letters.push(letter),
+            Ok(None) => return Ok(letters),
+            Err(ref err) if err.kind() == IoErrorKind::UnexpectedEof => return Ok(letters),
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+/// Replace content of segment file with given dead letters removing the file if there are none;
+/// segment must not be currently appended to
+pub fn write_segment(path: &Path, letters: &[DeadLetter]) -> IoResult<()> {
+    if letters.is_empty() {
+        return fs::remove_file(path)
+    }
+
+    // write new file and rename it over the old one so we never leave half written segment
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut tmp = BufWriter::new(OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?);
+        for letter in letters {
+            letter.write(&mut tmp)?;
+        }
+        tmp.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+/// Shared handle to spool used by inputs; spooling can be disabled
+#[derive(Clone)]
+pub struct DeadLetters {
+    spool: Option<Rc<RefCell<Spool>>>,
+}
+
+impl DeadLetters {
+    pub fn new(spool: Spool) -> DeadLetters {
+        DeadLetters {
+            spool: Some(Rc::new(RefCell::new(spool)))
+        }
+    }
+
+    pub fn disabled() -> DeadLetters {
+        DeadLetters {
+            spool: None
+        }
+    }
+
+    /// Log and spool message that failed to decode
+    pub fn store(&self, logger: &Logger, input: &str, remote_addr: Option<&str>, data: &[u8], error: &ParseError) {
+        let spool = match self.spool {
+            Some(ref spool) => spool,
+            None => {
+                warn!(logger, "Message failed to decode"; "error" => format!("{}", error), "bytes" => data.len());
+                return
+            }
+        };
+
+        let letter = DeadLetter {
+            timestamp: UTC::now(),
+            input: input.to_owned(),
+            remote_addr: remote_addr.map(ToOwned::to_owned),
+            error: format!("{}", error),
+            offset: Some(error.offset),
+            data: data.to_vec(),
+        };
+
+        match spool.borrow_mut().append(&letter) {
+            Ok(()) => warn!(logger, "Message failed to decode; stored in dead letter spool"; "error" => letter.error, "bytes" => data.len()),
+            Err(err) => error!(logger, "Message failed to decode and could not be stored in dead letter spool"; "error" => letter.error, "bytes" => data.len(), "spool_error" => format!("{}", err))
+        }
+    }
+
+    /// Log and spool message that decoded fine but its event was not accepted downstream
+    pub fn store_rejected(&self, logger: &Logger, input: &str, remote_addr: Option<&str>, data: &[u8], reason: &str) {
+        let spool = match self.spool {
+            Some(ref spool) => spool,
+            None => {
+                warn!(logger, "Message was rejected"; "error" => reason, "bytes" => data.len());
+                return
+            }
+        };
+
+        let letter = DeadLetter {
+            timestamp: UTC::now(),
+            input: input.to_owned(),
+            remote_addr: remote_addr.map(ToOwned::to_owned),
+            error: reason.to_owned(),
+            offset: None,
+            data: data.to_vec(),
+        };
+
+        match spool.borrow_mut().append(&letter) {
+            Ok(()) => warn!(logger, "Message was rejected; stored in dead letter spool"; "error" => reason, "bytes" => data.len()),
+            Err(err) => error!(logger, "Message was rejected and could not be stored in dead letter spool"; "error" => reason, "bytes" => data.len(), "spool_error" => format!("{}", err))
+        }
+    }
+}
+
+/// Spool messages that codec failed to decode; stream continues if codec could skip the
+/// message and fails otherwise
+pub fn spool_failed<S, T>(logger: Logger, dead_letters: DeadLetters, input: &'static str, remote_addr: Option<String>, messages: S) -> Box<Stream<Item=T, Error=IoError>> where S: Stream<Item=T, Error=IoError> + 'static, T: 'static {
+    Box::new(messages
+        .then(move |result| match result {
+            Ok(message) => Ok(Some(message)),
+            Err(err) => {
+                let resumable = DecodeError::from_io_error(&err).map(|failure| {
+                    dead_letters.store(&logger, input, remote_addr.as_ref().map(|addr| addr.as_str()), &failure.frame, &failure.error);
+                    failure.resumable
+                });
+
+                if resumable == Some(true) {
+                    Ok(None)
+                } else {
+                    Err(err)
+                }
+            }
+        })
+        .filter_map(|message| message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::UTC;
+    use tempfile::TempDir;
+
+    fn letter(data: &[u8]) -> DeadLetter {
+        DeadLetter {
+            timestamp: UTC::now(),
+            input: "syslog".to_owned(),
+            remote_addr: Some("127.0.0.1:4242".to_owned()),
+            error: "Failed to parse".to_owned(),
+            offset: Some(1),
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn append_and_read() {
+        let dir = TempDir::new().unwrap();
+        let letters = vec![letter(b"foo\nbar"), letter(b"\x00\xffbaz\n")];
+
+        {
+            let mut spool = Spool::open(dir.path(), SpoolConfig::default()).unwrap();
+            for letter in letters.iter() {
+                spool.append(letter).unwrap();
+            }
+        }
+
+        let spool = Spool::open(dir.path(), SpoolConfig::default()).unwrap();
+        assert_eq!(spool.segments().len(), 1);
+        assert_eq!(read_segment(&spool.segments()[0]).unwrap(), letters);
+    }
+
+    #[test]
+    fn rewrite_segment() {
+        let dir = TempDir::new().unwrap();
+
+        let mut spool = Spool::open(dir.path(), SpoolConfig::default()).unwrap();
+        spool.append(&letter(b"foo")).unwrap();
+        spool.append(&letter(b"bar")).unwrap();
+        let segment = spool.segments().pop().unwrap();
+
+        write_segment(&segment, &[letter(b"bar")]).unwrap();
+        assert_eq!(read_segment(&segment).unwrap().iter().map(|l| l.data.clone()).collect::<Vec<_>>(), vec![b"bar".to_vec()]);
+
+        write_segment(&segment, &[]).unwrap();
+        assert!(!segment.exists());
+    }
+
+    #[test]
+    fn rotate_and_bound() {
+        let dir = TempDir::new().unwrap();
+        let config = SpoolConfig {
+            segment_size: 150,
+            max_size: 400,
+        };
+
+        let mut spool = Spool::open(dir.path(), config).unwrap();
+        for _ in 0..10 {
+            spool.append(&letter(&[b'x'; 100])).unwrap();
+        }
+
+        assert!(spool.size() <= 400);
+        let segments = spool.segments();
+        assert!(segments.len() > 1);
+        assert_eq!(read_segment(segments.last().unwrap()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn partial_last_record() {
+        let dir = TempDir::new().unwrap();
+        let mut spool = Spool::open(dir.path(), SpoolConfig::default()).unwrap();
+        spool.append(&letter(b"foo")).unwrap();
+        let segment = spool.segments().pop().unwrap();
+
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(b"{\"timestamp\":\"2017-").unwrap();
+        assert_eq!(read_segment(&segment).unwrap().len(), 1);
+
+        file.write_all(b"01-01T00:00:00+00:00\",\"input\":\"syslog\",\"remote_addr\":null,\"error\":\"\",\"offset\":null,\"length\":3}\nba").unwrap();
+        assert_eq!(read_segment(&segment).unwrap().len(), 1);
+    }
+}
@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 use std::fmt;
+use std::collections::BTreeMap;
 use maybe_string::MaybeStr;
 use chrono::{DateTime, UTC};
 
@@ -12,7 +13,7 @@ use chrono::{DateTime, UTC};
 /// By implementing this traits source event structures can enable this formats to be produced from
 /// them by different Serializers
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Payload<'e> {
     String(Cow<'e, str>),
     Data(Cow<'e, MaybeStr>)
@@ -29,9 +30,18 @@ impl<'e> fmt::Display for Payload<'e> {
     }
 }
 
+/// Value of event meta entry; arrays and objects are one-shot iterators
 pub enum MetaValue<'i> {
-    String(&'i str), // TODO: Cow?
+    Null,
+    Bool(bool),
+    I64(i64),
     U64(u64),
+    F64(f64),
+    String(Cow<'i, str>),
+    /// Serialized to JSON as lossy UTF-8 string
+    Bytes(Cow<'i, [u8]>),
+    Timestamp(DateTime<UTC>),
+    Array(Box<Iterator<Item=MetaValue<'i>> + 'i>),
     Object(Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i>),
 }
 
@@ -60,8 +70,259 @@ pub trait LogstashEvent {
     fn version(&self) -> &str { "1" }
     fn message(&self) -> Option<Cow<str>>;
     fn event_type(&self) -> &str;
-    fn tags(&self) -> Vec<&'static str>;
+    fn tags(&self) -> Vec<&str>;
     fn processed(&self) -> DateTime<UTC>;
     fn id(&self) -> Cow<str>;
     fn fields<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i>;
 }
+
+/// Information provided by an input about where and how an event was received
+pub trait Origin {
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i>;
+}
+
+/// Event as produced by codec decorated with its Origin by the input that received it
+#[derive(Debug, Clone)]
+pub struct Received<T, O> {
+    pub event: T,
+    pub origin: O,
+}
+
+impl<T: Event, O: Origin> Event for Received<T, O> {
+    fn id(&self) -> Cow<str> { self.event.id() }
+    fn source(&self) -> Cow<str> { self.event.source() }
+    fn timestamp(&self) -> DateTime<UTC> { self.event.timestamp() }
+    fn payload(&self) -> Option<Payload> { self.event.payload() }
+
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(self.event.meta().chain(self.origin.meta()))
+    }
+}
+
+impl<T: LogstashEvent, O: Origin> LogstashEvent for Received<T, O> {
+    fn timestamp(&self) -> DateTime<UTC> { self.event.timestamp() }
+    fn version(&self) -> &str { self.event.version() }
+    fn message(&self) -> Option<Cow<str>> { self.event.message() }
+    fn event_type(&self) -> &str { self.event.event_type() }
+    fn tags(&self) -> Vec<&str> { self.event.tags() }
+    fn processed(&self) -> DateTime<UTC> { self.event.processed() }
+    fn id(&self) -> Cow<str> { self.event.id() }
+
+    fn fields<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(self.event.fields().chain(self.origin.meta()))
+    }
+}
+
+/// Owned value of DynEvent field
+#[derive(Debug, Clone, PartialEq)]
+pub enum DynValue {
+    Null,
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Timestamp(DateTime<UTC>),
+    Array(Vec<DynValue>),
+    Object(BTreeMap<String, DynValue>),
+}
+
+impl DynValue {
+    pub fn as_meta(&self) -> MetaValue {
+        match *self {
+            DynValue::Null => MetaValue::Null,
+            DynValue::Bool(b) => MetaValue::Bool(b),
+            DynValue::I64(n) => MetaValue::I64(n),
+            DynValue::U64(n) => MetaValue::U64(n),
+            DynValue::F64(n) => MetaValue::F64(n),
+            DynValue::String(ref s) => MetaValue::String(Cow::Borrowed(s.as_str())),
+            DynValue::Bytes(ref bytes) => MetaValue::Bytes(Cow::Borrowed(bytes.as_slice())),
+            DynValue::Timestamp(timestamp) => MetaValue::Timestamp(timestamp),
+            DynValue::Array(ref values) => MetaValue::Array(Box::new(values.iter().map(DynValue::as_meta))),
+            DynValue::Object(ref map) => MetaValue::Object(Box::new(map.iter().map(|(key, value)| (key.as_str(), value.as_meta())))),
+        }
+    }
+}
+
+impl<'i> From<MetaValue<'i>> for DynValue {
+    fn from(value: MetaValue<'i>) -> DynValue {
+        match value {
+            MetaValue::Null => DynValue::Null,
+            MetaValue::Bool(b) => DynValue::Bool(b),
+            MetaValue::I64(n) => DynValue::I64(n),
+            MetaValue::U64(n) => DynValue::U64(n),
+            MetaValue::F64(n) => DynValue::F64(n),
+            MetaValue::String(s) => DynValue::String(s.into_owned()),
+            MetaValue::Bytes(bytes) => DynValue::Bytes(bytes.into_owned()),
+            MetaValue::Timestamp(timestamp) => DynValue::Timestamp(timestamp),
+            MetaValue::Array(iter) => DynValue::Array(iter.map(DynValue::from).collect()),
+            MetaValue::Object(iter) => DynValue::Object(iter.map(|(key, value)| (key.to_owned(), DynValue::from(value))).collect()),
+        }
+    }
+}
+
+fn owned_payload(payload: Payload) -> Payload<'static> {
+    match payload {
+        Payload::String(s) => Payload::String(Cow::Owned(s.into_owned())),
+        Payload::Data(data) => Payload::Data(Cow::Owned(data.into_owned())),
+    }
+}
+
+/// Owned event with fields that can be freely changed by processing stages
+///
+/// Any Event can be turned into DynEvent with DynEvent::from_event; its meta becomes fields.
+/// Events that are also LogstashEvent should use DynEvent::from_logstash_event to keep their
+/// type, tags and processed time.
+#[derive(Debug, Clone)]
+pub struct DynEvent {
+    pub id: String,
+    pub source: String,
+    pub timestamp: DateTime<UTC>,
+    pub processed: DateTime<UTC>,
+    pub payload: Option<Payload<'static>>,
+    pub event_type: String,
+    pub tags: Vec<String>,
+    pub fields: BTreeMap<String, DynValue>,
+}
+
+impl DynEvent {
+    pub fn from_event<T: Event>(event: &T) -> DynEvent {
+        DynEvent {
+            id: event.id().into_owned(),
+            source: event.source().into_owned(),
+            timestamp: event.timestamp(),
+            processed: UTC::now(),
+            payload: event.payload().map(owned_payload),
+            event_type: "event".to_owned(),
+            tags: Vec::new(),
+            fields: event.meta().map(|(key, value)| (key.to_owned(), DynValue::from(value))).collect(),
+        }
+    }
+
+    pub fn from_logstash_event<T: Event + LogstashEvent>(event: &T) -> DynEvent {
+        DynEvent {
+            id: Event::id(event).into_owned(),
+            source: event.source().into_owned(),
+            timestamp: Event::timestamp(event),
+            processed: event.processed(),
+            payload: event.payload().map(owned_payload),
+            event_type: event.event_type().to_owned(),
+            tags: event.tags().into_iter().map(ToOwned::to_owned).collect(),
+            fields: event.fields().map(|(key, value)| (key.to_owned(), DynValue::from(value))).collect(),
+        }
+    }
+
+    /// Move field to new key replacing what was there; returns false if there was no such field
+    pub fn rename_field(&mut self, from: &str, to: &str) -> bool {
+        match self.fields.remove(from) {
+            Some(value) => {
+                self.fields.insert(to.to_owned(), value);
+                true
+            }
+            None => false
+        }
+    }
+
+    fn field_meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(self.fields.iter().map(|(key, value)| (key.as_str(), value.as_meta())))
+    }
+}
+
+impl Event for DynEvent {
+    fn id(&self) -> Cow<str> { Cow::Borrowed(&self.id) }
+    fn source(&self) -> Cow<str> { Cow::Borrowed(&self.source) }
+    fn timestamp(&self) -> DateTime<UTC> { self.timestamp }
+
+    fn payload(&self) -> Option<Payload> {
+        match self.payload {
+            Some(Payload::String(ref s)) => Some(Payload::String(Cow::Borrowed(s))),
+            Some(Payload::Data(ref data)) => Some(Payload::Data(Cow::Borrowed(data))),
+            None => None
+        }
+    }
+
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> { self.field_meta() }
+}
+
+impl LogstashEvent for DynEvent {
+    fn timestamp(&self) -> DateTime<UTC> { self.timestamp }
+
+    fn message(&self) -> Option<Cow<str>> {
+        match self.payload {
+            Some(Payload::String(ref s)) => Some(Cow::Borrowed(s)),
+            Some(Payload::Data(ref data)) => Some(Cow::Owned(data.to_lossy_string())),
+            None => None
+        }
+    }
+
+    fn event_type(&self) -> &str { &self.event_type }
+    fn tags(&self) -> Vec<&str> { self.tags.iter().map(String::as_str).collect() }
+    fn processed(&self) -> DateTime<UTC> { self.processed }
+    fn id(&self) -> Cow<str> { Cow::Borrowed(&self.id) }
+    fn fields<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> { self.field_meta() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::syslog::{SyslogEvent, IdStrategy, parse_errors};
+    use serialize::{Serializer, JsonLogstashEventSerializer};
+
+    #[test]
+    fn dyn_event_from_syslog() {
+        let message = r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3"] foo"#;
+        let event = parse_errors::syslog_rfc5424(message.as_bytes()).map(|event| event.identified(IdStrategy::MsgId, message.as_bytes()).into_owned()).unwrap().1;
+
+        let mut event = DynEvent::from_event(&event);
+        assert_eq!(event.id, "ID47");
+        assert_eq!(event.source, "mymachine.example.com");
+        assert_eq!(event.fields.get("program"), Some(&DynValue::String("evntslog".to_owned())));
+        assert!(event.rename_field("program", "app"));
+        event.fields.remove("structured_data");
+        event.fields.insert("env".to_owned(), DynValue::String("prod".to_owned()));
+
+        let json = String::from_utf8(JsonLogstashEventSerializer.serialize(&event, Vec::new()).unwrap()).unwrap();
+        assert!(json.contains(r#""message":"foo""#));
+        assert!(json.contains(r#""@id":"ID47""#));
+        assert!(json.contains(r#""app":"evntslog""#));
+        assert!(json.contains(r#""env":"prod""#));
+        assert!(!json.contains("program"));
+        assert!(!json.contains("exampleSDID"));
+    }
+
+    #[test]
+    fn dyn_event_from_logstash_event() {
+        let message = "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo";
+        let syslog = parse_errors::syslog_rfc5424(message.as_bytes()).map(SyslogEvent::into_owned).unwrap().1;
+
+        let mut event = DynEvent::from_logstash_event(&syslog);
+        assert_eq!(event.event_type, "syslog");
+        assert_eq!(event.tags, vec!["class:syslog".to_owned()]);
+        assert_eq!(event.processed, syslog.processed);
+        event.tags.push("relayed".to_owned());
+
+        let json = String::from_utf8(JsonLogstashEventSerializer.serialize(&event, Vec::new()).unwrap()).unwrap();
+        assert!(json.contains(r#""type":"syslog""#));
+        assert!(json.contains(r#""tags":["class:syslog","relayed"]"#));
+    }
+
+    #[test]
+    fn typed_fields_to_json() {
+        let message = "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo";
+        let event = parse_errors::syslog_rfc5424(message.as_bytes()).map(SyslogEvent::into_owned).unwrap().1;
+
+        let mut event = DynEvent::from_event(&event);
+        event.fields.clear();
+        event.fields.insert("null".to_owned(), DynValue::Null);
+        event.fields.insert("bool".to_owned(), DynValue::Bool(true));
+        event.fields.insert("i64".to_owned(), DynValue::I64(-3));
+        event.fields.insert("f64".to_owned(), DynValue::F64(0.5));
+        event.fields.insert("bytes".to_owned(), DynValue::Bytes(b"ab\xff".to_vec()));
+        event.fields.insert("time".to_owned(), DynValue::Timestamp(event.timestamp));
+        event.fields.insert("array".to_owned(), DynValue::Array(vec![DynValue::U64(1), DynValue::String("a".to_owned())]));
+
+        let json = String::from_utf8(JsonLogstashEventSerializer.serialize(&event, Vec::new()).unwrap()).unwrap();
+        assert!(json.ends_with("\"array\":[1,\"a\"],\"bool\":true,\"bytes\":\"ab\u{FFFD}\",\"f64\":0.5,\"i64\":-3,\"null\":null,\"time\":\"2003-10-11T22:14:15.003+00:00\"}"));
+    }
+}
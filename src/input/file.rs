@@ -0,0 +1,651 @@
+use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom, BufRead, BufReader};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Result as IoResult;
+use std::os::unix::fs::{MetadataExt, FileExt};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use std::mem;
+use std::cmp::min;
+
+use slog::Logger;
+
+use futures::Future;
+use futures::stream::{self, Stream};
+
+use tokio_core::io::{Codec, EasyBuf};
+use tokio_core::reactor::{Handle, Interval};
+
+use glob::glob;
+
+use PipeError;
+use shutdown::{Shutdown, until_shutdown};
+use ack::Acked;
+use event::{Origin, Received, MetaValue};
+use dead_letter::DeadLetters;
+use codec::DecodeError;
+use codec::nom::Resync;
+use codec::error::ParseError;
+
+// limit how much is read from single file per poll so other files get their turn
+const MAX_READ_PER_POLL: u64 = 1024 * 1024;
+
+// number of bytes at the start of the file used to tell if inode was reused for another file
+const FINGERPRINT_LEN: usize = 1024;
+
+/// Path of the file and byte offset in it at which the event starts
+#[derive(Debug, Clone)]
+pub struct FilePosition {
+    pub path: String,
+    pub offset: u64,
+}
+
+impl Origin for FilePosition {
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(vec![
+            ("path", MetaValue::String(Cow::Borrowed(self.path.as_str()))),
+            ("offset", MetaValue::U64(self.offset)),
+        ].into_iter())
+    }
+}
+
+/// Files are identified by inode so they can be followed across renames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    fn new(meta: &fs::Metadata) -> FileId {
+        FileId {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        }
+    }
+}
+
+/// Hash of the first bytes of the file; inode can be reused by new file after old one was
+/// removed so inode alone does not tell if we have seen the file before
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fingerprint {
+    len: u64,
+    hash: u64,
+}
+
+impl Fingerprint {
+    // FNV-1a as it has to be stable between runs
+    fn of(head: &[u8]) -> Fingerprint {
+        let mut hash = 0xcbf29ce484222325u64;
+        for &byte in head {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Fingerprint {
+            len: head.len() as u64,
+            hash: hash,
+        }
+    }
+
+    /// Fingerprint of up to len first bytes of the file
+    fn read(file: &File, len: usize) -> IoResult<Fingerprint> {
+        let mut head = vec![0; len];
+        let mut read = 0;
+        while read < len {
+            match file.read_at(&mut head[read..], read as u64)? {
+                0 => break,
+                bytes => read += bytes
+            }
+        }
+        head.truncate(read);
+        Ok(Fingerprint::of(&head))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SincePosition {
+    offset: u64,
+    fingerprint: Fingerprint,
+    path: PathBuf,
+}
+
+/// sincedb-style state file with one "dev ino offset fingerprint_len fingerprint path" line per
+/// file
+struct SinceDb {
+    path: PathBuf,
+    positions: HashMap<FileId, SincePosition>,
+    /// Files that are currently followed; positions of other files are not updated
+    followed: HashSet<FileId>,
+    dirty: bool,
+}
+
+impl SinceDb {
+    fn load(path: &Path) -> IoResult<SinceDb> {
+        let mut positions = HashMap::new();
+
+        match File::open(path) {
+            Ok(file) => for line in BufReader::new(file).lines() {
+                let line = line?;
+                let mut fields = line.splitn(6, ' ');
+                match (fields.next().and_then(|f| f.parse().ok()),
+                       fields.next().and_then(|f| f.parse().ok()),
+                       fields.next().and_then(|f| f.parse().ok()),
+                       fields.next().and_then(|f| f.parse().ok()),
+                       fields.next().and_then(|f| u64::from_str_radix(f, 16).ok()),
+                       fields.next()) {
+                    (Some(dev), Some(ino), Some(offset), Some(len), Some(hash), Some(file_path)) => {
+                        positions.insert(FileId { dev: dev, ino: ino }, SincePosition {
+                            offset: offset,
+                            fingerprint: Fingerprint { len: len, hash: hash },
+                            path: PathBuf::from(file_path),
+                        });
+                    }
+                    _ => return Err(IoError::new(IoErrorKind::InvalidData, format!("bad sincedb line: {:?}", line)))
+                }
+            },
+            Err(ref err) if err.kind() == IoErrorKind::NotFound => (),
+            Err(err) => return Err(err)
+        }
+
+        Ok(SinceDb {
+            path: path.to_owned(),
+            positions: positions,
+            followed: HashSet::new(),
+            dirty: false,
+        })
+    }
+
+    /// Offset to resume reading given file from; files that were not seen before or that
+    /// changed since (truncated or inode reused) are read from the start
+    fn resume(&self, id: &FileId, file: &File, len: u64) -> IoResult<u64> {
+        match self.positions.get(id) {
+            Some(position) if position.offset <= len => {
+                if Fingerprint::read(file, position.fingerprint.len as usize)? == position.fingerprint {
+                    Ok(position.offset)
+                } else {
+                    Ok(0)
+                }
+            }
+            _ => Ok(0)
+        }
+    }
+
+    fn follow(&mut self, id: FileId) {
+        self.followed.insert(id);
+    }
+
+    fn commit(&mut self, id: FileId, offset: u64, fingerprint: Fingerprint, path: &Path) {
+        // acknowledgment came after we stopped following the file
+        if !self.followed.contains(&id) {
+            return
+        }
+        self.positions.insert(id, SincePosition {
+            offset: offset,
+            fingerprint: fingerprint,
+            path: path.to_owned(),
+        });
+        self.dirty = true;
+    }
+
+    fn forget(&mut self, id: &FileId) {
+        self.followed.remove(id);
+        if self.positions.remove(id).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    // write new file and rename it over the old one so we never leave half written state
+    fn store(&mut self) -> IoResult<()> {
+        if !self.dirty {
+            return Ok(())
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut tmp = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+            for (id, position) in self.positions.iter() {
+                writeln!(tmp, "{} {} {} {} {:x} {}", id.dev, id.ino, position.offset, position.fingerprint.len, position.fingerprint.hash, position.path.display())?;
+            }
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+// last events get acknowledged after input stream has finished
+impl Drop for SinceDb {
+    fn drop(&mut self) {
+        let _ = self.store();
+    }
+}
+
+struct TailedFile<C> {
+    path: PathBuf,
+    file: File,
+    /// Offset of first byte in buf
+    offset: u64,
+    buf: EasyBuf,
+    codec: C,
+    seen: bool,
+    fingerprint: Fingerprint,
+}
+
+impl<C> TailedFile<C> {
+    fn read_offset(&self) -> u64 {
+        self.offset + self.buf.len() as u64
+    }
+}
+
+/// Decoded event with file position after it and fingerprint of the file to commit on
+/// acknowledgment
+type Tailed<T> = (Received<T, FilePosition>, FileId, u64, Fingerprint);
+
+// resume from committed position unless file got truncated or replaced since
+fn open(since_db: &SinceDb, id: &FileId, path: &Path, len: u64) -> IoResult<(File, u64, Fingerprint)> {
+    let mut file = File::open(path)?;
+    let offset = since_db.resume(id, &file, len)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let fingerprint = Fingerprint::read(&file, FINGERPRINT_LEN)?;
+    Ok((file, offset, fingerprint))
+}
+
+struct Tailer<C> {
+    logger: Logger,
+    name: &'static str,
+    dead_letters: DeadLetters,
+    patterns: Vec<String>,
+    codec: C,
+    files: HashMap<FileId, TailedFile<C>>,
+    since_db: Rc<RefCell<SinceDb>>,
+}
+
+impl<C, T> Tailer<C> where C: Codec<In=T> + Clone {
+    fn discover(&mut self) {
+        for file in self.files.values_mut() {
+            file.seen = false;
+        }
+
+        for pattern in self.patterns.iter() {
+            let paths = match glob(pattern) {
+                Ok(paths) => paths,
+                Err(err) => {
+                    error!(&self.logger, "Bad file pattern"; "error" => format!("{}", err), "pattern" => pattern.clone());
+                    continue
+                }
+            };
+
+            for path in paths.filter_map(|path| path.ok()) {
+                let meta = match fs::metadata(&path) {
+                    Ok(meta) => meta,
+                    Err(_) => continue
+                };
+                if !meta.is_file() {
+                    continue
+                }
+
+                match self.files.entry(FileId::new(&meta)) {
+                    Entry::Occupied(mut entry) => {
+                        let tailed = entry.get_mut();
+                        if tailed.path != path {
+                            info!(&self.logger, "File renamed"; "from" => format!("{}", tailed.path.display()), "to" => format!("{}", path.display()));
+                            tailed.path = path;
+                        }
+                        tailed.seen = true;
+                    }
+                    Entry::Vacant(entry) => {
+                        let (file, offset, fingerprint) = match open(&self.since_db.borrow(), entry.key(), &path, meta.len()) {
+                            Ok(opened) => opened,
+                            Err(err) => {
+                                error!(&self.logger, "Failed to open file"; "error" => format!("{}", err), "path" => format!("{}", path.display()));
+                                continue
+                            }
+                        };
+                        self.since_db.borrow_mut().follow(*entry.key());
+
+                        info!(&self.logger, "Following file"; "path" => format!("{}", path.display()), "offset" => offset);
+                        entry.insert(TailedFile {
+                            path: path,
+                            file: file,
+                            offset: offset,
+                            buf: EasyBuf::new(),
+                            codec: self.codec.clone(),
+                            seen: true,
+                            fingerprint: fingerprint,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn read(logger: &Logger, tailed: &mut TailedFile<C>) -> IoResult<bool> {
+        // copytruncate rotation
+        if tailed.file.metadata()?.len() < tailed.read_offset() {
+            info!(logger, "File truncated"; "path" => format!("{}", tailed.path.display()));
+            tailed.file.seek(SeekFrom::Start(0))?;
+            tailed.offset = 0;
+            tailed.buf = EasyBuf::new();
+            tailed.fingerprint = Fingerprint::of(&[]);
+        }
+
+        let read = {
+            let mut buf = tailed.buf.get_mut();
+            (&mut tailed.file).take(MAX_READ_PER_POLL).read_to_end(&mut *buf)?
+        };
+
+        // file was shorter than fingerprint when we have seen it last time
+        if read > 0 && tailed.fingerprint.len < FINGERPRINT_LEN as u64 {
+            tailed.fingerprint = Fingerprint::read(&tailed.file, FINGERPRINT_LEN)?;
+        }
+        Ok(read > 0)
+    }
+
+    fn decode(&self, id: FileId, tailed: &mut TailedFile<C>, events: &mut Vec<Tailed<T>>) {
+        let logger = &self.logger;
+        loop {
+            let have_bytes = tailed.buf.len();
+            match tailed.codec.decode(&mut tailed.buf) {
+                Ok(Some(event)) => {
+                    let start = tailed.offset;
+                    tailed.offset += (have_bytes - tailed.buf.len()) as u64;
+
+                    events.push((Received {
+                        event: event,
+                        origin: FilePosition {
+                            path: format!("{}", tailed.path.display()),
+                            offset: start,
+                        }
+                    }, id, tailed.offset, tailed.fingerprint));
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    let path = format!("{}", tailed.path.display());
+                    let failure = DecodeError::from_io_error(&err);
+
+                    if failure.map(|failure| failure.resumable).unwrap_or(false) {
+                        // codec skipped the frame; carry on with the next one
+                        if let Some(failure) = failure {
+                            self.dead_letters.store(logger, self.name, Some(path.as_str()), &failure.frame, &failure.error);
+                        }
+                        error!(logger, "Failed to decode file data"; "error" => format!("{}", err), "path" => path, "offset" => tailed.offset, "skipped" => have_bytes - tailed.buf.len());
+                        tailed.offset += (have_bytes - tailed.buf.len()) as u64;
+                        continue
+                    }
+
+                    // we don't know where next frame starts; skip the failing line only
+                    let skip = match Resync::Newline.frame_len(tailed.buf.as_slice()) {
+                        Ok(Some(line_len)) => line_len,
+                        // wait for the rest of the line unless it will not fit a read
+                        _ if (tailed.buf.len() as u64) < MAX_READ_PER_POLL => break,
+                        _ => tailed.buf.len()
+                    };
+
+                    {
+                        let line = &tailed.buf.as_slice()[..skip];
+                        match failure {
+                            Some(failure) => self.dead_letters.store(logger, self.name, Some(path.as_str()), &failure.frame[..min(skip, failure.frame.len())], &failure.error),
+                            None => self.dead_letters.store(logger, self.name, Some(path.as_str()), line, &ParseError::new("input", "Failed to decode file data", line, 0)),
+                        }
+                    }
+                    error!(logger, "Failed to decode file data"; "error" => format!("{}", err), "path" => path, "offset" => tailed.offset, "skipped" => have_bytes - tailed.buf.len() + skip);
+                    tailed.offset += (have_bytes - tailed.buf.len() + skip) as u64;
+                    tailed.buf.drain_to(skip);
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self) -> Vec<Tailed<T>> {
+        self.discover();
+
+        let mut events = Vec::new();
+        let mut gone = Vec::new();
+
+        // take files out so we can decode with &self
+        let mut files = mem::replace(&mut self.files, HashMap::new());
+        for (id, tailed) in files.iter_mut() {
+            let more = match Tailer::read(&self.logger, tailed) {
+                Ok(more) => more,
+                Err(err) => {
+                    error!(&self.logger, "Failed to read file"; "error" => format!("{}", err), "path" => format!("{}", tailed.path.display()));
+                    false
+                }
+            };
+
+            self.decode(*id, tailed, &mut events);
+
+            // rotated away and fully read
+            if !tailed.seen && !more {
+                gone.push(*id);
+            }
+        }
+
+        self.files = files;
+
+        for id in gone {
+            if let Some(tailed) = self.files.remove(&id) {
+                info!(&self.logger, "Stopped following file"; "path" => format!("{}", tailed.path.display()));
+            }
+            self.since_db.borrow_mut().forget(&id);
+        }
+
+        events
+    }
+}
+
+/// Follow files matching glob patterns decoding them with codec
+///
+/// Files are followed by inode so renamed files are read to the end and truncated files are
+/// read again from the start. Position after each event is stored in since_db file once the
+/// event got acknowledged downstream (see output::write::write_threaded_acked) so that
+/// restarted input resumes from there. Files not found in since_db or which start differs from
+/// what was seen before (inode reused by new file) are read from the beginning. Data that failed
+/// to decode is stored in dead letter spool.
+pub fn file_input<C, T, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, patterns: &[&str], since_db: &Path, poll_interval: Duration, codec: C) -> Box<Stream<Item=Acked<Received<T, FilePosition>>, Error=PipeError<(), OE>>> where C: Codec<In=T> + Clone + 'static, T: Debug + 'static, OE: 'static {
+    let logger = logger.new(o!("input" => name));
+
+    let since_db = Rc::new(RefCell::new(SinceDb::load(since_db).expect("loaded sincedb file")));
+    let mut tailer = Tailer {
+        logger: logger.clone(),
+        name: name,
+        dead_letters: dead_letters.clone(),
+        patterns: patterns.iter().map(|p| (*p).to_owned()).collect(),
+        codec: codec,
+        files: HashMap::new(),
+        since_db: since_db.clone(),
+    };
+    info!(&logger, "Following files"; "patterns" => format!("{:?}", tailer.patterns));
+
+    let err_logger = logger.clone();
+    Box::new(until_shutdown(shutdown, Interval::new(poll_interval, &handle).expect("file poll interval"))
+        .map_err(move |err| {
+            error!(&err_logger, "File poll interval failed: {}", err);
+            PipeError::Input(())
+        })
+        .map(move |_| {
+            if let Err(err) = since_db.borrow_mut().store() {
+                error!(&logger, "Failed to store sincedb file: {}", err);
+            }
+
+            let handle = handle.clone();
+            let since_db = since_db.clone();
+            stream::iter(tailer.poll().into_iter().map(move |(received, id, end, fingerprint)| {
+                let path = PathBuf::from(&received.origin.path);
+                let (acked, ack) = Acked::new(received);
+
+                let since_db = since_db.clone();
+                handle.spawn(ack
+                    .map(move |_| since_db.borrow_mut().commit(id, end, fingerprint, &path))
+                    .map_err(|_| ()));
+
+                Ok(acked)
+            }))
+        })
+        .flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::str::from_utf8;
+    use tempfile::TempDir;
+    use null_logger;
+
+    #[derive(Clone)]
+    struct Lines;
+
+    impl Codec for Lines {
+        type In = String;
+        type Out = ();
+
+        fn decode(&mut self, buf: &mut EasyBuf) -> IoResult<Option<String>> {
+            match buf.as_slice().iter().position(|&c| c == b'\n') {
+                // fails without telling where the next line starts
+                Some(_) if buf.as_slice().starts_with(b"bad\n") => Err(IoError::new(IoErrorKind::InvalidData, "bad line")),
+                Some(pos) => {
+                    let line = buf.drain_to(pos + 1);
+                    Ok(Some(from_utf8(&line.as_slice()[..pos]).unwrap().to_owned()))
+                }
+                None => Ok(None)
+            }
+        }
+
+        fn encode(&mut self, _msg: (), _buf: &mut Vec<u8>) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    fn tailer(dir: &TempDir) -> Tailer<Lines> {
+        Tailer {
+            logger: null_logger(),
+            name: "test",
+            dead_letters: DeadLetters::disabled(),
+            patterns: vec![format!("{}", dir.path().join("*.log").display())],
+            codec: Lines,
+            files: HashMap::new(),
+            since_db: Rc::new(RefCell::new(SinceDb::load(&dir.path().join("sincedb")).unwrap())),
+        }
+    }
+
+    fn append(path: &Path, data: &[u8]) {
+        OpenOptions::new().append(true).create(true).open(path).unwrap().write_all(data).unwrap();
+    }
+
+    fn lines(tailed: &[Tailed<String>]) -> Vec<String> {
+        let mut lines: Vec<String> = tailed.iter().map(|&(ref received, _, _, _)| received.event.clone()).collect();
+        lines.sort();
+        lines
+    }
+
+    // as if event got acknowledged
+    fn ack(tailer: &Tailer<Lines>, tailed: &Tailed<String>) {
+        let &(ref received, id, end, fingerprint) = tailed;
+        tailer.since_db.borrow_mut().commit(id, end, fingerprint, Path::new(&received.origin.path));
+    }
+
+    #[test]
+    fn resume() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.log");
+        append(&path, b"foo\nbar\n");
+
+        {
+            let mut tailer = tailer(&dir);
+            let tailed = tailer.poll();
+            assert_eq!(lines(&tailed), vec!["bar", "foo"]);
+            assert_eq!(tailed[0].0.event, "foo");
+            ack(&tailer, &tailed[0]);
+        }
+
+        append(&path, b"baz\n");
+        let mut tailer = tailer(&dir);
+        assert_eq!(lines(&tailer.poll()), vec!["bar", "baz"]);
+    }
+
+    #[test]
+    fn replaced_file_read_from_start() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.log");
+        append(&path, b"foo\nbar\n");
+
+        {
+            let mut tailer = tailer(&dir);
+            let tailed = tailer.poll();
+            ack(&tailer, &tailed[1]);
+        }
+
+        // same inode but different content
+        File::create(&path).unwrap().write_all(b"quix\nbaz\nqux\n").unwrap();
+        let mut tailer = tailer(&dir);
+        assert_eq!(lines(&tailer.poll()), vec!["baz", "quix", "qux"]);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.log");
+        append(&path, b"foo\n");
+
+        let mut tailer = tailer(&dir);
+        assert_eq!(lines(&tailer.poll()), vec!["foo"]);
+
+        let rotated = dir.path().join("test.log.1");
+        fs::rename(&path, &rotated).unwrap();
+        append(&rotated, b"bar\n");
+        append(&path, b"baz\n");
+        let tailed = tailer.poll();
+        assert_eq!(lines(&tailed), vec!["bar", "baz"]);
+
+        // rotated file was read to the end and forgotten
+        assert!(tailer.poll().is_empty());
+        assert_eq!(tailer.files.len(), 1);
+
+        // late acknowledgment does not bring it back
+        for tailed in tailed.iter() {
+            ack(&tailer, tailed);
+        }
+        assert_eq!(tailer.since_db.borrow().positions.len(), 1);
+    }
+
+    #[test]
+    fn copytruncate() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.log");
+        append(&path, b"foo\nbar\n");
+
+        let mut tailer = tailer(&dir);
+        assert_eq!(lines(&tailer.poll()), vec!["bar", "foo"]);
+
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(0).unwrap();
+        append(&path, b"baz\n");
+        let tailed = tailer.poll();
+        assert_eq!(lines(&tailed), vec!["baz"]);
+        assert_eq!(tailed[0].0.origin.offset, 0);
+    }
+
+    #[test]
+    fn skip_undecodable_line() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.log");
+        append(&path, b"foo\nbad\nbar\nba");
+
+        let mut tailer = tailer(&dir);
+        let tailed = tailer.poll();
+        assert_eq!(lines(&tailed), vec!["bar", "foo"]);
+        assert_eq!(tailed.iter().find(|tailed| tailed.0.event == "bar").unwrap().0.origin.offset, 8);
+
+        append(&path, b"z\n");
+        assert_eq!(lines(&tailer.poll()), vec!["baz"]);
+    }
+}
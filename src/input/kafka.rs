@@ -0,0 +1,483 @@
+use std::fmt::Debug;
+use std::borrow::Cow;
+use std::collections::{HashMap, BTreeMap};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Result as IoResult;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::time::{Duration, Instant};
+
+use slog::Logger;
+
+use futures::{Future, Sink};
+use futures::stream::Stream;
+use futures::sync::mpsc;
+
+use tokio_core::reactor::Handle;
+
+use nom::{IResult, ErrorKind};
+
+use kafka::consumer::{Consumer, GroupOffsetStorage};
+pub use kafka::consumer::FetchOffset;
+
+use PipeError;
+use shutdown::Shutdown;
+use ack::Acked;
+use dead_letter::DeadLetters;
+use codec::error::ParseError;
+use event::{Origin, Received, MetaValue};
+use output::retry::Backoff;
+
+/// Kafka record the event was decoded from
+#[derive(Debug, Clone)]
+pub struct KafkaPosition {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl Origin for KafkaPosition {
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(vec![
+            ("topic", MetaValue::String(Cow::Borrowed(self.topic.as_str()))),
+            ("partition", MetaValue::I64(self.partition as i64)),
+            ("offset", MetaValue::I64(self.offset)),
+        ].into_iter())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaInputConfig {
+    /// Bootstrap brokers as host:port
+    pub hosts: Vec<String>,
+    pub client_id: String,
+    /// Consumer group offsets are committed for
+    ///
+    /// Group membership is not coordinated with brokers so only one logslash instance may use a
+    /// given group; more members would each read all partitions and overwrite each other's
+    /// commits. Running more than one member per group is not supported.
+    pub group: String,
+    pub topics: Vec<String>,
+    /// Where to start when group has no committed offset for a partition
+    pub fallback_offset: FetchOffset,
+    /// How often offsets of processed records are committed
+    pub commit_interval: Duration,
+    /// Number of records that can be waiting for decoding
+    pub queue_size: usize,
+    /// Number of consumed records that can wait to become committable before fetching pauses
+    pub max_in_flight: usize,
+    /// Retry of connecting and fetching before input fails
+    pub backoff: Backoff,
+}
+
+impl Default for KafkaInputConfig {
+    fn default() -> KafkaInputConfig {
+        KafkaInputConfig {
+            hosts: vec!["localhost:9092".to_owned()],
+            client_id: "logslash".to_owned(),
+            group: "logslash".to_owned(),
+            topics: Vec::new(),
+            fallback_offset: FetchOffset::Earliest,
+            commit_interval: Duration::from_secs(1),
+            queue_size: 1000,
+            max_in_flight: 10000,
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+struct KafkaRecord {
+    position: KafkaPosition,
+    value: Vec<u8>,
+}
+
+/// Record was passed downstream, spooled or rejected and spooled
+type Processed = KafkaPosition;
+
+/// Records of a partition that were consumed but not yet committable, by offset
+#[derive(Default)]
+struct PartitionOffsets {
+    /// Whether record was processed
+    in_flight: BTreeMap<i64, bool>,
+    /// Highest offset up to which all records were processed that was not committed yet
+    committable: Option<i64>,
+}
+
+impl PartitionOffsets {
+    fn consumed(&mut self, offset: i64) {
+        self.in_flight.insert(offset, false);
+    }
+
+    fn processed(&mut self, offset: i64) {
+        if let Some(processed) = self.in_flight.get_mut(&offset) {
+            *processed = true;
+        }
+
+        loop {
+            let first = match self.in_flight.iter().next() {
+                Some((&offset, &true)) => offset,
+                _ => return
+            };
+            self.in_flight.remove(&first);
+            self.committable = Some(first);
+        }
+    }
+
+    /// Highest offset up to which all records were processed; taken once
+    fn committable(&mut self) -> Option<i64> {
+        self.committable.take()
+    }
+
+    /// Records that are not committable yet
+    fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+type Partitions = HashMap<(String, i32), PartitionOffsets>;
+
+fn in_flight(partitions: &Partitions) -> usize {
+    partitions.values().map(PartitionOffsets::in_flight).sum()
+}
+
+fn kafka_error<E: ToString>(err: E) -> IoError {
+    IoError::new(IoErrorKind::Other, err.to_string())
+}
+
+/// Consumer group member that fetches records and commits offsets
+trait Consume {
+    fn poll(&mut self) -> IoResult<Vec<KafkaRecord>>;
+    /// Commit offsets of given topic partitions for the group
+    fn commit(&mut self, offsets: &[(&str, i32, i64)]) -> IoResult<()>;
+}
+
+impl Consume for Consumer {
+    fn poll(&mut self) -> IoResult<Vec<KafkaRecord>> {
+        let message_sets = Consumer::poll(self).map_err(kafka_error)?;
+
+        let mut records = Vec::new();
+        for message_set in message_sets.iter() {
+            for message in message_set.messages() {
+                records.push(KafkaRecord {
+                    position: KafkaPosition {
+                        topic: message_set.topic().to_owned(),
+                        partition: message_set.partition(),
+                        offset: message.offset,
+                    },
+                    value: message.value.to_vec(),
+                });
+            }
+        }
+        Ok(records)
+    }
+
+    fn commit(&mut self, offsets: &[(&str, i32, i64)]) -> IoResult<()> {
+        for &(topic, partition, offset) in offsets {
+            self.consume_message(topic, partition, offset).map_err(kafka_error)?;
+        }
+        self.commit_consumed().map_err(kafka_error)
+    }
+}
+
+fn connect(config: &KafkaInputConfig) -> IoResult<Consumer> {
+    let mut builder = Consumer::from_hosts(config.hosts.clone())
+        .with_client_id(config.client_id.clone())
+        .with_group(config.group.clone())
+        .with_fallback_offset(config.fallback_offset)
+        .with_offset_storage(GroupOffsetStorage::Kafka);
+    for topic in config.topics.iter() {
+        builder = builder.with_topic(topic.clone());
+    }
+    builder.create().map_err(kafka_error)
+}
+
+fn commit<C: Consume>(consumer: &mut C, partitions: &mut Partitions) -> IoResult<()> {
+    let offsets: Vec<_> = partitions.iter_mut()
+        .filter_map(|(&(ref topic, partition), offsets)| offsets.committable().map(|offset| (topic.as_str(), partition, offset)))
+        .collect();
+    if offsets.is_empty() {
+        return Ok(())
+    }
+    consumer.commit(&offsets)
+}
+
+fn track(partitions: &mut Partitions, done: Processed) {
+    if let Some(offsets) = partitions.get_mut(&(done.topic, done.partition)) {
+        offsets.processed(done.offset);
+    }
+}
+
+/// Commit offsets of processed records if commit_interval has passed since last commit
+fn commit_due<C: Consume>(logger: &Logger, config: &KafkaInputConfig, consumer: &mut C, partitions: &mut Partitions, last_commit: &mut Instant) {
+    if last_commit.elapsed() >= config.commit_interval {
+        if let Err(err) = commit(consumer, partitions) {
+            error!(logger, "Failed to commit Kafka offsets: {}", err);
+        }
+        *last_commit = Instant::now();
+    }
+}
+
+/// Fetch records until stopped committing offsets of processed ones periodically; input stream
+/// ends when records sender is dropped
+fn fetch<C: Consume>(logger: &Logger, config: &KafkaInputConfig, stopped: &AtomicBool, consumer: &mut C, partitions: &mut Partitions, processed: &std_mpsc::Receiver<Processed>, mut records: mpsc::Sender<Result<KafkaRecord, IoError>>) {
+    let mut last_commit = Instant::now();
+
+    while !stopped.load(Ordering::SeqCst) {
+        let fetched = match config.backoff.retry_blocking(logger, || consumer.poll()) {
+            Ok(fetched) => fetched,
+            Err(err) => {
+                error!(logger, "Failed to fetch records from Kafka; giving up: {}", err);
+                let _ = records.send(Err(err)).wait();
+                return
+            }
+        };
+
+        for record in fetched {
+            // backpressure: don't fetch more while too many records wait to be processed
+            while in_flight(partitions) >= config.max_in_flight {
+                if stopped.load(Ordering::SeqCst) {
+                    return
+                }
+                match processed.recv_timeout(config.commit_interval) {
+                    Ok(done) => track(partitions, done),
+                    Err(std_mpsc::RecvTimeoutError::Timeout) => (),
+                    // input stream was dropped
+                    Err(std_mpsc::RecvTimeoutError::Disconnected) => return
+                }
+                commit_due(logger, config, consumer, partitions, &mut last_commit);
+            }
+
+            partitions.entry((record.position.topic.clone(), record.position.partition)).or_insert_with(PartitionOffsets::default).consumed(record.position.offset);
+
+            records = match records.send(Ok(record)).wait() {
+                Ok(records) => records,
+                // input stream was dropped
+                Err(_) => return
+            };
+        }
+
+        while let Ok(done) = processed.try_recv() {
+            track(partitions, done);
+        }
+
+        commit_due(logger, config, consumer, partitions, &mut last_commit);
+    }
+}
+
+/// Runs in its own thread until stopped and records in flight were processed
+fn consume<C: Consume, F>(logger: &Logger, config: &KafkaInputConfig, stopped: &AtomicBool, connect: F, records: mpsc::Sender<Result<KafkaRecord, IoError>>, processed: std_mpsc::Receiver<Processed>) where F: Fn(&KafkaInputConfig) -> IoResult<C> {
+    let mut consumer = match config.backoff.retry_blocking(logger, || connect(config)) {
+        Ok(consumer) => consumer,
+        Err(err) => {
+            error!(logger, "Failed to connect to Kafka; giving up: {}", err);
+            let _ = records.send(Err(err)).wait();
+            return
+        }
+    };
+    info!(logger, "Connected to Kafka");
+
+    let mut partitions = HashMap::new();
+    fetch(logger, config, stopped, &mut consumer, &mut partitions, &processed, records);
+
+    // give records in flight a chance to get through the pipeline
+    while in_flight(&partitions) > 0 {
+        match processed.recv_timeout(config.commit_interval) {
+            Ok(done) => track(&mut partitions, done),
+            Err(_) => break
+        }
+    }
+
+    if let Err(err) = commit(&mut consumer, &mut partitions) {
+        error!(logger, "Failed to commit Kafka offsets: {}", err);
+    }
+    info!(logger, "Kafka consumer done");
+}
+
+/// Consume records of Kafka topics as consumer group member; each record value is parsed as
+/// exactly one message
+///
+/// Offset of the record is committed for the group only after the event got acknowledged
+/// downstream (see output::write::write_threaded_acked) and all records before it in the partition were
+/// committable too; records that failed to parse and records whose event was dropped without
+/// acknowledgment are stored in dead letter spool and committed. Fetching pauses while
+/// max_in_flight records are not committable.
+/// Consumption stops on shutdown and main should wait for final commit with wait_drained.
+/// Only one member per group is supported (see KafkaInputConfig::group).
+pub fn kafka_input<T, P, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, config: KafkaInputConfig, parser: P) -> Box<Stream<Item=Acked<Received<T, KafkaPosition>>, Error=PipeError<(), OE>>> where T: Debug + 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> + 'static, OE: 'static {
+    consumer_input(logger, name, handle, shutdown, dead_letters, config, parser, connect)
+}
+
+fn consumer_input<T, P, OE, C, F>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, config: KafkaInputConfig, parser: P, connect: F) -> Box<Stream<Item=Acked<Received<T, KafkaPosition>>, Error=PipeError<(), OE>>> where T: Debug + 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> + 'static, OE: 'static, C: Consume, F: Fn(&KafkaInputConfig) -> IoResult<C> + Send + 'static {
+    let logger = logger.new(o!("input" => name, "topics" => config.topics.join(",")));
+    let (records_sender, records) = mpsc::channel(config.queue_size);
+    let (processed_sender, processed) = std_mpsc::channel();
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    let consumer_stopped = stopped.clone();
+    handle.spawn(shutdown.signal().map(move |_| stopped.store(true, Ordering::SeqCst)));
+
+    let consumer_logger = logger.clone();
+    shutdown.spawn(name, move || consume(&consumer_logger, &config, &consumer_stopped, connect, records_sender, processed))
+        .expect("failed to spawn thread for kafka_input");
+
+    let err_logger = logger.clone();
+    let dead_letters = dead_letters.clone();
+    Box::new(records
+        .then(move |record| match record {
+            Ok(Ok(record)) => Ok(record),
+            Ok(Err(err)) => {
+                error!(&err_logger, "Kafka consumer failed: {}", err);
+                Err(PipeError::Input(()))
+            }
+            Err(()) => Err(PipeError::Input(()))
+        })
+        .filter_map(move |record| {
+            let KafkaRecord { position, value } = record;
+
+            let parsed = match parser(&value) {
+                IResult::Done(_, event) => Ok(event),
+                IResult::Error(ErrorKind::Custom(err)) => Err(err),
+                IResult::Error(_) | IResult::Incomplete(_) => Err(ParseError::new("input", "Failed to apply parser to record", &value, 0)),
+            };
+
+            let failure = match parsed {
+                Ok(event) => {
+                    let (acked, ack) = Acked::new(Received {
+                        event: event,
+                        origin: position.clone(),
+                    });
+
+                    let processed = processed_sender.clone();
+                    let logger = logger.clone();
+                    let dead_letters = dead_letters.clone();
+                    handle.spawn(ack.then(move |result| {
+                        if result.is_err() {
+                            // don't let single record hold back commits of the partition forever
+                            dead_letters.store_rejected(&logger, name, None, &value, "Event was dropped without acknowledgment");
+                        }
+                        let _ = processed.send(position);
+                        Ok::<(), ()>(())
+                    }));
+                    return Some(acked)
+                }
+                Err(failure) => failure,
+            };
+
+            dead_letters.store(&logger, name, None, &value, &failure);
+            let _ = processed_sender.send(position);
+            None
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+    use tempfile::TempDir;
+    use dead_letter::{Spool, SpoolConfig, read_segment};
+    use {event_loop, null_logger};
+
+    #[test]
+    fn commit_processed_in_order() {
+        let mut offsets = PartitionOffsets::default();
+        for offset in 10..15 {
+            offsets.consumed(offset);
+        }
+
+        offsets.processed(11);
+        assert_eq!(offsets.committable(), None);
+
+        offsets.processed(10);
+        offsets.processed(13);
+        assert_eq!(offsets.committable(), Some(11));
+        assert_eq!(offsets.committable(), None);
+        assert_eq!(offsets.in_flight(), 3);
+
+        // pending record holds back the rest of the partition
+        offsets.processed(14);
+        assert_eq!(offsets.committable(), None);
+        offsets.processed(12);
+        assert_eq!(offsets.committable(), Some(14));
+        assert_eq!(offsets.in_flight(), 0);
+    }
+
+    // returns given records with first poll and records commits
+    #[derive(Clone)]
+    struct MockConsumer {
+        records: Arc<Mutex<Vec<KafkaRecord>>>,
+        commits: Arc<Mutex<Vec<(String, i32, i64)>>>,
+    }
+
+    impl Consume for MockConsumer {
+        fn poll(&mut self) -> IoResult<Vec<KafkaRecord>> {
+            thread::sleep(Duration::from_millis(1));
+            Ok(self.records.lock().unwrap().drain(..).collect())
+        }
+
+        fn commit(&mut self, offsets: &[(&str, i32, i64)]) -> IoResult<()> {
+            self.commits.lock().unwrap().extend(offsets.iter().map(|&(topic, partition, offset)| (topic.to_owned(), partition, offset)));
+            Ok(())
+        }
+    }
+
+    fn whole(input: &[u8]) -> IResult<&[u8], Vec<u8>, ParseError> {
+        IResult::Done(&input[input.len()..], input.to_vec())
+    }
+
+    #[test]
+    fn commit_after_dropped_event() {
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+        let shutdown = Shutdown::new();
+        let dir = TempDir::new().unwrap();
+        let dead_letters = DeadLetters::new(Spool::open(dir.path(), SpoolConfig::default()).unwrap());
+
+        let consumer = MockConsumer {
+            records: Arc::new(Mutex::new((0..3).map(|offset| KafkaRecord {
+                position: KafkaPosition {
+                    topic: "test".to_owned(),
+                    partition: 0,
+                    offset: offset,
+                },
+                value: format!("record {}", offset).into_bytes(),
+            }).collect())),
+            commits: Arc::default(),
+        };
+        let commits = consumer.commits.clone();
+        let config = KafkaInputConfig {
+            commit_interval: Duration::from_millis(1),
+            max_in_flight: 2,
+            .. KafkaInputConfig::default()
+        };
+
+        let input = consumer_input::<_, _, (), _, _>(&null_logger(), "kafka", handle, &shutdown, &dead_letters, config, whole, move |_: &KafkaInputConfig| Ok(consumer.clone()));
+
+        let (first, input) = event_loop.run(input.into_future()).map_err(|_| ()).unwrap();
+        let (second, input) = event_loop.run(input.into_future()).map_err(|_| ()).unwrap();
+        first.unwrap().ack();
+        drop(second.unwrap());
+
+        // third record is held back until one of the first two is processed
+        let (third, _input) = event_loop.run(input.into_future()).map_err(|_| ()).unwrap();
+        assert_eq!(third.unwrap().ack().event, b"record 2".to_vec());
+
+        for _ in 0..100 {
+            if commits.lock().unwrap().last() == Some(&("test".to_owned(), 0, 2)) {
+                break
+            }
+            event_loop.turn(Some(Duration::from_millis(10)));
+        }
+        assert_eq!(commits.lock().unwrap().last(), Some(&("test".to_owned(), 0, 2)));
+
+        let letters = read_segment(&Spool::open(dir.path(), SpoolConfig::default()).unwrap().segments()[0]).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].data, b"record 1".to_vec());
+
+        shutdown.trigger();
+        event_loop.turn(Some(Duration::from_millis(10)));
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+    }
+}
@@ -1,3 +1,10 @@
 pub mod tcp;
+pub mod udp;
+pub mod tls;
+pub mod relp;
+pub mod file;
+#[cfg(target_os = "linux")]
+pub mod unix;
 pub mod nom;
 pub mod syslog;
+pub mod kafka;
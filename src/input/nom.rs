@@ -6,10 +6,18 @@ use futures::stream::Stream;
 use tokio_core::reactor::Handle;
 
 use PipeError;
-use codec::nom::{NomCodec, NomParser};
+use shutdown::Shutdown;
+use dead_letter::DeadLetters;
+use codec::nom::{NomCodec, NomParser, Resync};
 
 use input::tcp::tcp_input;
 
-pub fn tcp_nom_input<T, OE>(logger: &Logger, name: &'static str, handle: Handle, addr: &SocketAddr, parser: NomParser<T>) -> Box<Stream<Item=T, Error=PipeError<(), OE>>> where T: Debug + 'static {
-    tcp_input(logger, name, handle, addr, NomCodec::new(parser))
+pub fn tcp_nom_input<T, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, parser: NomParser<T>) -> Box<Stream<Item=T, Error=PipeError<(), OE>>> where T: Debug + 'static {
+    tcp_input(logger, name, handle, shutdown, dead_letters, addr, NomCodec::new(parser))
+}
+
+/// Like tcp_nom_input but frames that failed to parse are skipped (and spooled) until more than
+/// max_errors of them were received on the connection
+pub fn tcp_nom_resyncing_input<T, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, parser: NomParser<T>, resync: Resync, max_errors: usize) -> Box<Stream<Item=T, Error=PipeError<(), OE>>> where T: Debug + 'static {
+    tcp_input(logger, name, handle, shutdown, dead_letters, addr, NomCodec::resyncing(parser, resync, max_errors))
 }
@@ -0,0 +1,220 @@
+use std::net::SocketAddr;
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::io::Error as IoError;
+
+use slog::Logger;
+
+use futures::{Future, Sink};
+use futures::future::{ok, Either};
+use futures::stream::Stream;
+use futures::sync::mpsc;
+
+use tokio_core::io::Io;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+
+use nom::{IResult, ErrorKind};
+
+use PipeError;
+use shutdown::Shutdown;
+use ack::Acked;
+use dead_letter::DeadLetters;
+use codec::error::ParseError;
+use codec::relp::{RelpCodec, RelpFrame, RelpResponse};
+use input::tcp::tcp_accept;
+
+/// Number of transactions processed concurrently per connection (librelp default window size)
+const RELP_WINDOW: usize = 128;
+
+fn syslog_command<T, P>(logger: &Logger, name: &str, dead_letters: &DeadLetters, remote_addr: Option<&str>, sender: &mpsc::Sender<Acked<T>>, parser: &P, frame: RelpFrame) -> Box<Future<Item=RelpResponse, Error=IoError>> where T: 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> {
+    let txnr = frame.txnr;
+
+    let event = match parser(&frame.data) {
+        IResult::Done(_, event) => event,
+        IResult::Error(ErrorKind::Custom(err)) => {
+            dead_letters.store(logger, name, remote_addr, &frame.data, &err);
+            return Box::new(ok(RelpResponse::status(txnr, 500, err.message)))
+        }
+        IResult::Error(_) | IResult::Incomplete(_) => {
+            dead_letters.store(logger, name, remote_addr, &frame.data, &ParseError::new("input", "Failed to apply parser to RELP syslog message", &frame.data, 0));
+            return Box::new(ok(RelpResponse::status(txnr, 500, "Failed to parse message")))
+        }
+    };
+
+    let (event, acked) = Acked::new(event);
+    let logger = logger.clone();
+
+    // respond only after event was accepted by the output
+    Box::new(sender.clone().send(event).then(move |sent| match sent {
+        Ok(_) => Either::A(acked.then(move |acked| ok(match acked {
+            Ok(()) => RelpResponse::ok(txnr),
+            Err(_) => {
+                warn!(&logger, "RELP syslog message was not accepted by output"; "txnr" => txnr);
+                RelpResponse::status(txnr, 500, "Message not accepted")
+            }
+        }))),
+        Err(_) => Either::B(ok(RelpResponse::status(txnr, 500, "Pipeline closed")))
+    }))
+}
+
+/// Reliable Event Logging Protocol server; each received event needs to be acknowledged
+/// downstream (see output::write::write_threaded_acked) before RELP response is sent to the client
+pub fn relp_input<T, P, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, parser: P) -> Box<Stream<Item=Acked<T>, Error=PipeError<(), OE>>> where T: Debug + 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> + 'static {
+    let logger = logger.new(o!("input" => name));
+    let listener = TcpListener::bind(addr, &handle).expect("bound TCP socket");
+    info!(&logger, "Listening for RELP connections"; "bound" => format!("{}", addr));
+
+    listener_input(logger, name, handle, shutdown, dead_letters, listener, parser)
+}
+
+fn listener_input<T, P, OE>(logger: Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, listener: TcpListener, parser: P) -> Box<Stream<Item=Acked<T>, Error=PipeError<(), OE>>> where T: Debug + 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> + 'static {
+    let (sender, receiver) = mpsc::channel(10);
+    let parser = Rc::new(parser);
+    let dead_letters = dead_letters.clone();
+
+    tcp_accept(&logger, handle, shutdown, listener, move |tcp_stream, conn_logger| {
+        let remote_addr = tcp_stream.peer_addr().ok().map(|addr| format!("{}", addr));
+        let dead_letters = dead_letters.clone();
+        let (responses, frames) = tcp_stream.framed(RelpCodec).split();
+        let sender = sender.clone();
+        let parser = parser.clone();
+        let frame_logger = conn_logger.clone();
+        let conn_err_logger = conn_logger.clone();
+
+        Box::new(frames
+            .map(move |frame| -> Box<Future<Item=RelpResponse, Error=IoError>> {
+                debug!(&frame_logger, "Received RELP command"; "command" => frame.command.clone(), "txnr" => frame.txnr);
+                match frame.command.as_str() {
+                    "open" => Box::new(ok(RelpResponse::open(frame.txnr))),
+                    "syslog" => syslog_command(&frame_logger, name, &dead_letters, remote_addr.as_ref().map(|addr| addr.as_str()), &sender, &*parser, frame),
+                    "close" => Box::new(ok(RelpResponse::close(frame.txnr))),
+                    _ => Box::new(ok(RelpResponse::status(frame.txnr, 500, "Unsupported command")))
+                }
+            })
+            .buffered(RELP_WINDOW)
+            .forward(responses)
+            .map_err(move |err| {
+                error!(&conn_err_logger, "Error while processing RELP session: {}", err);
+                ()})
+            .map(move |_| {
+                info!(&conn_logger, "RELP session closed by remote");
+                ()}))
+    });
+
+    Box::new(receiver.map_err(|_| PipeError::Input(())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Write, BufRead, BufReader};
+    use std::io::Result as IoResult;
+    use std::io::ErrorKind as IoErrorKind;
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use futures::sync::oneshot;
+    use {event_loop, null_logger};
+    use codec::syslog::{SyslogEvent, OwnedSyslogEvent, parse_errors};
+    use output::write::{Durable, write_threaded_acked};
+    use output::retry::Backoff;
+    use dead_letter::{Spool, SpoolConfig, read_segment};
+    use tempfile::TempDir;
+
+    #[derive(Clone)]
+    struct Out {
+        data: Arc<Mutex<Vec<u8>>>,
+        broken: bool,
+    }
+
+    impl Write for Out {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            if self.broken {
+                return Err(IoError::new(IoErrorKind::BrokenPipe, "broken"))
+            }
+            self.data.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Durable for Out {
+        fn sync(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    fn syslog_rfc5424(input: &[u8]) -> IResult<&[u8], OwnedSyslogEvent, ParseError> {
+        parse_errors::syslog_rfc5424(input).map(SyslogEvent::into_owned)
+    }
+
+    // send syslog command and return response line together with what was written to the output
+    // when response was received
+    fn relay(out: Out, dead_letters: &DeadLetters, command: &'static [u8]) -> (String, Vec<u8>) {
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+        let shutdown = Shutdown::new();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let input = listener_input::<_, _, ()>(null_logger(), "syslog", handle.clone(), &shutdown, dead_letters, listener, syslog_rfc5424);
+        let backoff = Backoff { max_retries: Some(0), .. Backoff::default() };
+        let output = write_threaded_acked(&null_logger(), "test", &shutdown, out.clone(), backoff, |event: &OwnedSyslogEvent, buf: &mut Vec<u8>| -> Result<(), IoError> {
+            buf.extend_from_slice(event.msg_id.as_ref().unwrap().as_bytes());
+            Ok(())
+        });
+        handle.spawn(input.forward(output).map(|_| ()).map_err(|_| ()));
+
+        let (response_sender, response) = oneshot::channel();
+        thread::spawn(move || {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            client.write_all(command).unwrap();
+
+            let mut line = String::new();
+            BufReader::new(client).read_line(&mut line).unwrap();
+            let written = out.data.lock().unwrap().clone();
+            response_sender.complete((line, written));
+        });
+
+        event_loop.run(response).unwrap()
+    }
+
+    const SYSLOG: &'static [u8] = b"2 syslog 75 <165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n";
+
+    #[test]
+    fn respond_after_written() {
+        let (response, written) = relay(Out { data: Arc::default(), broken: false }, &DeadLetters::disabled(), SYSLOG);
+        assert_eq!(response, "2 rsp 6 200 OK\n");
+        assert_eq!(written, b"ID47".to_vec());
+    }
+
+    #[test]
+    fn no_ack_when_output_failed() {
+        let (response, written) = relay(Out { data: Arc::default(), broken: true }, &DeadLetters::disabled(), SYSLOG);
+        assert_eq!(response, "2 rsp 24 500 Message not accepted\n");
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn dead_letter_failed_message() {
+        let dir = TempDir::new().unwrap();
+        let dead_letters = DeadLetters::new(Spool::open(dir.path(), SpoolConfig::default()).unwrap());
+        let (response, written) = relay(Out { data: Arc::default(), broken: false }, &dead_letters, b"2 syslog 3 foo\n");
+        assert!(response.starts_with("2 rsp "));
+        assert!(response.contains(" 500 "));
+        assert!(written.is_empty());
+
+        let spool = Spool::open(dir.path(), SpoolConfig::default()).unwrap();
+        let letters = read_segment(&spool.segments()[0]).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].input, "syslog");
+        assert_eq!(letters[0].data, b"foo".to_vec());
+        assert!(letters[0].remote_addr.as_ref().unwrap().starts_with("127.0.0.1:"));
+    }
+}
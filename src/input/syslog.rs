@@ -1,15 +1,87 @@
 use std::net::SocketAddr;
+use std::borrow::Cow;
+#[cfg(target_os = "linux")]
+use std::path::Path;
 
 use slog::Logger;
+use nom::IResult;
 use futures::stream::Stream;
 use tokio_core::reactor::Handle;
 
 use PipeError;
-use codec::syslog::SyslogCodec;
-pub use codec::syslog::SyslogEvent;
+use shutdown::Shutdown;
+use dead_letter::DeadLetters;
+use event::Received;
+use ack::Acked;
+use codec::error::ParseError;
+use codec::syslog::{SyslogCodec, Rfc3164Config, parse_errors};
+pub use codec::syslog::{SyslogEvent, OwnedSyslogEvent, IdStrategy};
 
 use input::tcp::tcp_input;
+use input::udp::{udp_input, Peer, DatagramStats};
+use input::tls::{tls_input, TlsConfig, TlsPeer};
+use input::relp::relp_input;
+use input::kafka::{kafka_input, KafkaInputConfig, KafkaPosition};
+#[cfg(target_os = "linux")]
+use input::unix::{unix_stream_input, unix_datagram_input, local_hostname, UnixPeer};
 
-pub fn tcp_syslog_input<OE>(logger: &Logger, handle: Handle, addr: &SocketAddr) -> Box<Stream<Item=SyslogEvent, Error=PipeError<(), OE>>> {
-    tcp_input(logger, "syslog", handle, addr, SyslogCodec::rfc5424_in_newline_frame())
+// datagram, RELP and Kafka inputs need events that outlive the received data; each input is
+// single unframed message
+fn syslog_rfc5424(input: &[u8], id_strategy: IdStrategy) -> IResult<&[u8], OwnedSyslogEvent, ParseError> {
+    parse_errors::syslog_rfc5424(input).map(|event| event.identified(id_strategy, input).into_owned())
+}
+
+fn syslog_auto<'a>(input: &'a [u8], config: &Rfc3164Config, id_strategy: IdStrategy) -> IResult<&'a [u8], OwnedSyslogEvent, ParseError> {
+    parse_errors::syslog_auto(input, config).map(|event| event.identified(id_strategy, input).into_owned())
+}
+
+pub fn tcp_syslog_input<OE>(logger: &Logger, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, codec: SyslogCodec) -> Box<Stream<Item=OwnedSyslogEvent, Error=PipeError<(), OE>>> {
+    tcp_input(logger, "syslog", handle, shutdown, dead_letters, addr, codec)
+}
+
+/// RFC 5426 - each datagram carries single RFC 5424 message without framing
+pub fn udp_syslog_input<OE>(logger: &Logger, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, max_message_size: usize, id_strategy: IdStrategy, stats: DatagramStats) -> Box<Stream<Item=Received<OwnedSyslogEvent, Peer>, Error=PipeError<(), OE>>> where OE: 'static {
+    udp_input(logger, "syslog", handle, shutdown, dead_letters, addr, max_message_size, move |input| syslog_rfc5424(input, id_strategy), stats)
+}
+
+/// RFC 5425 - syslog over TLS with octet-counting framing; use SyslogCodec::auto() to also accept
+/// new line framing
+pub fn tls_syslog_input<OE>(logger: &Logger, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, config: &TlsConfig, codec: SyslogCodec) -> Box<Stream<Item=Received<OwnedSyslogEvent, TlsPeer>, Error=PipeError<(), OE>>> {
+    tls_input(logger, "syslog", handle, shutdown, dead_letters, addr, config, codec)
+}
+
+/// RELP with RFC 5424 or RFC 3164 messages; events need to be acknowledged downstream
+pub fn relp_syslog_input<OE>(logger: &Logger, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, config: Rfc3164Config, id_strategy: IdStrategy) -> Box<Stream<Item=Acked<OwnedSyslogEvent>, Error=PipeError<(), OE>>> {
+    relp_input(logger, "syslog", handle, shutdown, dead_letters, addr, move |input| syslog_auto(input, &config, id_strategy))
+}
+
+/// Kafka records each carrying single RFC 5424 or RFC 3164 message; events need to be
+/// acknowledged downstream
+pub fn kafka_syslog_input<OE>(logger: &Logger, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, config: KafkaInputConfig, rfc3164: Rfc3164Config, id_strategy: IdStrategy) -> Box<Stream<Item=Acked<Received<OwnedSyslogEvent, KafkaPosition>>, Error=PipeError<(), OE>>> where OE: 'static {
+    kafka_input(logger, "syslog", handle, shutdown, dead_letters, config, move |input| syslog_auto(input, &rfc3164, id_strategy))
+}
+
+#[cfg(target_os = "linux")]
+fn fill_in_hostname(hostname: String) -> Box<Fn(Received<OwnedSyslogEvent, UnixPeer>) -> Received<OwnedSyslogEvent, UnixPeer>> {
+    Box::new(move |mut received| {
+        if received.event.hostname.is_empty() {
+            received.event.hostname = Cow::Owned(hostname.clone());
+        }
+        received
+    })
+}
+
+/// Local syslog stream socket; messages without hostname get local host name
+#[cfg(target_os = "linux")]
+pub fn unix_stream_syslog_input<OE>(logger: &Logger, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, path: &Path, codec: SyslogCodec) -> Box<Stream<Item=Received<OwnedSyslogEvent, UnixPeer>, Error=PipeError<(), OE>>> where OE: 'static {
+    let fill_in_hostname = fill_in_hostname(local_hostname().expect("local host name"));
+    Box::new(unix_stream_input(logger, "syslog", handle, shutdown, dead_letters, path, codec).map(move |received| fill_in_hostname(received)))
+}
+
+/// Local syslog datagram socket like /dev/log; messages without hostname get local host name
+#[cfg(target_os = "linux")]
+pub fn unix_datagram_syslog_input<OE>(logger: &Logger, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, path: &Path, max_message_size: usize, config: Rfc3164Config, id_strategy: IdStrategy) -> Box<Stream<Item=Received<OwnedSyslogEvent, UnixPeer>, Error=PipeError<(), OE>>> where OE: 'static {
+    let fill_in_hostname = fill_in_hostname(local_hostname().expect("local host name"));
+    Box::new(unix_datagram_input(logger, "syslog", handle, shutdown, dead_letters, path, max_message_size, move |input| syslog_auto(input, &config, id_strategy))
+        .map(move |received| fill_in_hostname(received)))
 }
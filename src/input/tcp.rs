@@ -14,10 +14,12 @@ use futures::Sink;
 use futures::future;
 
 use tokio_core::io::{Io, Codec};
-use tokio_core::net::TcpListener;
+use tokio_core::net::{TcpListener, TcpStream};
 use tokio_core::reactor::Handle;
 
 use PipeError;
+use shutdown::{Shutdown, until_shutdown};
+use dead_letter::{DeadLetters, spool_failed};
 
 #[derive(Debug)]
 enum TcpInputError<T: Debug> {
@@ -60,42 +62,72 @@ impl<T: Debug> Error for TcpInputError<T> {
     }
 }
 
-pub fn tcp_input<C, T, OE>(logger: &Logger, name: &'static str, handle: Handle, addr: &SocketAddr, codec: C) -> Box<Stream<Item=T, Error=PipeError<(), OE>>> where C: Codec<In=T, Out=()> + Clone + 'static, T: Debug + 'static {
-    let logger = logger.new(o!("input" => name));
-    let (sender, receiver) = mpsc::channel(10);
-    let listener_handle = handle.clone();
-
+/// Bind TCP listener and call connection for each accepted connection spawning returned future
+/// on the event loop; on shutdown listener is closed and connections stop reading
+pub fn tcp_listen<F>(logger: &Logger, handle: Handle, shutdown: &Shutdown, addr: &SocketAddr, connection: F) where F: FnMut(TcpStream, Logger) -> Box<Future<Item=(), Error=()>> + 'static {
     let listener = TcpListener::bind(addr, &handle).expect("bound TCP socket");
-    info!(&logger, "Listening for TCP connections"; "bound" => format!("{}", addr));
+    info!(logger, "Listening for TCP connections"; "bound" => format!("{}", addr));
+
+    tcp_accept(logger, handle, shutdown, listener, connection)
+}
+
+/// Like tcp_listen but using already bound listener
+pub fn tcp_accept<F>(logger: &Logger, handle: Handle, shutdown: &Shutdown, listener: TcpListener, mut connection: F) where F: FnMut(TcpStream, Logger) -> Box<Future<Item=(), Error=()>> + 'static {
+    let listener_handle = handle.clone();
 
     let incoming_logger = logger.clone();
+    let logger = logger.clone();
+    let done_logger = logger.clone();
+    let shutdown = shutdown.clone();
     listener_handle.spawn(
-        listener
-        .incoming()
+        until_shutdown(&shutdown.clone(), listener.incoming())
         .for_each(move |(tcp_stream, remote_addr)| {
             let id = tcp_stream.as_raw_fd();
             let conn_logger = incoming_logger.new(o!("connection" => id, "remote" => format!("{}", remote_addr)));
             info!(&conn_logger, "Accepted TCP connection");
 
-            let conn_err_logger = conn_logger.clone();
-            let connection = sender.clone()
-                .with(|message| {
-                    future::ok::<T, TcpInputError<T>>(message)
-                })
-                .send_all(tcp_stream.framed(codec.clone()))
-                .map_err(move |err| {
-                    error!(&conn_err_logger, "Error while decoding input: {:?}", err);
-                    ()})
-                .map(move |(_sink, _stream)| {
-                    info!(&conn_logger, "TCP connection closed by remote");
-                    ()});
-
-            handle.spawn(connection);
+            let connection = connection(tcp_stream, conn_logger.clone());
+            if let Err(err) = shutdown.spawn_connection(&handle, id, connection) {
+                error!(&conn_logger, "Connection will not stop on shutdown: {}", err);
+            }
             Ok(())
         })
+        .map(move |_| {
+            info!(&done_logger, "Stopped listening for TCP connections");
+            ()})
         .map_err(move |err| {
             error!(&logger, "Error processing incomming TCP connectionsi: {:?}", err);
             ()}));
+}
+
+/// Send decoded messages from connection down the pipeline until connection is closed
+pub fn forward_decoded<S, T>(logger: Logger, sender: mpsc::Sender<T>, messages: S) -> Box<Future<Item=(), Error=()>> where S: Stream<Item=T, Error=IoError> + 'static, T: Debug + 'static {
+    let conn_err_logger = logger.clone();
+    Box::new(sender
+        .with(|message| {
+            future::ok::<T, TcpInputError<T>>(message)
+        })
+        .send_all(messages)
+        .map_err(move |err| {
+            error!(&conn_err_logger, "Error while decoding input: {:?}", err);
+            ()})
+        .map(move |(_sink, _stream)| {
+            info!(&logger, "Connection closed by remote");
+            ()}))
+}
+
+/// Messages that failed to decode are stored in dead letter spool
+pub fn tcp_input<C, T, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, codec: C) -> Box<Stream<Item=T, Error=PipeError<(), OE>>> where C: Codec<In=T> + Clone + 'static, T: Debug + 'static {
+    let logger = logger.new(o!("input" => name));
+    let (sender, receiver) = mpsc::channel(10);
+
+    let dead_letters = dead_letters.clone();
+
+    tcp_listen(&logger, handle, shutdown, addr, move |tcp_stream, conn_logger| {
+        let remote_addr = tcp_stream.peer_addr().ok().map(|addr| format!("{}", addr));
+        let messages = spool_failed(conn_logger.clone(), dead_letters.clone(), name, remote_addr, tcp_stream.framed(codec.clone()));
+        forward_decoded(conn_logger, sender.clone(), messages)
+    });
 
     //TODO: provide error stream
     Box::new(receiver.map_err(|_| PipeError::Input(())))
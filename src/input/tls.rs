@@ -0,0 +1,266 @@
+use std::net::SocketAddr;
+use std::borrow::Cow;
+use std::fmt::{self, Debug, Display};
+use std::error::Error;
+use std::io::Error as IoError;
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+
+use slog::Logger;
+
+use futures::Future;
+use futures::stream::Stream;
+use futures::sync::mpsc;
+
+use tokio_core::io::{Io, Codec};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+
+use openssl::error::ErrorStack;
+use openssl::nid;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslMethod, SslAcceptor, SslAcceptorBuilder, SslRef, SSL_VERIFY_PEER, SSL_VERIFY_FAIL_IF_NO_PEER_CERT};
+use openssl::x509::{X509, X509NameRef};
+use tokio_openssl::SslAcceptorExt;
+
+use PipeError;
+use shutdown::Shutdown;
+use dead_letter::{DeadLetters, spool_failed};
+use event::{Origin, Received, MetaValue};
+use input::tcp::{tcp_accept, forward_decoded};
+
+#[derive(Debug)]
+pub enum TlsConfigError {
+    Io(IoError),
+    Ssl(ErrorStack),
+}
+
+impl From<IoError> for TlsConfigError {
+    fn from(error: IoError) -> TlsConfigError {
+        TlsConfigError::Io(error)
+    }
+}
+
+impl From<ErrorStack> for TlsConfigError {
+    fn from(error: ErrorStack) -> TlsConfigError {
+        TlsConfigError::Ssl(error)
+    }
+}
+
+impl Display for TlsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TlsConfigError::Io(ref error) => write!(f, "{}: {}", self.description(), error),
+            TlsConfigError::Ssl(ref error) => write!(f, "{}: {}", self.description(), error),
+        }
+    }
+}
+
+impl Error for TlsConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            TlsConfigError::Io(_) => "Failed to read TLS certificate or key",
+            TlsConfigError::Ssl(_) => "Failed to load TLS certificate or key",
+        }
+    }
+}
+
+/// Server certificate and key with optional CA used to verify client certificates
+pub struct TlsConfig {
+    pub cert: X509,
+    pub key: PKey,
+    /// When set clients have to present certificate signed by this CA
+    pub client_ca: Option<X509>,
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, IoError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+impl TlsConfig {
+    pub fn from_pem_files(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<TlsConfig, TlsConfigError> {
+        Ok(TlsConfig {
+            cert: X509::from_pem(&read_file(cert)?)?,
+            key: PKey::private_key_from_pem(&read_file(key)?)?,
+            client_ca: match client_ca {
+                Some(client_ca) => Some(X509::from_pem(&read_file(client_ca)?)?),
+                None => None
+            }
+        })
+    }
+
+    fn acceptor(&self) -> Result<SslAcceptor, ErrorStack> {
+        let mut builder = SslAcceptorBuilder::mozilla_intermediate(SslMethod::tls(), &self.key, &self.cert, Vec::<X509>::new())?;
+
+        if let Some(ref client_ca) = self.client_ca {
+            let context = builder.builder_mut();
+            context.set_verify(SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT);
+            context.cert_store_mut().add_cert(client_ca.clone())?;
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// Subject of verified client certificate
+#[derive(Debug, Clone)]
+pub struct TlsPeer {
+    pub subject: Option<String>,
+}
+
+fn subject(name: &X509NameRef) -> String {
+    [("CN", nid::COMMONNAME), ("OU", nid::ORGANIZATIONALUNITNAME), ("O", nid::ORGANIZATIONNAME)].iter()
+        .flat_map(|&(key, nid)| name.entries_by_nid(nid)
+                  .filter_map(|entry| entry.data().as_utf8().ok())
+                  .map(move |value| format!("{}={}", key, value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl TlsPeer {
+    fn from_ssl(ssl: &SslRef) -> TlsPeer {
+        TlsPeer {
+            // only trust certificates that passed verification
+            subject: if ssl.verify_result().is_none() {
+                ssl.peer_certificate().map(|cert| subject(cert.subject_name()))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl Origin for TlsPeer {
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(self.subject.iter().map(|subject| ("tls_peer_subject", MetaValue::String(Cow::Borrowed(subject.as_str())))))
+    }
+}
+
+/// Like tcp_input but connections need to establish TLS session first
+pub fn tls_input<C, T, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, config: &TlsConfig, codec: C) -> Box<Stream<Item=Received<T, TlsPeer>, Error=PipeError<(), OE>>> where C: Codec<In=T> + Clone + 'static, T: Debug + 'static {
+    let logger = logger.new(o!("input" => name));
+    let listener = TcpListener::bind(addr, &handle).expect("bound TCP socket");
+    info!(&logger, "Listening for TLS connections"; "bound" => format!("{}", addr));
+
+    listener_input(logger, name, handle, shutdown, dead_letters, listener, config, codec)
+}
+
+fn listener_input<C, T, OE>(logger: Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, listener: TcpListener, config: &TlsConfig, codec: C) -> Box<Stream<Item=Received<T, TlsPeer>, Error=PipeError<(), OE>>> where C: Codec<In=T> + Clone + 'static, T: Debug + 'static {
+    let (sender, receiver) = mpsc::channel(10);
+    let acceptor = config.acceptor().expect("TLS acceptor");
+    let dead_letters = dead_letters.clone();
+
+    tcp_accept(&logger, handle, shutdown, listener, move |tcp_stream, conn_logger| {
+        let codec = codec.clone();
+        let sender = sender.clone();
+        let dead_letters = dead_letters.clone();
+        let remote_addr = tcp_stream.peer_addr().ok().map(|addr| format!("{}", addr));
+        let handshake_logger = conn_logger.clone();
+
+        Box::new(acceptor.accept_async(tcp_stream)
+            .map_err(move |err| {
+                error!(&handshake_logger, "TLS handshake failed: {}", err);
+                ()})
+            .and_then(move |tls_stream| {
+                let peer = TlsPeer::from_ssl(tls_stream.get_ref().ssl());
+                let conn_logger = conn_logger.new(o!("peer" => format!("{}", peer.subject.as_ref().map(|s| s.as_str()).unwrap_or("-"))));
+                info!(&conn_logger, "TLS session established");
+
+                let messages = spool_failed(conn_logger.clone(), dead_letters, name, remote_addr, tls_stream.framed(codec));
+                forward_decoded(conn_logger, sender, messages.map(move |event| Received {
+                    event: event,
+                    origin: peer.clone()
+                }))
+            }))
+    });
+
+    Box::new(receiver.map_err(|_| PipeError::Input(())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use futures::stream::Stream;
+    use tokio_core::net::TcpStream;
+    use tokio_core::io::write_all;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslConnectorBuilder, SslMethod};
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use tokio_openssl::SslConnectorExt;
+
+    use {event_loop, null_logger};
+    use codec::syslog::SyslogCodec;
+
+    fn certificate(common_name: &str, issuer: Option<(&X509, &PKey)>) -> (X509, PKey) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut cert = X509Builder::new().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+
+        match issuer {
+            Some((issuer_cert, issuer_key)) => {
+                cert.set_issuer_name(issuer_cert.subject_name()).unwrap();
+                cert.sign(issuer_key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                cert.set_issuer_name(&name).unwrap();
+                cert.sign(&key, MessageDigest::sha256()).unwrap();
+            }
+        }
+
+        (cert.build(), key)
+    }
+
+    #[test]
+    fn client_certificate_subject() {
+        let (ca_cert, ca_key) = certificate("test CA", None);
+        let (server_cert, server_key) = certificate("localhost", Some((&ca_cert, &ca_key)));
+        let (client_cert, client_key) = certificate("client.example.com", Some((&ca_cert, &ca_key)));
+
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let config = TlsConfig {
+            cert: server_cert,
+            key: server_key,
+            client_ca: Some(ca_cert.clone()),
+        };
+        let input = listener_input::<_, _, ()>(null_logger(), "syslog", handle.clone(), &Shutdown::new(), &DeadLetters::disabled(), listener, &config, SyslogCodec::rfc5424_in_newline_frame());
+
+        let mut connector = SslConnectorBuilder::new(SslMethod::tls()).unwrap();
+        connector.builder_mut().set_certificate(&client_cert).unwrap();
+        connector.builder_mut().set_private_key(&client_key).unwrap();
+        connector.builder_mut().cert_store_mut().add_cert(ca_cert).unwrap();
+        let connector = connector.build();
+
+        let client = TcpStream::connect(&addr, &handle)
+            .and_then(move |stream| connector.connect_async("localhost", stream).map_err(|err| IoError::new(::std::io::ErrorKind::Other, err)))
+            .and_then(|stream| write_all(stream, &b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo\n"[..]))
+            .map_err(|err| panic!("client failed: {}", err));
+
+        handle.spawn(client.map(|_| ()));
+        let (event, _input) = event_loop.run(input.into_future()).map_err(|_| ()).unwrap();
+        let event = event.unwrap();
+
+        assert_eq!(event.event.hostname, "mymachine.example.com");
+        assert_eq!(event.origin.subject, Some("CN=client.example.com".to_owned()));
+    }
+}
@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+use std::borrow::Cow;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::fmt::Debug;
+use std::iter::once;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use slog::Logger;
+
+use futures::{Async, Poll};
+use futures::stream::Stream;
+
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::Handle;
+
+use nom::{IResult, ErrorKind};
+
+use PipeError;
+use shutdown::{Shutdown, until_shutdown};
+use dead_letter::DeadLetters;
+use codec::error::ParseError;
+use event::{Origin, Received, MetaValue};
+
+/// Counters of datagrams seen by udp_input
+#[derive(Debug, Clone, Default)]
+pub struct DatagramStats {
+    pub received: Arc<AtomicUsize>,
+    /// Datagrams that were bigger than maximum message size
+    pub oversized: Arc<AtomicUsize>,
+    /// Datagrams that ended before message was complete
+    pub truncated: Arc<AtomicUsize>,
+    /// Datagrams that failed to parse
+    pub failed: Arc<AtomicUsize>,
+}
+
+fn count(counter: &AtomicUsize) -> usize {
+    counter.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Address of the host that sent the datagram
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    addr_string: String,
+}
+
+impl Peer {
+    fn new(addr: SocketAddr) -> Peer {
+        Peer {
+            addr: addr,
+            addr_string: format!("{}", addr)
+        }
+    }
+}
+
+impl Origin for Peer {
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(once(("remote_addr", MetaValue::String(Cow::Borrowed(self.addr_string.as_str())))))
+    }
+}
+
+struct Datagrams {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+}
+
+impl Stream for Datagrams {
+    type Item = (Vec<u8>, SocketAddr);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, IoError> {
+        match self.socket.recv_from(&mut self.buf) {
+            Ok((len, addr)) => Ok(Async::Ready(Some((self.buf[..len].to_vec(), addr)))),
+            Err(ref err) if err.kind() == IoErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(err) => Err(err)
+        }
+    }
+}
+
+// senders often terminate datagrams with new line or NUL like they would do on stream
+fn trim_datagram(datagram: &[u8]) -> &[u8] {
+    let mut end = datagram.len();
+    while end > 0 && (datagram[end - 1] == b'\n' || datagram[end - 1] == b'\0') {
+        end -= 1;
+    }
+    &datagram[..end]
+}
+
+/// Each datagram is parsed as exactly one message; datagrams that failed to parse are stored in
+/// dead letter spool
+pub fn udp_input<T, P, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, addr: &SocketAddr, max_message_size: usize, parser: P, stats: DatagramStats) -> Box<Stream<Item=Received<T, Peer>, Error=PipeError<(), OE>>> where T: Debug + 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> + 'static, OE: 'static {
+    let logger = logger.new(o!("input" => name));
+
+    let socket = UdpSocket::bind(addr, &handle).expect("bound UDP socket");
+    info!(&logger, "Listening for UDP datagrams"; "bound" => format!("{}", addr), "max_message_size" => max_message_size);
+
+    socket_input(logger, name, shutdown, dead_letters, socket, max_message_size, parser, stats)
+}
+
+fn socket_input<T, P, OE>(logger: Logger, name: &'static str, shutdown: &Shutdown, dead_letters: &DeadLetters, socket: UdpSocket, max_message_size: usize, parser: P, stats: DatagramStats) -> Box<Stream<Item=Received<T, Peer>, Error=PipeError<(), OE>>> where T: Debug + 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> + 'static, OE: 'static {
+    let err_logger = logger.clone();
+    let dead_letters = dead_letters.clone();
+    Box::new(until_shutdown(shutdown, Datagrams {
+            socket: socket,
+            // one extra byte so we can tell when datagram did not fit
+            buf: vec![0; max_message_size + 1],
+        })
+        .filter_map(move |(datagram, remote_addr)| {
+            count(&stats.received);
+
+            if datagram.len() > max_message_size {
+                warn!(&logger, "Dropping oversized datagram"; "remote" => format!("{}", remote_addr), "oversized" => count(&stats.oversized));
+                return None
+            }
+
+            match parser(trim_datagram(&datagram)) {
+                IResult::Done(_, event) => Some(Received {
+                    event: event,
+                    origin: Peer::new(remote_addr)
+                }),
+                IResult::Error(ErrorKind::Custom(err)) => {
+                    count(&stats.failed);
+                    dead_letters.store(&logger, name, Some(&format!("{}", remote_addr)), &datagram, &err);
+                    None
+                }
+                IResult::Error(_) => {
+                    count(&stats.failed);
+                    dead_letters.store(&logger, name, Some(&format!("{}", remote_addr)), &datagram, &ParseError::new("input", "Failed to apply parser to datagram", &datagram, 0));
+                    None
+                }
+                IResult::Incomplete(_) => {
+                    warn!(&logger, "Dropping truncated datagram"; "remote" => format!("{}", remote_addr), "truncated" => count(&stats.truncated));
+                    None
+                }
+            }
+        })
+        .map_err(move |err| {
+            error!(&err_logger, "Error while receiving UDP datagrams: {}", err);
+            PipeError::Input(())
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket as StdUdpSocket;
+    use nom::Needed;
+    use {event_loop, null_logger};
+
+    // fixed size messages so that short datagram is incomplete
+    fn four_bytes(input: &[u8]) -> IResult<&[u8], Vec<u8>, ParseError> {
+        if input.len() < 4 {
+            IResult::Incomplete(Needed::Size(4))
+        } else {
+            IResult::Done(&input[4..], input[..4].to_vec())
+        }
+    }
+
+    #[test]
+    fn trim() {
+        assert_eq!(trim_datagram(b"foo\n\0"), b"foo");
+        assert_eq!(trim_datagram(b"foo\nbar"), b"foo\nbar");
+        assert_eq!(trim_datagram(b"\n\n"), b"");
+    }
+
+    #[test]
+    fn stats_and_remote_addr() {
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+        let socket = UdpSocket::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let stats = DatagramStats::default();
+
+        let input = socket_input::<_, _, ()>(null_logger(), "test", &Shutdown::new(), &DeadLetters::disabled(), socket, 8, four_bytes, stats.clone());
+
+        let client = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"012345678", &addr).unwrap();
+        client.send_to(b"ab\n", &addr).unwrap();
+        client.send_to(b"abcd\n", &addr).unwrap();
+
+        let (received, _input) = event_loop.run(input.into_future()).map_err(|_| ()).unwrap();
+        let received = received.unwrap();
+        assert_eq!(received.event, b"abcd".to_vec());
+        assert_eq!(received.origin.addr, client.local_addr().unwrap());
+
+        let meta: Vec<_> = received.origin.meta().map(|(name, value)| (name, match value {
+            MetaValue::String(value) => value.into_owned(),
+            _ => panic!("remote_addr should be string")
+        })).collect();
+        assert_eq!(meta, vec![("remote_addr", client.local_addr().unwrap().to_string())]);
+
+        assert_eq!(stats.received.load(Ordering::Relaxed), 3);
+        assert_eq!(stats.oversized.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.truncated.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.failed.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,384 @@
+use std::path::{Path, PathBuf};
+use std::fmt::Debug;
+use std::fs::{metadata, remove_file};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Result as IoResult;
+use std::mem;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use slog::Logger;
+use libc;
+
+use futures::{Async, Poll, Future};
+use futures::stream::Stream;
+use futures::sync::mpsc;
+
+use tokio_core::io::{Io, Codec};
+use tokio_core::reactor::{Handle, PollEvented};
+use tokio_uds::UnixListener;
+use mio_uds::UnixDatagram;
+
+use nom::{IResult, ErrorKind};
+
+use PipeError;
+use shutdown::{Shutdown, until_shutdown};
+use dead_letter::{DeadLetters, spool_failed};
+use codec::error::ParseError;
+use event::{Origin, Received, MetaValue};
+use input::tcp::forward_decoded;
+
+/// Credentials of process that sent the message as reported by the kernel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCred {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl PeerCred {
+    fn from_ucred(ucred: &libc::ucred) -> PeerCred {
+        PeerCred {
+            pid: ucred.pid as u32,
+            uid: ucred.uid,
+            gid: ucred.gid,
+        }
+    }
+}
+
+/// None if credentials could not be obtained
+#[derive(Debug, Clone)]
+pub struct UnixPeer {
+    pub cred: Option<PeerCred>,
+}
+
+impl Origin for UnixPeer {
+    fn meta<'i>(&'i self) -> Box<Iterator<Item=(&'i str, MetaValue<'i>)> + 'i> {
+        Box::new(self.cred.iter().flat_map(|cred| vec![
+            ("peer_pid", MetaValue::U64(cred.pid as u64)),
+            ("peer_uid", MetaValue::U64(cred.uid as u64)),
+            ("peer_gid", MetaValue::U64(cred.gid as u64)),
+        ]))
+    }
+}
+
+fn peer_cred(fd: RawFd) -> IoResult<PeerCred> {
+    let mut ucred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let ret = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_PEERCRED, &mut ucred as *mut libc::ucred as *mut libc::c_void, &mut len)
+    };
+
+    if ret != 0 {
+        return Err(IoError::last_os_error())
+    }
+    Ok(PeerCred::from_ucred(&ucred))
+}
+
+// ask kernel to attach SCM_CREDENTIALS to each received datagram
+fn set_pass_cred(fd: RawFd) -> IoResult<()> {
+    let enable: libc::c_int = 1;
+
+    let ret = unsafe {
+        libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_PASSCRED, &enable as *const libc::c_int as *const libc::c_void, mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+
+    if ret != 0 {
+        return Err(IoError::last_os_error())
+    }
+    Ok(())
+}
+
+/// Returns length of received datagram, sender credentials and true if datagram was truncated
+fn recv_with_cred(fd: RawFd, buf: &mut [u8]) -> IoResult<(usize, Option<PeerCred>, bool)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len()
+    };
+    let mut control = [0u8; 64];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(IoError::last_os_error())
+    }
+
+    let mut cred = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_CREDENTIALS {
+                cred = Some(PeerCred::from_ucred(&*(libc::CMSG_DATA(cmsg) as *const libc::ucred)));
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((len as usize, cred, msg.msg_flags & libc::MSG_TRUNC != 0))
+}
+
+// remove stale socket file left by previous run; refuse to remove anything else
+fn remove_socket_file(path: &Path) -> IoResult<()> {
+    match metadata(path) {
+        Ok(meta) => if meta.file_type().is_socket() {
+            remove_file(path)
+        } else {
+            Err(IoError::new(IoErrorKind::AlreadyExists, "path exists and is not a socket"))
+        },
+        Err(ref err) if err.kind() == IoErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err)
+    }
+}
+
+/// Removes socket file when input stream is dropped
+struct RemoveOnDrop<S> {
+    stream: S,
+    path: PathBuf,
+    logger: Logger,
+}
+
+impl<S: Stream> Stream for RemoveOnDrop<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        self.stream.poll()
+    }
+}
+
+impl<S> Drop for RemoveOnDrop<S> {
+    fn drop(&mut self) {
+        if let Err(err) = remove_socket_file(&self.path) {
+            error!(&self.logger, "Failed to remove socket file: {}", err);
+        }
+    }
+}
+
+pub fn unix_stream_input<C, T, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, path: &Path, codec: C) -> Box<Stream<Item=Received<T, UnixPeer>, Error=PipeError<(), OE>>> where C: Codec<In=T> + Clone + 'static, T: Debug + 'static, OE: 'static {
+    let logger = logger.new(o!("input" => name, "path" => format!("{}", path.display())));
+    let (sender, receiver) = mpsc::channel(10);
+
+    remove_socket_file(path).expect("removed stale socket file");
+    let listener = UnixListener::bind(path, &handle).expect("bound Unix stream socket");
+    info!(&logger, "Listening for Unix stream connections");
+
+    let incoming_logger = logger.clone();
+    let listener_logger = logger.clone();
+    let listener_handle = handle.clone();
+    let connection_shutdown = shutdown.clone();
+    let dead_letters = dead_letters.clone();
+    listener_handle.spawn(
+        until_shutdown(shutdown, listener.incoming())
+        .for_each(move |(unix_stream, _addr)| {
+            let id = unix_stream.as_raw_fd();
+            let peer = UnixPeer {
+                cred: peer_cred(id).map_err(|err| error!(&incoming_logger, "Failed to get peer credentials: {}", err)).ok()
+            };
+            let conn_logger = incoming_logger.new(o!("connection" => id, "peer" => format!("{:?}", peer.cred)));
+            info!(&conn_logger, "Accepted Unix stream connection");
+
+            let messages = spool_failed(conn_logger.clone(), dead_letters.clone(), name, None, unix_stream.framed(codec.clone()));
+            let connection = forward_decoded(conn_logger.clone(), sender.clone(), messages.map(move |event| Received {
+                event: event,
+                origin: peer.clone()
+            }));
+            if let Err(err) = connection_shutdown.spawn_connection(&handle, id, connection) {
+                error!(&conn_logger, "Connection will not stop on shutdown: {}", err);
+            }
+            Ok(())
+        })
+        .map_err(move |err| {
+            error!(&listener_logger, "Error processing incomming Unix stream connections: {:?}", err);
+            ()}));
+
+    Box::new(RemoveOnDrop {
+        stream: receiver.map_err(|_| PipeError::Input(())),
+        path: path.to_owned(),
+        logger: logger,
+    })
+}
+
+struct CredDatagrams {
+    io: PollEvented<UnixDatagram>,
+    buf: Vec<u8>,
+}
+
+impl Stream for CredDatagrams {
+    type Item = (Vec<u8>, Option<PeerCred>, bool);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, IoError> {
+        if let Async::NotReady = self.io.poll_read() {
+            return Ok(Async::NotReady)
+        }
+
+        match recv_with_cred(self.io.get_ref().as_raw_fd(), &mut self.buf) {
+            Ok((len, cred, truncated)) => Ok(Async::Ready(Some((self.buf[..len].to_vec(), cred, truncated)))),
+            Err(ref err) if err.kind() == IoErrorKind::WouldBlock => {
+                self.io.need_read();
+                Ok(Async::NotReady)
+            }
+            Err(err) => Err(err)
+        }
+    }
+}
+
+/// Each datagram is parsed as exactly one message
+pub fn unix_datagram_input<T, P, OE>(logger: &Logger, name: &'static str, handle: Handle, shutdown: &Shutdown, dead_letters: &DeadLetters, path: &Path, max_message_size: usize, parser: P) -> Box<Stream<Item=Received<T, UnixPeer>, Error=PipeError<(), OE>>> where T: Debug + 'static, P: Fn(&[u8]) -> IResult<&[u8], T, ParseError> + 'static, OE: 'static {
+    let logger = logger.new(o!("input" => name, "path" => format!("{}", path.display())));
+
+    remove_socket_file(path).expect("removed stale socket file");
+    let socket = UnixDatagram::bind(path).expect("bound Unix datagram socket");
+    set_pass_cred(socket.as_raw_fd()).expect("enabled SO_PASSCRED on Unix datagram socket");
+    info!(&logger, "Listening for Unix datagrams"; "max_message_size" => max_message_size);
+
+    let datagram_logger = logger.clone();
+    let err_logger = logger.clone();
+    let dead_letters = dead_letters.clone();
+    Box::new(RemoveOnDrop {
+        stream: until_shutdown(shutdown, CredDatagrams {
+                io: PollEvented::new(socket, &handle).expect("registered Unix datagram socket"),
+                buf: vec![0; max_message_size],
+            })
+            .filter_map(move |(datagram, cred, truncated)| {
+                if truncated {
+                    warn!(&datagram_logger, "Dropping truncated datagram"; "peer" => format!("{:?}", cred));
+                    return None
+                }
+
+                let end = datagram.iter().rposition(|&c| c != b'\n' && c != b'\0').map(|pos| pos + 1).unwrap_or(0);
+                match parser(&datagram[..end]) {
+                    IResult::Done(_, event) => Some(Received {
+                        event: event,
+                        origin: UnixPeer { cred: cred }
+                    }),
+                    IResult::Error(ErrorKind::Custom(err)) => {
+                        dead_letters.store(&datagram_logger, name, None, &datagram, &err);
+                        None
+                    }
+                    IResult::Error(_) | IResult::Incomplete(_) => {
+                        dead_letters.store(&datagram_logger, name, None, &datagram, &ParseError::new("input", "Failed to apply parser to datagram", &datagram, 0));
+                        None
+                    }
+                }
+            })
+            .map_err(move |err| {
+                error!(&err_logger, "Error while receiving Unix datagrams: {}", err);
+                PipeError::Input(())
+            }),
+        path: path.to_owned(),
+        logger: logger,
+    })
+}
+
+/// Local host name to fill in for messages that came without one
+pub fn local_hostname() -> IoResult<String> {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return Err(IoError::last_os_error())
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).map_err(|err| IoError::new(IoErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::net::{UnixStream, UnixDatagram as StdUnixDatagram};
+    use nom::Needed;
+    use tempfile::TempDir;
+    use {event_loop, null_logger};
+    use codec::nom::NomCodec;
+
+    fn line(input: &[u8]) -> IResult<&[u8], Vec<u8>, ParseError> {
+        match input.iter().position(|&byte| byte == b'\n') {
+            Some(pos) => IResult::Done(&input[pos + 1..], input[..pos].to_vec()),
+            None => IResult::Incomplete(Needed::Unknown)
+        }
+    }
+
+    fn own_cred() -> PeerCred {
+        unsafe {
+            PeerCred {
+                pid: libc::getpid() as u32,
+                uid: libc::getuid(),
+                gid: libc::getgid(),
+            }
+        }
+    }
+
+    #[test]
+    fn stream_peer_cred() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert_eq!(peer_cred(a.as_raw_fd()).unwrap(), own_cred());
+    }
+
+    #[test]
+    fn datagram_cred() {
+        let (receiver, sender) = StdUnixDatagram::pair().unwrap();
+        set_pass_cred(receiver.as_raw_fd()).unwrap();
+
+        sender.send(b"foo").unwrap();
+        let mut buf = [0u8; 16];
+        let (len, cred, truncated) = recv_with_cred(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"foo");
+        assert_eq!(cred, Some(own_cred()));
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn datagram_truncated() {
+        let (receiver, sender) = StdUnixDatagram::pair().unwrap();
+        set_pass_cred(receiver.as_raw_fd()).unwrap();
+
+        sender.send(b"foobar").unwrap();
+        let mut buf = [0u8; 3];
+        let (len, cred, truncated) = recv_with_cred(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"foo");
+        assert_eq!(cred, Some(own_cred()));
+        assert!(truncated);
+    }
+
+    #[test]
+    fn datagram_without_cred() {
+        let (receiver, sender) = StdUnixDatagram::pair().unwrap();
+        sender.send(b"foo").unwrap();
+
+        // SO_PASSCRED not enabled on receiving socket
+        let mut buf = [0u8; 16];
+        let (len, cred, _truncated) = recv_with_cred(receiver.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"foo");
+        assert_eq!(cred, None);
+    }
+
+    #[test]
+    fn stream_input_removes_socket_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("input.sock");
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+
+        let input = unix_stream_input::<_, _, ()>(&null_logger(), "test", handle, &Shutdown::new(), &DeadLetters::disabled(), &path, NomCodec::new(line));
+        assert!(path.exists());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"foo\n").unwrap();
+
+        let (received, input) = event_loop.run(input.into_future()).map_err(|_| ()).unwrap();
+        let received = received.unwrap();
+        assert_eq!(received.event, b"foo".to_vec());
+        assert_eq!(received.origin.cred, Some(own_cred()));
+
+        drop(input);
+        assert!(!path.exists());
+    }
+}
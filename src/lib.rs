@@ -16,18 +16,33 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate openssl;
+extern crate tokio_openssl;
+extern crate tokio_uds;
+extern crate mio_uds;
+extern crate libc;
+extern crate glob;
+extern crate tokio_signal;
+extern crate kafka;
+extern crate flate2;
+#[cfg(test)]
+extern crate tempfile;
 
 pub mod input;
 pub mod output;
 pub mod codec;
 pub mod event;
 pub mod serialize;
+pub mod ack;
+pub mod shutdown;
+pub mod dead_letter;
 
 use tokio_core::reactor::Core;
 use futures::sync::mpsc::SendError;
 use std::fmt::{self, Display, Debug};
 use std::error::Error;
 use slog::{DrainExt, Logger};
+use output::OutputError;
 
 #[derive(Debug)]
 pub enum PipeError<IE, OE> {
@@ -53,9 +68,9 @@ impl<IE: Debug + Display, OE: Debug + Display> Error for PipeError<IE, OE> {
     }
 }
 
-impl<IE, OE, T> From<SendError<T>> for PipeError<IE, OE> {
-    fn from(_send_error: SendError<T>) -> PipeError<IE, OE> {
-        panic!("Output receiver died")
+impl<IE, T> From<SendError<T>> for PipeError<IE, OutputError> {
+    fn from(_send_error: SendError<T>) -> PipeError<IE, OutputError> {
+        PipeError::Output(OutputError::Closed)
     }
 }
 
@@ -3,9 +3,12 @@ extern crate slog; extern crate logslash;
 extern crate futures;
 extern crate chrono;
 
-use logslash::{terminal_logger, event_loop};
+use logslash::{terminal_logger, event_loop, PipeError};
+use logslash::shutdown::Shutdown;
+use logslash::dead_letter::{DeadLetters, Spool, SpoolConfig};
 use logslash::event::Event;
-use logslash::input::syslog::{SyslogEvent, tcp_syslog_input};
+use logslash::input::syslog::{OwnedSyslogEvent, tcp_syslog_input};
+use logslash::codec::syslog::SyslogCodec;
 use logslash::output::debug::DebugPort;
 use logslash::output::debug::*;
 use logslash::serialize::Serializer;
@@ -16,6 +19,9 @@ use futures::{Future, Stream};
 use std::borrow::Cow;
 use std::io::Write;
 use std::fs::File;
+use std::env;
+use std::time::Duration;
+use std::path::Path;
 use chrono::{DateTime, UTC};
 
 //TODO:
@@ -23,21 +29,27 @@ use chrono::{DateTime, UTC};
 // * use codec to serialize into buffer owned by Framed
 // * support for arbitary fileds in messages
 // * put events behind Rc to reduce copying?
-// * proper nom errors with dumps etc
 // * reduce expect/unwrap for pipeline setup?
 // * parse common syslog messages
-// * auto-select syslog parser based on message
 // * benches
 // * use CPU thread pools for processing of inputs and outputs
-// * dead letters and parsing error logging
-// * Kafka output
 // * prelude with common input/output/codecs
 
+/// How long to wait for inputs to drain and outputs to flush after shutdown signal; can be
+/// overridden with SHUTDOWN_DEADLINE_SECS environment variable
+const SHUTDOWN_DEADLINE_SECS: u64 = 10;
+
+fn shutdown_deadline() -> Duration {
+    Duration::from_secs(env::var("SHUTDOWN_DEADLINE_SECS").ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(SHUTDOWN_DEADLINE_SECS))
+}
+
 #[derive(Debug)]
-struct SyslogDebugPortEvent(SyslogEvent);
+struct SyslogDebugPortEvent(OwnedSyslogEvent);
 
 impl DebugPort for SyslogDebugPortEvent {
-    type Payload = SyslogEvent;
+    type Payload = OwnedSyslogEvent;
 
     fn id(&self) -> Cow<str> { self.0.id() }
     fn timestamp(&self) -> DateTime<UTC> { self.0.timestamp() }
@@ -54,17 +66,40 @@ fn main() {
     let logger = terminal_logger();
     info!(&logger, "Setting up pipline");
 
-    let syslog = tcp_syslog_input(&logger, handle.clone(), &"127.0.0.1:5514".parse().unwrap());
+    let shutdown = Shutdown::new();
+    shutdown.on_signals(&logger, &handle);
+
+    let dead_letters = DeadLetters::new(Spool::open(Path::new("/tmp/dead-letters"), SpoolConfig::default()).expect("failed to open dead letter spool"));
+
+    let syslog = tcp_syslog_input(&logger, handle.clone(), &shutdown, &dead_letters, &"127.0.0.1:5514".parse().unwrap(), SyslogCodec::auto());
     // syslog.rename() - need a future stream - Receiver is a Stream
 
-    //let print = debug_print(&logger, JsonLogstashEventSerializer::default());
-    let print = debug_to_file(&logger, File::create("/tmp/out").expect("falied to open out file"), JsonLogstashEventSerializer::default());
+    //let print = debug_print(&logger, &shutdown, JsonLogstashEventSerializer::default());
+    let print = debug_to_file(&logger, &shutdown, File::create("/tmp/out").expect("falied to open out file"), JsonLogstashEventSerializer::default());
+
+    // single deadline counted from shutdown signal for both inputs and outputs
+    let deadline = shutdown_deadline();
 
-    //TODO: input and ouptut need to provide some printable error when they fail
     let pipe = syslog.map(SyslogDebugPortEvent).forward(print)
-        .map_err(|e| error!(&logger, "Error while processing pipe: {:?}", e));
+        .map(|_| ())
+        .map_err(|e| match e {
+            PipeError::Input(()) => error!(&logger, "Input failed; stopping pipeline"),
+            // nothing can be delivered anymore; stop accepting new events
+            PipeError::Output(err) => {
+                error!(&logger, "Output failed; stopping pipeline"; "error" => format!("{}", err));
+                shutdown.trigger();
+            }
+        })
+        // give up on inputs that did not drain in time
+        .select(shutdown.deadline(&handle, deadline).map(|_| warn!(&logger, "Inputs did not finish in time")));
 
     info!(logger, "Running pipline");
-    event_loop.run(pipe).expect("successful event loop run");
-    info!(logger, "Pipline done");
+    // errors were logged above; pipe (and so output sink) is dropped here letting output threads finish
+    let _ = event_loop.run(pipe);
+
+    if shutdown.wait_drained(deadline) {
+        info!(logger, "Pipline done");
+    } else {
+        error!(logger, "Outputs did not finish in time");
+    }
 }
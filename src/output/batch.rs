@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+use std::thread;
+use std::mem;
+
+use futures::{Future, Stream, Sink};
+use futures::stream;
+use futures::sync::mpsc::{channel, Receiver};
+
+pub enum Batched<T> {
+    Event(T),
+    /// Interval has passed; time to check if batch waited long enough
+    Tick,
+    /// All senders are gone and all events were received
+    End,
+}
+
+/// Blocking iterator over received events interleaved with periodic ticks; for use in output
+/// threads that send events in batches
+///
+/// Ticks keep coming after End so iteration needs to be stopped by the caller.
+pub fn ticking<T>(name: &str, receiver: Receiver<T>, interval: Duration) -> Box<Iterator<Item=Batched<T>>> where T: 'static {
+    let (ticker, ticks) = channel(1);
+    thread::Builder::new().name(format!("{}_tick", name)).spawn(move || {
+        let mut ticker = ticker;
+        loop {
+            thread::sleep(interval);
+            ticker = match ticker.send(()).wait() {
+                Ok(ticker) => ticker,
+                // batching thread is done
+                Err(_) => return
+            };
+        }
+    }).expect("failed to spawn thread for ticks");
+
+    Box::new(receiver.map(Batched::Event)
+        .chain(stream::once(Ok(Batched::End)))
+        .select(ticks.map(|_| Batched::Tick))
+        .wait()
+        .map(|item| item.expect("channel receiver does not fail")))
+}
+
+/// Items waiting to be sent together
+pub struct Batch<I> {
+    items: Vec<I>,
+    bytes: usize,
+    started: Instant,
+}
+
+impl<I> Default for Batch<I> {
+    fn default() -> Batch<I> {
+        Batch::new()
+    }
+}
+
+impl<I> Batch<I> {
+    pub fn new() -> Batch<I> {
+        Batch {
+            items: Vec::new(),
+            bytes: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, item: I, bytes: usize) {
+        if self.items.is_empty() {
+            self.started = Instant::now();
+        }
+        self.items.push(item);
+        self.bytes += bytes;
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Batch has max_events items, max_bytes bytes or its oldest item waited max_delay
+    pub fn is_ready(&self, max_events: usize, max_bytes: usize, max_delay: Duration) -> bool {
+        !self.items.is_empty() && (self.items.len() >= max_events || self.bytes >= max_bytes || self.started.elapsed() >= max_delay)
+    }
+
+    /// Take items out leaving the batch empty
+    pub fn take(&mut self) -> Vec<I> {
+        self.bytes = 0;
+        mem::replace(&mut self.items, Vec::new())
+    }
+}
@@ -11,6 +11,9 @@ use futures::Sink;
 use chrono::{DateTime, UTC};
 use PipeError;
 use serialize::Serializer;
+use shutdown::Shutdown;
+use output::OutputError;
+use output::retry::Backoff;
 use output::write::write_threaded;
 
 pub trait DebugPort {
@@ -52,14 +55,14 @@ fn serialize_event<T, S>(event: &T, buf: &mut Vec<u8>, serializer: &S) -> Result
         .map(|_| ())
 }
 
-pub fn debug_to_file<T, S, IE>(logger: &Logger, file: File, serializer: S) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, ()>>> where T: DebugPort + Send + 'static, S: Serializer<T::Payload> + Send + 'static, IE: 'static {
-    write_threaded(logger, "debug_to_file", file, move |event: &T, buf: &mut Vec<u8>| {
+pub fn debug_to_file<T, S, IE>(logger: &Logger, shutdown: &Shutdown, file: File, serializer: S) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: DebugPort + Send + 'static, S: Serializer<T::Payload> + Send + 'static, IE: 'static {
+    write_threaded(logger, "debug_to_file", shutdown, file, Backoff::default(), move |event: &T, buf: &mut Vec<u8>| {
         serialize_event(event, buf, &serializer)
     })
 }
 
-pub fn debug_print<T, S, IE>(logger: &Logger, serializer: S) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, ()>>> where T: DebugPort + Send + 'static, S: Serializer<T::Payload> + Send + 'static, IE: 'static {
-    write_threaded(logger, "debug_print", stdout(), move |event: &T, buf: &mut Vec<u8>| {
+pub fn debug_print<T, S, IE>(logger: &Logger, shutdown: &Shutdown, serializer: S) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: DebugPort + Send + 'static, S: Serializer<T::Payload> + Send + 'static, IE: 'static {
+    write_threaded(logger, "debug_print", shutdown, stdout(), Backoff::default(), move |event: &T, buf: &mut Vec<u8>| {
         serialize_event(event, buf, &serializer)
     })
 }
@@ -0,0 +1,571 @@
+use std::net::{SocketAddr, TcpStream};
+use std::io::{Read, Write, BufRead, BufReader};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Result as IoResult;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::thread;
+
+use slog::Logger;
+
+use futures::Sink;
+use futures::sync::mpsc::{channel, Sender, Receiver};
+
+use chrono::UTC;
+use serde_json;
+use serde_json::Value;
+
+use PipeError;
+use shutdown::{Shutdown, ShutdownFlag};
+use event::LogstashEvent;
+use serialize::{Serializer, JsonLogstashEventSerializer};
+use dead_letter::{DeadLetter, Spool};
+use output::{OutputError, TimeFormatError, check_time_format};
+use output::retry::Backoff;
+use output::write::{Failure, WriterSink, fail};
+use output::batch::{Batch, Batched, ticking};
+
+#[derive(Debug, Clone)]
+pub struct ElasticsearchConfig {
+    pub addr: SocketAddr,
+    /// Value of Host header
+    pub host: String,
+    /// Index name with strftime style placeholders filled in from event timestamp
+    pub index: String,
+    pub doc_type: String,
+    /// Send bulk request once that many events are batched...
+    pub max_events: usize,
+    /// ... or batched documents got that big in bytes...
+    pub max_bytes: usize,
+    /// ... or oldest batched event waited that long
+    pub max_delay: Duration,
+    /// Connect, read and write timeout of bulk requests
+    pub timeout: Duration,
+    /// Retry of failed requests and of items rejected with 429 or 5xx status
+    pub backoff: Backoff,
+}
+
+impl Default for ElasticsearchConfig {
+    fn default() -> ElasticsearchConfig {
+        ElasticsearchConfig {
+            addr: "127.0.0.1:9200".parse().expect("default address"),
+            host: "localhost".to_owned(),
+            index: "logstash-%Y.%m.%d".to_owned(),
+            doc_type: "logs".to_owned(),
+            max_events: 1000,
+            max_bytes: 5 * 1024 * 1024,
+            max_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Action<'a> {
+    index: ActionMeta<'a>,
+}
+
+#[derive(Serialize)]
+struct ActionMeta<'a> {
+    #[serde(rename = "_index")]
+    index: &'a str,
+    #[serde(rename = "_type")]
+    doc_type: &'a str,
+    #[serde(rename = "_id")]
+    id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct BulkResponse {
+    items: Vec<BTreeMap<String, BulkItemResult>>,
+}
+
+#[derive(Deserialize)]
+struct BulkItemResult {
+    status: u16,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+/// Action and document lines of bulk request for single event
+struct BulkItem {
+    action: Vec<u8>,
+    doc: Vec<u8>,
+}
+
+fn retryable(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+    close: bool,
+}
+
+fn invalid_response(message: &str) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, format!("Invalid HTTP response: {}", message))
+}
+
+fn read_line<R: BufRead>(input: &mut R, line: &mut String) -> IoResult<()> {
+    line.clear();
+    if input.read_line(line)? == 0 {
+        return Err(IoError::new(IoErrorKind::UnexpectedEof, "Connection closed while reading HTTP response"))
+    }
+    Ok(())
+}
+
+fn read_chunked<R: BufRead>(input: &mut R) -> IoResult<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        read_line(input, &mut line)?;
+        let size = line.trim_right().split(';').next().and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or_else(|| invalid_response("bad chunk size"))?;
+        if size == 0 {
+            // skip trailers
+            loop {
+                read_line(input, &mut line)?;
+                if line.trim_right().is_empty() {
+                    return Ok(body)
+                }
+            }
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        input.read_exact(&mut body[start..])?;
+        read_line(input, &mut line)?;
+    }
+}
+
+fn read_response<R: BufRead>(input: &mut R) -> IoResult<Response> {
+    let mut line = String::new();
+    read_line(input, &mut line)?;
+    let status = line.split(' ').nth(1).and_then(|status| status.parse().ok()).ok_or_else(|| invalid_response("bad status line"))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut close = false;
+    loop {
+        read_line(input, &mut line)?;
+        let header = line.trim_right();
+        if header.is_empty() {
+            break
+        }
+        let colon = header.find(':').ok_or_else(|| invalid_response("bad header"))?;
+        let value = header[colon + 1..].trim().to_lowercase();
+        match header[..colon].to_lowercase().as_str() {
+            "content-length" => content_length = Some(value.parse().map_err(|_| invalid_response("bad content length"))?),
+            "transfer-encoding" => chunked = value == "chunked",
+            "connection" => close = value == "close",
+            _ => ()
+        }
+    }
+
+    let body = if chunked {
+        read_chunked(input)?
+    } else if let Some(length) = content_length {
+        let mut body = vec![0; length];
+        input.read_exact(&mut body)?;
+        body
+    } else {
+        // body ends with the connection
+        close = true;
+        let mut body = Vec::new();
+        input.read_to_end(&mut body)?;
+        body
+    };
+
+    Ok(Response {
+        status: status,
+        body: body,
+        close: close,
+    })
+}
+
+/// Minimal HTTP/1.1 client keeping connection alive between requests
+struct BulkClient {
+    addr: SocketAddr,
+    host: String,
+    timeout: Duration,
+    connection: Option<BufReader<TcpStream>>,
+}
+
+impl BulkClient {
+    fn post(&mut self, logger: &Logger, body: &[u8]) -> IoResult<Response> {
+        if self.connection.is_none() {
+            // don't hang on unresponsive node; timeouts are retried as any other I/O error
+            let stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            self.connection = Some(BufReader::new(stream));
+            info!(logger, "Connected to Elasticsearch");
+        }
+
+        let result = {
+            let connection = self.connection.as_mut().expect("connected");
+            write!(connection.get_mut(), "POST /_bulk HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-ndjson\r\nContent-Length: {}\r\n\r\n", self.host, body.len())
+                .and_then(|_| connection.get_mut().write_all(body))
+                .and_then(|_| read_response(connection))
+        };
+
+        match result {
+            Ok(ref response) if !response.close => (),
+            // reconnect on next request
+            _ => self.connection = None
+        }
+        result
+    }
+}
+
+/// Sends batches to Elasticsearch and takes care of failed items
+struct Bulk {
+    logger: Logger,
+    config: ElasticsearchConfig,
+    client: BulkClient,
+    spool: Option<Spool>,
+    shutdown: ShutdownFlag,
+}
+
+impl Bulk {
+    fn dead_letter(&mut self, item: &BulkItem, error: String) {
+        error!(&self.logger, "Elasticsearch rejected document"; "error" => error.clone());
+
+        if let Some(ref mut spool) = self.spool {
+            let letter = DeadLetter {
+                timestamp: UTC::now(),
+                input: "elasticsearch".to_owned(),
+                remote_addr: Some(format!("{}", self.config.addr)),
+                error: error,
+                offset: None,
+                data: item.doc.clone(),
+            };
+            if let Err(err) = spool.append(&letter) {
+                error!(&self.logger, "Failed to store rejected document in dead letter spool"; "error" => format!("{}", err));
+            }
+        }
+    }
+
+    /// Items that need to be sent again
+    fn send(&mut self, items: Vec<BulkItem>) -> IoResult<Vec<BulkItem>> {
+        let mut body = Vec::new();
+        for item in items.iter() {
+            body.extend_from_slice(&item.action);
+            body.extend_from_slice(&item.doc);
+        }
+
+        let response = {
+            let client = &mut self.client;
+            let logger = &self.logger;
+            self.config.backoff.retry_blocking_until(logger, &self.shutdown, || client.post(logger, &body))
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                if self.shutdown.is_set() {
+                    let error = format!("Shut down before Elasticsearch could be reached: {}", err);
+                    for item in items.iter() {
+                        self.dead_letter(item, error.clone());
+                    }
+                }
+                return Err(err)
+            }
+        };
+
+        if retryable(response.status) {
+            warn!(&self.logger, "Elasticsearch bulk request failed; will retry"; "status" => response.status);
+            return Ok(items)
+        }
+        if response.status >= 400 {
+            let error = format!("Bulk request failed with status {}: {}", response.status, String::from_utf8_lossy(&response.body));
+            for item in items.iter() {
+                self.dead_letter(item, error.clone());
+            }
+            return Ok(Vec::new())
+        }
+
+        let results: BulkResponse = serde_json::from_slice(&response.body).map_err(|err| invalid_response(&format!("bad bulk response: {}", err)))?;
+        if results.items.len() != items.len() {
+            return Err(invalid_response("bulk response item count does not match the request"))
+        }
+
+        let mut retry = Vec::new();
+        for (item, result) in items.into_iter().zip(results.items.into_iter()) {
+            let result = match result.into_iter().next() {
+                Some((_, result)) => result,
+                None => return Err(invalid_response("empty bulk response item"))
+            };
+
+            if retryable(result.status) {
+                retry.push(item);
+            } else if result.status >= 400 {
+                let error = format!("Status {}: {}", result.status, result.error.map(|error| error.to_string()).unwrap_or_else(String::new));
+                self.dead_letter(&item, error);
+            }
+        }
+        Ok(retry)
+    }
+
+    /// Send batch retrying items that failed with 429 or 5xx; gives up on them according to
+    /// backoff or once shutdown was triggered putting them to dead letter spool
+    fn flush(&mut self, mut items: Vec<BulkItem>) -> IoResult<()> {
+        let mut retry = 0;
+        loop {
+            items = self.send(items)?;
+            if items.is_empty() {
+                return Ok(())
+            }
+
+            match self.config.backoff.delay(retry) {
+                Some(_) if self.shutdown.is_set() => {
+                    for item in items.iter() {
+                        self.dead_letter(item, "Shut down before document was accepted".to_owned());
+                    }
+                    return Ok(())
+                }
+                Some(delay) => {
+                    warn!(&self.logger, "Elasticsearch did not accept some documents; retrying"; "documents" => items.len(), "retry" => retry + 1);
+                    thread::sleep(delay);
+                    retry += 1;
+                }
+                None => {
+                    for item in items.iter() {
+                        self.dead_letter(item, "Gave up retrying rejected document".to_owned());
+                    }
+                    return Ok(())
+                }
+            }
+        }
+    }
+}
+
+/// Index events in Elasticsearch as Logstash JSON documents using bulk API
+///
+/// Events are batched in separate thread and sent when batch is full or max_delay has passed.
+/// Documents are indexed with `@id` as document ID. Items rejected with 429 or 5xx status are
+/// retried; other rejected documents are stored in given dead letter spool (this needs to be
+/// different spool than the one used by inputs). When Elasticsearch can't be reached the sink
+/// fails after giving up according to backoff.
+/// Requests that take longer than timeout are failed and retried.
+/// Once shutdown was triggered nothing is retried; documents that were not accepted are stored
+/// in the dead letter spool so writer thread finishes in time.
+///
+/// Returns error if index name is not a valid strftime style format.
+pub fn elasticsearch_output<T, IE>(logger: &Logger, shutdown: &Shutdown, config: ElasticsearchConfig, spool: Option<Spool>) -> Result<Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>>, TimeFormatError> where T: LogstashEvent + Send + 'static, IE: 'static {
+    check_time_format(&config.index)?;
+
+    let logger = logger.new(o!("output" => "elasticsearch", "addr" => format!("{}", config.addr)));
+    let (sender, receiver): (Sender<T>, Receiver<T>) = channel(config.max_events);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
+    let shutdown_flag = shutdown.flag();
+
+    shutdown.spawn("elasticsearch", move || {
+        let serializer = JsonLogstashEventSerializer::default();
+        let mut bulk = Bulk {
+            logger: logger.clone(),
+            client: BulkClient {
+                addr: config.addr,
+                host: config.host.clone(),
+                timeout: config.timeout,
+                connection: None,
+            },
+            config: config,
+            spool: spool,
+            shutdown: shutdown_flag,
+        };
+
+        let mut batch = Batch::new();
+
+        // wake up periodically so we can send batches that waited long enough
+        for item in ticking("elasticsearch", receiver, bulk.config.max_delay) {
+            let end = match item {
+                Batched::Event(event) => {
+                    let mut action = Vec::new();
+                    let index = event.timestamp().format(&bulk.config.index).to_string();
+                    let written = serde_json::to_writer(&mut action, &Action {
+                        index: ActionMeta {
+                            index: &index,
+                            doc_type: &bulk.config.doc_type,
+                            id: &event.id(),
+                        }
+                    });
+                    let doc = written.map_err(|err| err.to_string())
+                        .and_then(|_| serializer.serialize(&event, Vec::new()).map_err(|err| err.to_string()));
+                    let mut doc = match doc {
+                        Ok(doc) => doc,
+                        Err(err) => {
+                            error!(&logger, "Event failed to serialize: {}", err);
+                            continue
+                        }
+                    };
+                    action.push(b'\n');
+                    doc.push(b'\n');
+
+                    let bytes = action.len() + doc.len();
+                    batch.push(BulkItem {
+                        action: action,
+                        doc: doc,
+                    }, bytes);
+                    false
+                }
+                Batched::Tick => false,
+                Batched::End => true,
+            };
+
+            if (end && !batch.is_empty()) || batch.is_ready(bulk.config.max_events, bulk.config.max_bytes, bulk.config.max_delay) {
+                if let Err(err) = bulk.flush(batch.take()) {
+                    error!(&logger, "Failed to send events to Elasticsearch; giving up: {}", err);
+                    fail(&writer_failure, err);
+                    return
+                }
+            }
+            if end {
+                break
+            }
+        }
+        info!(&logger, "Elasticsearch output done");
+    }).expect("failed to spawn thread for elasticsearch_output");
+
+    Ok(Box::new(WriterSink::new(sender, failure)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use futures::Future;
+    use codec::syslog::{OwnedSyslogEvent, IdStrategy, parse_errors};
+    use dead_letter::{SpoolConfig, read_segment};
+    use null_logger;
+    use tempfile::TempDir;
+
+    fn event(msg_id: &str) -> OwnedSyslogEvent {
+        let message = format!("<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - {} - foo", msg_id);
+        // unique message IDs make it easy to tell documents apart
+        parse_errors::syslog_rfc5424(message.as_bytes()).map(|event| event.identified(IdStrategy::MsgId, message.as_bytes()).into_owned()).unwrap().1
+    }
+
+    // respond to each request on single connection with given bulk item statuses
+    fn stub(listener: TcpListener, responses: Vec<Vec<u16>>, requests: mpsc::Sender<String>) {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut input = BufReader::new(stream.try_clone().unwrap());
+            let mut output = stream;
+
+            for statuses in responses {
+                let mut content_length = 0;
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    input.read_line(&mut line).unwrap();
+                    if line.to_lowercase().starts_with("content-length:") {
+                        content_length = line[15..].trim().parse().unwrap();
+                    }
+                    if line.trim_right().is_empty() {
+                        break
+                    }
+                }
+                let mut body = vec![0; content_length];
+                input.read_exact(&mut body).unwrap();
+                requests.send(String::from_utf8(body).unwrap()).unwrap();
+
+                let items: Vec<String> = statuses.iter().map(|status| format!("{{\"index\":{{\"status\":{}{}}}}}", status,
+                    if *status == 400 { ",\"error\":{\"type\":\"mapper_parsing_exception\"}" } else { "" })).collect();
+                let response = format!("{{\"took\":1,\"errors\":true,\"items\":[{}]}}", items.join(","));
+                write!(output, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", response.len(), response).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn retry_and_dead_letter() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (requests_sender, requests) = mpsc::channel();
+        let config = ElasticsearchConfig {
+            addr: listener.local_addr().unwrap(),
+            max_events: 3,
+            max_delay: Duration::from_secs(60),
+            backoff: Backoff {
+                initial_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(10),
+                max_retries: Some(2),
+            },
+            .. ElasticsearchConfig::default()
+        };
+        stub(listener, vec![vec![201, 429, 400], vec![201]], requests_sender);
+
+        let dir = TempDir::new().unwrap();
+        let spool = Spool::open(dir.path(), SpoolConfig::default()).unwrap();
+
+        let shutdown = Shutdown::new();
+        let mut output = elasticsearch_output::<_, ()>(&null_logger(), &shutdown, config, Some(spool)).unwrap();
+        for msg_id in ["ID1", "ID2", "ID3"].iter() {
+            output = output.send(event(msg_id)).wait().ok().unwrap();
+        }
+        drop(output);
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        let first = requests.recv().unwrap();
+        assert_eq!(first.lines().count(), 6);
+        assert!(first.starts_with("{\"index\":{\"_index\":\"logstash-2003.10.11\",\"_type\":\"logs\",\"_id\":\"ID1\"}}\n{"));
+
+        let retried = requests.recv().unwrap();
+        assert_eq!(retried.lines().count(), 2);
+        assert!(retried.contains("\"_id\":\"ID2\""));
+
+        let segments = Spool::open(dir.path(), SpoolConfig::default()).unwrap().segments();
+        let letters = read_segment(&segments[0]).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert!(letters[0].error.contains("mapper_parsing_exception"));
+        assert!(String::from_utf8_lossy(&letters[0].data).contains("\"@id\":\"ID3\""));
+    }
+
+    #[test]
+    fn dead_letter_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (requests_sender, requests) = mpsc::channel();
+        let config = ElasticsearchConfig {
+            addr: listener.local_addr().unwrap(),
+            max_delay: Duration::from_secs(60),
+            backoff: Backoff {
+                initial_delay: Duration::from_secs(60),
+                max_delay: Duration::from_secs(60),
+                max_retries: None,
+            },
+            .. ElasticsearchConfig::default()
+        };
+        stub(listener, vec![vec![429]], requests_sender);
+
+        let dir = TempDir::new().unwrap();
+        let spool = Spool::open(dir.path(), SpoolConfig::default()).unwrap();
+
+        let shutdown = Shutdown::new();
+        let output = elasticsearch_output::<_, ()>(&null_logger(), &shutdown, config, Some(spool)).unwrap();
+        let output = output.send(event("ID1")).wait().ok().unwrap();
+        shutdown.trigger();
+        drop(output);
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+        assert_eq!(requests.recv().unwrap().lines().count(), 2);
+
+        let segments = Spool::open(dir.path(), SpoolConfig::default()).unwrap().segments();
+        let letters = read_segment(&segments[0]).unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].error, "Shut down before document was accepted");
+    }
+
+    #[test]
+    fn invalid_index_format() {
+        let config = ElasticsearchConfig {
+            index: "logstash-%Y.%m.%".to_owned(),
+            .. ElasticsearchConfig::default()
+        };
+        let shutdown = Shutdown::new();
+        let error = elasticsearch_output::<OwnedSyslogEvent, ()>(&null_logger(), &shutdown, config, None).err().unwrap();
+        assert_eq!(error.format, "logstash-%Y.%m.%");
+    }
+}
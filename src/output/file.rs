@@ -0,0 +1,789 @@
+use std::path::{Path, PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write, BufWriter};
+use std::io::Result as IoResult;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use std::collections::HashMap;
+
+use slog::Logger;
+
+use futures::{Future, Stream, Sink};
+use futures::sync::mpsc::{channel, Sender, Receiver};
+
+use tokio_core::reactor::Handle;
+use tokio_signal::unix::{Signal, SIGHUP};
+
+use chrono::{DateTime, UTC, TimeZone};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use PipeError;
+use shutdown::{Shutdown, ShutdownFlag};
+use event::{Event, MetaValue};
+use serialize::Serializer;
+use output::{OutputError, Framing, TimeFormatError, check_time_format};
+use output::retry::Backoff;
+use output::write::{Failure, WriterSink, fail};
+use output::batch::{Batched, ticking};
+
+/// Time boundary (UTC) at which file is rotated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotateEvery {
+    Hour,
+    Day,
+}
+
+impl RotateEvery {
+    fn period(&self, time: &DateTime<UTC>) -> String {
+        match *self {
+            RotateEvery::Hour => time.format("%Y%m%d%H").to_string(),
+            RotateEvery::Day => time.format("%Y%m%d").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RotatingFileConfig {
+    /// Rotate before file would get bigger than that
+    pub max_size: Option<u64>,
+    /// Rotate on first write after time boundary
+    pub every: Option<RotateEvery>,
+    /// Gzip rotated files
+    pub compress: bool,
+    /// Remove oldest rotated files so that only that many are kept; None to keep all
+    pub keep: Option<usize>,
+    /// Retry of failed writes and file operations before output fails
+    pub backoff: Backoff,
+}
+
+impl Default for RotatingFileConfig {
+    fn default() -> RotatingFileConfig {
+        RotatingFileConfig {
+            max_size: Some(256 * 1024 * 1024),
+            every: Some(RotateEvery::Day),
+            compress: false,
+            keep: Some(7),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+/// Request file output to close and open its file again on next write; used when file was
+/// moved away by external tool like logrotate
+#[derive(Debug, Clone, Default)]
+pub struct Reopen(Arc<AtomicBool>);
+
+impl Reopen {
+    pub fn new() -> Reopen {
+        Reopen::default()
+    }
+
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn requested(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+
+    /// Request reopen on SIGHUP
+    pub fn on_sighup(&self, logger: &Logger, handle: &Handle) {
+        let reopen = self.clone();
+        let logger = logger.clone();
+        let err_logger = logger.clone();
+
+        handle.spawn(Signal::new(SIGHUP, handle).flatten_stream()
+            .for_each(move |_| {
+                info!(&logger, "Reopening output files");
+                reopen.request();
+                Ok(())
+            })
+            .map_err(move |err| {
+                error!(&err_logger, "Failed to listen for SIGHUP: {}", err);
+                ()}));
+    }
+}
+
+fn modified(file: &File) -> IoResult<DateTime<UTC>> {
+    let since_epoch = file.metadata()?.modified()?.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    Ok(UTC.timestamp(since_epoch.as_secs() as i64, since_epoch.subsec_nanos()))
+}
+
+fn compress(path: &Path) -> IoResult<PathBuf> {
+    let mut file_name = path.file_name().expect("rotated file name").to_owned();
+    file_name.push(".gz");
+    let compressed = path.with_file_name(file_name);
+
+    let mut encoder = GzEncoder::new(File::create(&compressed)?, Compression::Default);
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)?;
+    Ok(compressed)
+}
+
+/// Whether name is <file name>.<%Y%m%dT%H%M%S>[-<n>][.gz] of file rotated from given file
+fn is_rotated(file_name: &str, name: &str) -> bool {
+    let digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_digit(10));
+
+    if !name.starts_with(file_name) || !name[file_name.len()..].starts_with('.') {
+        return false
+    }
+    let mut rest = &name[file_name.len() + 1..];
+    if rest.ends_with(".gz") {
+        rest = &rest[..rest.len() - 3];
+    }
+    let stamp = match rest.find('-') {
+        Some(dash) if digits(&rest[dash + 1..]) => &rest[..dash],
+        Some(_) => return false,
+        None => rest
+    };
+    stamp.len() == 15 && stamp.chars().enumerate().all(|(i, c)| if i == 8 { c == 'T' } else { c.is_digit(10) })
+}
+
+// BufWriter either accepts part of the data or fails without accepting any of it (keeping what
+// it failed to flush) so retrying only the rest never duplicates or tears a frame; write is told
+// if the frame is about to be started
+fn write_frame<F>(logger: &Logger, backoff: &Backoff, shutdown: &ShutdownFlag, frame: &[u8], mut write: F) -> IoResult<()> where F: FnMut(&[u8], bool) -> IoResult<usize> {
+    let mut written = 0;
+    while written < frame.len() {
+        let count = backoff.retry_blocking_until(logger, shutdown, || match write(&frame[written..], written == 0) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole event")),
+            result => result
+        })?;
+        written += count;
+    }
+    Ok(())
+}
+
+/// File written by rotating_file_output with its rotated predecessors named
+/// <file name>.<time of first write>[-<n>][.gz] kept in the same directory
+///
+/// Time of first write is kept in hidden .<file name>.started file next to it so it survives
+/// restarts.
+struct RotatingFile {
+    path: PathBuf,
+    config: RotatingFileConfig,
+    file: Option<BufWriter<File>>,
+    size: u64,
+    opened: DateTime<UTC>,
+}
+
+impl RotatingFile {
+    fn open(&mut self) -> IoResult<()> {
+        let file = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.opened = if self.size > 0 {
+            match self.started() {
+                Some(started) => started,
+                // file not written by us; the best we know is when it was last written
+                None => modified(&file)?
+            }
+        } else {
+            let now = UTC::now();
+            let mut started = File::create(self.started_path())?;
+            started.write_all(now.to_rfc3339().as_bytes())?;
+            started.sync_all()?;
+            now
+        };
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    fn started_path(&self) -> PathBuf {
+        self.path.with_file_name(format!(".{}.started", self.file_name()))
+    }
+
+    /// Time of first write to current file recorded when it was created
+    fn started(&self) -> Option<DateTime<UTC>> {
+        let mut started = String::new();
+        match File::open(self.started_path()).and_then(|mut file| file.read_to_string(&mut started)) {
+            Ok(_) => DateTime::parse_from_rfc3339(started.trim()).ok().map(|started| started.with_timezone(&UTC)),
+            Err(_) => None
+        }
+    }
+
+    fn close(&mut self) -> IoResult<()> {
+        if let Some(file) = self.file.take() {
+            file.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn due(&self, incoming: u64) -> bool {
+        if self.size == 0 {
+            return false
+        }
+        if let Some(max_size) = self.config.max_size {
+            if self.size + incoming > max_size {
+                return true
+            }
+        }
+        if let Some(every) = self.config.every {
+            if every.period(&self.opened) != every.period(&UTC::now()) {
+                return true
+            }
+        }
+        false
+    }
+
+    fn file_name(&self) -> String {
+        self.path.file_name().expect("output file name").to_string_lossy().into_owned()
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        let base = format!("{}.{}", self.file_name(), self.opened.format("%Y%m%dT%H%M%S"));
+        let mut n = 0;
+        loop {
+            let name = if n == 0 { base.clone() } else { format!("{}-{}", base, n) };
+            let path = self.path.with_file_name(&name);
+            if !path.exists() && !self.path.with_file_name(format!("{}.gz", name)).exists() {
+                return path
+            }
+            n += 1;
+        }
+    }
+
+    /// Rotated files; oldest first
+    fn rotated(&self) -> IoResult<Vec<PathBuf>> {
+        let dir = match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_owned(),
+            _ => PathBuf::from(".")
+        };
+        let file_name = self.file_name();
+
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(String::new);
+            if is_rotated(&file_name, &name) {
+                // compressed and not yet compressed files sort together
+                let key = name.trim_right_matches(".gz").to_owned();
+                rotated.push((key, path));
+            }
+        }
+        rotated.sort();
+        Ok(rotated.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn rotate(&mut self, logger: &Logger) -> IoResult<()> {
+        self.close()?;
+
+        let mut rotated = self.rotated_path();
+        fs::rename(&self.path, &rotated)?;
+        if self.config.compress {
+            rotated = compress(&rotated)?;
+        }
+        info!(logger, "Rotated output file"; "rotated" => format!("{}", rotated.display()));
+
+        if let Some(keep) = self.config.keep {
+            let rotated = self.rotated()?;
+            if rotated.len() > keep {
+                for path in rotated[..rotated.len() - keep].iter() {
+                    info!(logger, "Removing old output file"; "path" => format!("{}", path.display()));
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        self.open()
+    }
+
+    /// Write part of the frame; file is rotated only before frame is started so it is never
+    /// split between files
+    fn write(&mut self, logger: &Logger, data: &[u8], start: bool) -> IoResult<usize> {
+        // file left from before restart or reopen may be due for rotation as well
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if start && self.due(data.len() as u64) {
+            self.rotate(logger)?;
+        }
+
+        let written = self.file.as_mut().expect("open file").write(data)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+}
+
+/// Write serialized and framed events to file from separate thread rotating it on size or time
+///
+/// Rotated files get renamed with timestamp of when they were started and are optionally
+/// compressed; only the configured number of them is kept. Rotation and compression happen in
+/// the writer thread so they hold up writing for a while. Failed writes are retried according
+/// to backoff until shutdown is triggered. Use Reopen (e.g. Reopen::on_sighup) to make the
+/// output open its file again after it was moved by external tool.
+pub fn rotating_file_output<T, S, IE>(logger: &Logger, name: &'static str, shutdown: &Shutdown, path: &Path, config: RotatingFileConfig, reopen: Reopen, serializer: S, framing: Framing) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: Send + 'static, S: Serializer<T> + Send + 'static, IE: 'static {
+    let logger = logger.new(o!("output" => name, "path" => format!("{}", path.display())));
+    let (sender, receiver): (Sender<T>, Receiver<T>) = channel(100);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
+    let backoff = config.backoff.clone();
+
+    let shutdown_flag = shutdown.flag();
+
+    let mut file = RotatingFile {
+        path: path.to_owned(),
+        config: config,
+        file: None,
+        size: 0,
+        opened: UTC::now(),
+    };
+
+    shutdown.spawn(name, move || {
+        let mut buf = Vec::with_capacity(64);
+        let mut frame = Vec::with_capacity(64);
+
+        // ends when all senders are dropped and queued events are written
+        for event in receiver.wait() {
+            let event = event.expect("channel receiver does not fail");
+
+            buf.clear();
+            if let Err(err) = serializer.serialize(&event, &mut buf) {
+                error!(&logger, "Event failed to serialize: {}", err);
+                continue
+            }
+            frame.clear();
+            framing.frame(&buf, &mut frame);
+
+            if reopen.requested() {
+                info!(&logger, "Reopening output file");
+                if let Err(err) = file.close() {
+                    error!(&logger, "Failed to close output file: {}", err);
+                }
+            }
+
+            if let Err(err) = write_frame(&logger, &backoff, &shutdown_flag, &frame, |data, start| file.write(&logger, data, start)) {
+                error!(&logger, "Failed to write to output file; giving up: {}", err);
+                fail(&writer_failure, err);
+                return
+            }
+        }
+
+        match file.close() {
+            Ok(()) => info!(&logger, "Writer done"),
+            Err(err) => {
+                error!(&logger, "Failed to flush output file: {}", err);
+                fail(&writer_failure, err);
+            }
+        }
+    }).expect("failed to spawn thread for rotating_file_output");
+
+    Box::new(WriterSink::new(sender, failure))
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    /// Text with strftime style placeholders filled in from event timestamp
+    Literal(String),
+    /// Event::source for "source" and "hostname"; value of meta entry otherwise
+    Field(String),
+}
+
+/// File path rendered per event from template like
+/// "/var/log/remote/%{hostname}/%{program}-%Y%m%d.log"
+///
+/// %{source} (or %{hostname}) is replaced with event source and other %{name} with value of
+/// event meta entry of that name or "-" if event does not have it. Values have "/" replaced so
+/// they can't escape their directory. Strftime style placeholders are filled in from event
+/// timestamp.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTemplate {
+    segments: Vec<PathSegment>,
+}
+
+impl PathTemplate {
+    /// Fails if template has invalid strftime style placeholder (including unclosed "%{")
+    pub fn new(template: &str) -> Result<PathTemplate, TimeFormatError> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find("%{") {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                // unclosed placeholder is taken literally and fails the format check
+                None => break
+            };
+            if start > 0 {
+                check_time_format(&rest[..start])?;
+                segments.push(PathSegment::Literal(rest[..start].to_owned()));
+            }
+            segments.push(PathSegment::Field(rest[start + 2..end].to_owned()));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            check_time_format(rest)?;
+            segments.push(PathSegment::Literal(rest.to_owned()));
+        }
+
+        Ok(PathTemplate {
+            segments: segments
+        })
+    }
+
+    pub fn render<T: Event>(&self, event: &T) -> PathBuf {
+        let timestamp = event.timestamp();
+        let mut path = String::new();
+
+        for segment in self.segments.iter() {
+            match *segment {
+                PathSegment::Literal(ref literal) => path.push_str(&timestamp.format(literal).to_string()),
+                PathSegment::Field(ref name) => {
+                    let value = match name.as_str() {
+                        "source" | "hostname" => Some(event.source().into_owned()),
+                        name => event.meta().find(|&(key, _)| key == name).and_then(|(_, value)| match value {
+                            MetaValue::String(value) => Some(value.into_owned()),
+                            MetaValue::Bool(value) => Some(value.to_string()),
+                            MetaValue::I64(value) => Some(value.to_string()),
+                            MetaValue::U64(value) => Some(value.to_string()),
+                            MetaValue::F64(value) => Some(value.to_string()),
+                            MetaValue::Timestamp(value) => Some(value.to_rfc3339()),
+                            MetaValue::Null | MetaValue::Bytes(_) | MetaValue::Array(_) | MetaValue::Object(_) => None,
+                        })
+                    };
+                    path.push_str(&sanitize_path_value(value.as_ref().map(|value| value.as_str()).unwrap_or("-")));
+                }
+            }
+        }
+        PathBuf::from(path)
+    }
+}
+
+fn sanitize_path_value(value: &str) -> String {
+    match value {
+        "" => "-".to_owned(),
+        "." | ".." => value.replace('.', "_"),
+        _ => value.replace('/', "_").replace('\0', "_")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DynamicFileConfig {
+    /// Close least recently used file when opening more than that many
+    pub max_open: usize,
+    /// Close files not written to for that long; open files are also flushed that often
+    pub idle_timeout: Duration,
+    /// Retry of failed writes before output fails
+    pub backoff: Backoff,
+}
+
+impl Default for DynamicFileConfig {
+    fn default() -> DynamicFileConfig {
+        DynamicFileConfig {
+            max_open: 64,
+            idle_timeout: Duration::from_secs(60),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+struct OpenFile {
+    file: BufWriter<File>,
+    last_write: Instant,
+}
+
+fn close_file(logger: &Logger, path: &Path, file: OpenFile) -> IoResult<()> {
+    debug!(logger, "Closing output file"; "path" => format!("{}", path.display()));
+    file.file.into_inner().map_err(|err| err.into_error())?.sync_all()
+}
+
+/// Pool of files open for appending
+struct FilePool {
+    config: DynamicFileConfig,
+    files: HashMap<PathBuf, OpenFile>,
+}
+
+impl FilePool {
+    /// Write part of the frame to file at path opening it if needed
+    fn write(&mut self, logger: &Logger, path: &Path, data: &[u8]) -> IoResult<usize> {
+        if !self.files.contains_key(path) {
+            if self.files.len() >= self.config.max_open {
+                let lru = self.files.iter().min_by_key(|&(_, file)| file.last_write).map(|(path, _)| path.clone());
+                if let Some(lru) = lru {
+                    let file = self.files.remove(&lru).expect("least recently used file");
+                    close_file(logger, &lru, file)?;
+                }
+            }
+
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = OpenOptions::new().append(true).create(true).open(path)?;
+            debug!(logger, "Opened output file"; "path" => format!("{}", path.display()));
+            self.files.insert(path.to_owned(), OpenFile {
+                file: BufWriter::new(file),
+                last_write: Instant::now(),
+            });
+        }
+
+        // file stays open on failure; dropping it would flush what it has buffered and the retry
+        // would write that again
+        let file = self.files.get_mut(path).expect("open file");
+        file.last_write = Instant::now();
+        file.file.write(data)
+    }
+
+    /// Close idle files and flush the rest
+    fn tick(&mut self, logger: &Logger) -> IoResult<()> {
+        let idle: Vec<PathBuf> = self.files.iter()
+            .filter(|&(_, file)| file.last_write.elapsed() >= self.config.idle_timeout)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in idle {
+            let file = self.files.remove(&path).expect("idle file");
+            close_file(logger, &path, file)?;
+        }
+
+        for file in self.files.values_mut() {
+            file.file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn close_all(&mut self, logger: &Logger) -> IoResult<()> {
+        let mut result = Ok(());
+        for (path, file) in self.files.drain() {
+            if let Err(err) = close_file(logger, &path, file) {
+                result = Err(err);
+            }
+        }
+        result
+    }
+}
+
+/// Write serialized and framed events from separate thread to files with paths rendered from
+/// the events
+///
+/// Directories are created as needed. Up to max_open files are kept open; least recently
+/// written file is closed to make room for new one and files are closed after idle_timeout.
+/// Failed writes are retried according to backoff until shutdown is triggered.
+pub fn dynamic_file_output<T, S, IE>(logger: &Logger, name: &'static str, shutdown: &Shutdown, template: PathTemplate, config: DynamicFileConfig, serializer: S, framing: Framing) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: Event + Send + 'static, S: Serializer<T> + Send + 'static, IE: 'static {
+    let logger = logger.new(o!("output" => name));
+    let (sender, receiver): (Sender<T>, Receiver<T>) = channel(100);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
+    let backoff = config.backoff.clone();
+    let shutdown_flag = shutdown.flag();
+
+    shutdown.spawn(name, move || {
+        let tick_interval = config.idle_timeout;
+        let mut pool = FilePool {
+            config: config,
+            files: HashMap::new(),
+        };
+        let mut buf = Vec::with_capacity(64);
+        let mut frame = Vec::with_capacity(64);
+
+        for item in ticking(name, receiver, tick_interval) {
+            let event = match item {
+                Batched::Event(event) => event,
+                Batched::Tick => {
+                    if let Err(err) = pool.tick(&logger) {
+                        error!(&logger, "Failed to flush output files: {}", err);
+                    }
+                    continue
+                }
+                Batched::End => break
+            };
+
+            buf.clear();
+            if let Err(err) = serializer.serialize(&event, &mut buf) {
+                error!(&logger, "Event failed to serialize: {}", err);
+                continue
+            }
+            frame.clear();
+            framing.frame(&buf, &mut frame);
+
+            let path = template.render(&event);
+            if let Err(err) = write_frame(&logger, &backoff, &shutdown_flag, &frame, |data, _| pool.write(&logger, &path, data)) {
+                error!(&logger, "Failed to write to output file; giving up"; "error" => format!("{}", err), "path" => format!("{}", path.display()));
+                fail(&writer_failure, err);
+                return
+            }
+        }
+
+        match pool.close_all(&logger) {
+            Ok(()) => info!(&logger, "Writer done"),
+            Err(err) => {
+                error!(&logger, "Failed to flush output files: {}", err);
+                fail(&writer_failure, err);
+            }
+        }
+    }).expect("failed to spawn thread for dynamic_file_output");
+
+    Box::new(WriterSink::new(sender, failure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use tempfile::TempDir;
+    use codec::syslog::{SyslogEvent, parse_errors};
+    use serialize::JsonEventSerializer;
+    use null_logger;
+
+    struct Plain;
+
+    impl Serializer<String> for Plain {
+        type Error = io::Error;
+
+        fn serialize<W: Write>(&self, event: &String, mut out: W) -> Result<W, io::Error> {
+            out.write_all(event.as_bytes())?;
+            Ok(out)
+        }
+    }
+
+    fn write_lines(path: &Path, config: RotatingFileConfig, reopen: Reopen, lines: &[&str]) {
+        let shutdown = Shutdown::new();
+        let mut output = rotating_file_output::<_, _, ()>(&null_logger(), "file", &shutdown, path, config, reopen, Plain, Framing::Newline);
+        for line in lines {
+            output = output.send(line.to_string()).wait().ok().unwrap();
+        }
+        drop(output);
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+    }
+
+    fn read(path: &Path) -> String {
+        let mut data = String::new();
+        if path.extension().map(|ext| ext == "gz").unwrap_or(false) {
+            GzDecoder::new(File::open(path).unwrap()).unwrap().read_to_string(&mut data).unwrap();
+        } else {
+            File::open(path).unwrap().read_to_string(&mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn rotate_compress_and_keep() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.log");
+        let config = RotatingFileConfig {
+            max_size: Some(8),
+            every: None,
+            compress: true,
+            keep: Some(2),
+            .. RotatingFileConfig::default()
+        };
+        write_lines(&path, config, Reopen::new(), &["one", "two", "three", "four", "five"]);
+
+        let file = RotatingFile {
+            path: path.clone(),
+            config: RotatingFileConfig::default(),
+            file: None,
+            size: 0,
+            opened: UTC::now(),
+        };
+        let rotated = file.rotated().unwrap();
+        assert_eq!(rotated.len(), 2);
+        assert!(rotated.iter().all(|path| path.extension().unwrap() == "gz"));
+        assert_eq!(read(&rotated[0]), "three\n");
+        assert_eq!(read(&rotated[1]), "four\n");
+        assert_eq!(read(&path), "five\n");
+    }
+
+    #[test]
+    fn rotated_names() {
+        assert!(is_rotated("out.log", "out.log.20031011T221415"));
+        assert!(is_rotated("out.log", "out.log.20031011T221415-2"));
+        assert!(is_rotated("out.log", "out.log.20031011T221415-12.gz"));
+        assert!(!is_rotated("out.log", "out.log"));
+        assert!(!is_rotated("out.log", "out.log.bak"));
+        assert!(!is_rotated("out.log", "out.log.20031011T221415-"));
+        assert!(!is_rotated("out.log", "out.log.20031011T221415.gz.gz"));
+        assert!(!is_rotated("out.log", "out.log.2003101122141"));
+        assert!(!is_rotated("out.log", "out.logs.20031011T221415"));
+        assert!(!is_rotated("out.log", ".out.log.started"));
+    }
+
+    #[test]
+    fn first_write_time_kept_over_restart() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.log");
+        let config = RotatingFileConfig {
+            max_size: Some(8),
+            every: None,
+            .. RotatingFileConfig::default()
+        };
+        write_lines(&path, config.clone(), Reopen::new(), &["one"]);
+        // pretend the file was started long ago
+        File::create(dir.path().join(".out.log.started")).unwrap().write_all(b"2003-10-11T22:14:15+00:00").unwrap();
+        File::create(dir.path().join("out.log.bak")).unwrap().write_all(b"not rotated").unwrap();
+
+        write_lines(&path, config, Reopen::new(), &["three"]);
+
+        assert_eq!(read(&dir.path().join("out.log.20031011T221415")), "one\n");
+        assert_eq!(read(&path), "three\n");
+        assert_eq!(read(&dir.path().join("out.log.bak")), "not rotated");
+    }
+
+    #[test]
+    fn reopen() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.log");
+        let moved = dir.path().join("moved.log");
+        let reopen = Reopen::new();
+
+        let shutdown = Shutdown::new();
+        let mut output = rotating_file_output::<_, _, ()>(&null_logger(), "file", &shutdown, &path, RotatingFileConfig::default(), reopen.clone(), Plain, Framing::Newline);
+        output = output.send("before".to_owned()).wait().ok().unwrap();
+
+        // wait for the line to be written before moving the file away
+        while !path.exists() {
+            ::std::thread::sleep(Duration::from_millis(10));
+        }
+        fs::rename(&path, &moved).unwrap();
+        reopen.request();
+
+        output = output.send("after".to_owned()).wait().ok().unwrap();
+        drop(output);
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        assert_eq!(read(&moved), "before\n");
+        assert_eq!(read(&path), "after\n");
+    }
+
+    #[test]
+    fn path_template() {
+        let template = PathTemplate::new("/var/log/remote/%{hostname}/%{program}-%{facility}-%Y%m%d.log").unwrap();
+        let message = "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - foo";
+        let event = parse_errors::syslog_rfc5424(message.as_bytes()).map(SyslogEvent::into_owned).unwrap().1;
+        assert_eq!(template.render(&event), PathBuf::from("/var/log/remote/mymachine.example.com/evntslog-local4-20031011.log"));
+
+        let message = "<165>1 2003-10-11T22:14:15.003Z ../.. - - - - foo";
+        let event = parse_errors::syslog_rfc5424(message.as_bytes()).map(SyslogEvent::into_owned).unwrap().1;
+        assert_eq!(template.render(&event), PathBuf::from("/var/log/remote/.._../--local4-20031011.log"));
+    }
+
+    #[test]
+    fn invalid_path_template() {
+        assert_eq!(PathTemplate::new("/var/log/%{hostname}/%Y%").err().unwrap().format, "/%Y%");
+        assert_eq!(PathTemplate::new("/var/log/%{hostname").err().unwrap().format, "/var/log/%{hostname");
+        assert!(PathTemplate::new("/var/log/%{hostname}/%%.log").is_ok());
+    }
+
+    #[test]
+    fn dynamic_paths() {
+        let dir = TempDir::new().unwrap();
+        let template = PathTemplate::new(&format!("{}/%{{hostname}}/%{{program}}.log", dir.path().display())).unwrap();
+        let config = DynamicFileConfig {
+            max_open: 1,
+            .. DynamicFileConfig::default()
+        };
+
+        let shutdown = Shutdown::new();
+        let mut output = dynamic_file_output::<_, _, ()>(&null_logger(), "file", &shutdown, template, config, JsonEventSerializer::default(), Framing::Newline);
+        for &(host, program) in [("foo", "a"), ("bar", "a"), ("foo", "a"), ("foo", "b")].iter() {
+            let message = format!("<165>1 2003-10-11T22:14:15.003Z {} {} - - - hello", host, program);
+            let event = parse_errors::syslog_rfc5424(message.as_bytes()).map(SyslogEvent::into_owned).unwrap().1;
+            output = output.send(event).wait().ok().unwrap();
+        }
+        drop(output);
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        assert_eq!(read(&dir.path().join("foo").join("a.log")).lines().count(), 2);
+        assert_eq!(read(&dir.path().join("bar").join("a.log")).lines().count(), 1);
+        assert_eq!(read(&dir.path().join("foo").join("b.log")).lines().count(), 1);
+    }
+}
@@ -0,0 +1,377 @@
+use std::borrow::Cow;
+use std::io::Write;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Result as IoResult;
+use std::time::Duration;
+
+use slog::Logger;
+
+use futures::Sink;
+use futures::sync::mpsc::{channel, Sender, Receiver};
+
+use kafka::producer::{Producer, Record, RequiredAcks, ProduceConfirm};
+pub use kafka::client::Compression;
+
+use PipeError;
+use shutdown::Shutdown;
+use serialize::Serializer;
+use output::OutputError;
+use output::retry::Backoff;
+use output::write::{Failure, WriterSink, fail};
+use output::batch::{Batch, Batched, ticking};
+
+/// Provides everything needed to produce event to Kafka topic
+pub trait KafkaPort {
+    type Payload;
+
+    fn topic(&self) -> Cow<str>;
+    /// Records with the same key go to the same partition; records without key are spread
+    /// across partitions
+    fn key(&self) -> Option<Cow<str>>;
+    fn write_payload<W: Write, S: Serializer<Self::Payload>>(&self, out: W, serializer: &S) -> Result<W, S::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    /// Bootstrap brokers as host:port
+    pub hosts: Vec<String>,
+    pub client_id: String,
+    pub compression: Compression,
+    /// How long brokers can wait for all in-sync replicas to acknowledge batch
+    pub ack_timeout: Duration,
+    /// Send batch once that many events are batched...
+    pub max_events: usize,
+    /// ... or batched payloads got that big in bytes...
+    pub max_bytes: usize,
+    /// ... or oldest batched event waited that long
+    pub max_delay: Duration,
+    /// Retry of batches that failed to send or were not acknowledged
+    pub backoff: Backoff,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> KafkaConfig {
+        KafkaConfig {
+            hosts: vec!["localhost:9092".to_owned()],
+            client_id: "logslash".to_owned(),
+            compression: Compression::GZIP,
+            ack_timeout: Duration::from_secs(5),
+            max_events: 1000,
+            max_bytes: 1024 * 1024,
+            max_delay: Duration::from_millis(200),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+/// Topic, key and payload of serialized event
+struct KafkaRecord {
+    topic: String,
+    key: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+fn kafka_error<E: ToString>(err: E) -> IoError {
+    IoError::new(IoErrorKind::Other, err.to_string())
+}
+
+/// Connection that stores batches of records in Kafka
+trait Produce {
+    /// Send all records failing if any partition did not acknowledge its records
+    fn produce(&mut self, batch: &[KafkaRecord]) -> IoResult<()>;
+}
+
+fn records(batch: &[KafkaRecord]) -> Vec<Record<&[u8], &[u8]>> {
+    batch.iter()
+        .map(|record| Record::from_key_value(&record.topic, record.key.as_slice(), record.payload.as_slice()))
+        .collect()
+}
+
+/// Fails if any partition reported an error instead of offset of stored records
+fn confirmed(confirms: Vec<ProduceConfirm>) -> IoResult<()> {
+    for confirm in confirms {
+        for partition in confirm.partition_confirms {
+            if let Err(code) = partition.offset {
+                return Err(kafka_error(format!("Partition {} of topic {} failed to store records: {:?}", partition.partition, confirm.topic, code)))
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Produce for Producer {
+    fn produce(&mut self, batch: &[KafkaRecord]) -> IoResult<()> {
+        let confirms = self.send_all(&records(batch)).map_err(kafka_error)?;
+        confirmed(confirms)
+    }
+}
+
+fn connect(config: &KafkaConfig) -> IoResult<Producer> {
+    Producer::from_hosts(config.hosts.clone())
+        .with_client_id(config.client_id.clone())
+        .with_compression(config.compression)
+        .with_required_acks(RequiredAcks::All)
+        .with_ack_timeout(config.ack_timeout)
+        .create()
+        .map_err(kafka_error)
+}
+
+/// Produce events to Kafka topics from separate thread
+///
+/// Events are batched and sent once batch is full or max_delay has passed; each batch needs to
+/// be acknowledged by all in-sync replicas. Batches that fail are sent again according to
+/// backoff and after giving up the sink fails. As whole batch is retried some events may be
+/// stored twice.
+pub fn kafka_output<T, S, IE>(logger: &Logger, shutdown: &Shutdown, config: KafkaConfig, serializer: S) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: KafkaPort + Send + 'static, S: Serializer<T::Payload> + Send + 'static, IE: 'static {
+    producing_output(logger, shutdown, config, serializer, connect)
+}
+
+fn producing_output<T, S, IE, P, C>(logger: &Logger, shutdown: &Shutdown, config: KafkaConfig, serializer: S, mut connect: C) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: KafkaPort + Send + 'static, S: Serializer<T::Payload> + Send + 'static, IE: 'static, P: Produce, C: FnMut(&KafkaConfig) -> IoResult<P> + Send + 'static {
+    let logger = logger.new(o!("output" => "kafka", "hosts" => config.hosts.join(",")));
+    let (sender, receiver): (Sender<T>, Receiver<T>) = channel(config.max_events);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
+
+    shutdown.spawn("kafka", move || {
+        let mut producer: Option<P> = None;
+        let mut batch = Batch::new();
+
+        for item in ticking("kafka", receiver, config.max_delay) {
+            let end = match item {
+                Batched::Event(event) => {
+                    let payload = match event.write_payload(Vec::new(), &serializer) {
+                        Ok(payload) => payload,
+                        Err(err) => {
+                            error!(&logger, "Event failed to serialize: {}", err);
+                            continue
+                        }
+                    };
+                    let bytes = payload.len();
+                    batch.push(KafkaRecord {
+                        topic: event.topic().into_owned(),
+                        key: event.key().map(|key| key.into_owned().into_bytes()).unwrap_or_else(Vec::new),
+                        payload: payload,
+                    }, bytes);
+                    false
+                }
+                Batched::Tick => false,
+                Batched::End => true,
+            };
+
+            if (end && !batch.is_empty()) || batch.is_ready(config.max_events, config.max_bytes, config.max_delay) {
+                let records = batch.take();
+                let result = config.backoff.retry_blocking(&logger, || {
+                    if producer.is_none() {
+                        producer = Some(connect(&config)?);
+                        info!(&logger, "Connected to Kafka");
+                    }
+                    producer.as_mut().expect("connected").produce(&records)
+                });
+
+                if let Err(err) = result {
+                    error!(&logger, "Failed to produce events to Kafka; giving up: {}", err);
+                    fail(&writer_failure, err);
+                    return
+                }
+            }
+            if end {
+                break
+            }
+        }
+        info!(&logger, "Kafka output done");
+    }).expect("failed to spawn thread for kafka_output");
+
+    Box::new(WriterSink::new(sender, failure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use futures::Future;
+    use kafka::consumer::{Consumer, FetchOffset};
+    use kafka::producer::ProducePartitionConfirm;
+    use kafka::error::KafkaCode;
+    use codec::syslog::{SyslogEvent, OwnedSyslogEvent, Framing, parse_errors};
+    use serialize::SyslogSerializer;
+    use null_logger;
+
+    struct HostnameKeyed(OwnedSyslogEvent);
+
+    impl KafkaPort for HostnameKeyed {
+        type Payload = OwnedSyslogEvent;
+
+        fn topic(&self) -> Cow<str> { "logslash-test".into() }
+        fn key(&self) -> Option<Cow<str>> { Some(self.0.hostname.clone()) }
+        fn write_payload<W: Write, S: Serializer<Self::Payload>>(&self, out: W, serializer: &S) -> Result<W, S::Error> {
+            serializer.serialize(&self.0, out)
+        }
+    }
+
+    struct Unkeyed(OwnedSyslogEvent);
+
+    impl KafkaPort for Unkeyed {
+        type Payload = OwnedSyslogEvent;
+
+        fn topic(&self) -> Cow<str> { self.0.program.clone().unwrap_or_else(|| "logslash-test".into()) }
+        fn key(&self) -> Option<Cow<str>> { None }
+        fn write_payload<W: Write, S: Serializer<Self::Payload>>(&self, out: W, serializer: &S) -> Result<W, S::Error> {
+            serializer.serialize(&self.0, out)
+        }
+    }
+
+    fn event(msg_id: &str) -> OwnedSyslogEvent {
+        let message = format!("<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - {} - foo", msg_id);
+        parse_errors::syslog_rfc5424(message.as_bytes()).map(SyslogEvent::into_owned).unwrap().1
+    }
+
+    // stores topic, key and payload of records of each batch after failing given number of times
+    #[derive(Clone, Default)]
+    struct MockBroker {
+        failures: Arc<Mutex<u32>>,
+        batches: Arc<Mutex<Vec<Vec<(String, Vec<u8>, String)>>>>,
+    }
+
+    impl Produce for MockBroker {
+        fn produce(&mut self, batch: &[KafkaRecord]) -> IoResult<()> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(kafka_error("NotEnoughReplicas"))
+            }
+            self.batches.lock().unwrap().push(batch.iter()
+                .map(|record| (record.topic.clone(), record.key.clone(), String::from_utf8(record.payload.clone()).unwrap()))
+                .collect());
+            Ok(())
+        }
+    }
+
+    fn config(max_events: usize, max_retries: u32) -> KafkaConfig {
+        KafkaConfig {
+            max_events: max_events,
+            max_delay: Duration::from_secs(60),
+            backoff: Backoff {
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+                max_retries: Some(max_retries),
+            },
+            .. KafkaConfig::default()
+        }
+    }
+
+    fn mock_output<T>(shutdown: &Shutdown, config: KafkaConfig, broker: &MockBroker) -> Box<Sink<SinkItem=T, SinkError=PipeError<(), OutputError>>> where T: KafkaPort<Payload=OwnedSyslogEvent> + Send + 'static {
+        let broker = broker.clone();
+        producing_output(&null_logger(), shutdown, config, SyslogSerializer::new(Framing::Newline), move |_: &KafkaConfig| Ok(broker.clone()))
+    }
+
+    #[test]
+    fn batching() {
+        let broker = MockBroker::default();
+        let shutdown = Shutdown::new();
+        let mut output = mock_output(&shutdown, config(2, 0), &broker);
+        for msg_id in ["ID1", "ID2", "ID3"].iter() {
+            output = output.send(HostnameKeyed(event(msg_id))).wait().ok().unwrap();
+        }
+        drop(output);
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        let batches = broker.batches.lock().unwrap();
+        assert_eq!(batches.iter().map(|batch| batch.len()).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(batches[0][0].0, "logslash-test");
+        assert_eq!(batches[0][0].1, b"mymachine.example.com".to_vec());
+        assert!(batches[0][0].2.ends_with("evntslog - ID1 - foo"));
+        assert!(batches[0][1].2.ends_with("evntslog - ID2 - foo"));
+        assert!(batches[1][0].2.ends_with("evntslog - ID3 - foo"));
+    }
+
+    #[test]
+    fn keying() {
+        let broker = MockBroker::default();
+        let shutdown = Shutdown::new();
+        let output = mock_output(&shutdown, config(10, 0), &broker);
+        drop(output.send(Unkeyed(event("ID1"))).wait().ok().unwrap());
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        let batches = broker.batches.lock().unwrap();
+        assert_eq!(batches[0][0].0, "evntslog");
+        assert!(batches[0][0].1.is_empty());
+    }
+
+    #[test]
+    fn retry_failed_batch() {
+        let broker = MockBroker::default();
+        *broker.failures.lock().unwrap() = 2;
+        let shutdown = Shutdown::new();
+        let output = mock_output(&shutdown, config(1, 2), &broker);
+        drop(output.send(HostnameKeyed(event("ID1"))).wait().ok().unwrap());
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        assert_eq!(broker.batches.lock().unwrap().len(), 1);
+        assert_eq!(*broker.failures.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn give_up_on_failed_batch() {
+        let broker = MockBroker::default();
+        *broker.failures.lock().unwrap() = 3;
+        let shutdown = Shutdown::new();
+        let output = mock_output(&shutdown, config(1, 2), &broker);
+        let output = output.send(HostnameKeyed(event("ID1"))).wait().ok().unwrap();
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        assert!(broker.batches.lock().unwrap().is_empty());
+        assert_matches!(output.send(HostnameKeyed(event("ID2"))).wait().err(), Some(PipeError::Output(OutputError::Write(_))));
+    }
+
+    // needs single node broker with auto topic creation listening on localhost:9092
+    #[test]
+    fn records_of_batch() {
+        let batch = vec![KafkaRecord { topic: "logs".to_owned(), key: b"host".to_vec(), payload: b"foo".to_vec() }];
+        let records = records(&batch);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, "logs");
+        assert_eq!(records[0].key, &b"host"[..]);
+        assert_eq!(records[0].value, &b"foo"[..]);
+    }
+
+    #[test]
+    fn partition_failure() {
+        let confirm = |offset| vec![ProduceConfirm {
+            topic: "logs".to_owned(),
+            partition_confirms: vec![
+                ProducePartitionConfirm { offset: Ok(10), partition: 0 },
+                ProducePartitionConfirm { offset: offset, partition: 1 },
+            ]
+        }];
+
+        assert!(confirmed(confirm(Ok(20))).is_ok());
+        let err = confirmed(confirm(Err(KafkaCode::NotEnoughReplicas))).unwrap_err();
+        assert_eq!(err.to_string(), "Partition 1 of topic logs failed to store records: NotEnoughReplicas");
+    }
+
+    // producer settings (compression, acks=all, ack timeout) are only verified against live broker
+    #[test]
+    #[ignore]
+    fn produce_to_local_broker() {
+        let message = "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - produced";
+        let event = parse_errors::syslog_rfc5424(message.as_bytes()).map(SyslogEvent::into_owned).unwrap().1;
+
+        let mut consumer = Consumer::from_hosts(vec!["localhost:9092".to_owned()])
+            .with_topic("logslash-test".to_owned())
+            .with_fallback_offset(FetchOffset::Latest)
+            .create()
+            .unwrap();
+
+        let shutdown = Shutdown::new();
+        let output = kafka_output::<_, _, ()>(&null_logger(), &shutdown, KafkaConfig::default(), SyslogSerializer::new(Framing::Newline));
+        drop(output.send(HostnameKeyed(event)).wait().ok().unwrap());
+        assert!(shutdown.wait_drained(Duration::from_secs(10)));
+
+        let messages: Vec<Vec<u8>> = consumer.poll().unwrap().iter()
+            .flat_map(|set| set.messages().iter().map(|message| message.value.to_vec()).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(messages.len(), 1);
+        assert!(String::from_utf8_lossy(&messages[0]).ends_with("mymachine.example.com evntslog - ID47 - produced\n"));
+    }
+}
@@ -1,2 +1,74 @@
 pub mod write;
 pub mod debug;
+pub mod retry;
+pub mod syslog;
+pub mod tcp;
+pub mod batch;
+pub mod elasticsearch;
+pub mod kafka;
+pub mod file;
+
+use std::fmt::{self, Display};
+use std::error::Error;
+use std::io::Error as IoError;
+
+use chrono::format::{StrftimeItems, Item};
+
+#[derive(Debug)]
+pub enum OutputError {
+    Write(IoError),
+    Closed,
+}
+
+impl From<IoError> for OutputError {
+    fn from(error: IoError) -> OutputError {
+        OutputError::Write(error)
+    }
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OutputError::Write(ref error) => write!(f, "{}: {}", self.description(), error),
+            OutputError::Closed => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+impl Error for OutputError {
+    fn description(&self) -> &str {
+        match *self {
+            OutputError::Write(_) => "Failed to write to output",
+            OutputError::Closed => "Output has stopped accepting events",
+        }
+    }
+}
+
+/// Format with strftime style placeholders that can't be used to format event timestamps
+#[derive(Debug)]
+pub struct TimeFormatError {
+    pub format: String,
+}
+
+impl Display for TimeFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:?}", self.description(), self.format)
+    }
+}
+
+impl Error for TimeFormatError {
+    fn description(&self) -> &str {
+        "Invalid timestamp format"
+    }
+}
+
+/// Make sure that formatting timestamp with given format won't fail
+pub fn check_time_format(format: &str) -> Result<(), TimeFormatError> {
+    if StrftimeItems::new(format).any(|item| match item { Item::Error => true, _ => false }) {
+        return Err(TimeFormatError { format: format.to_owned() })
+    }
+    Ok(())
+}
+
+// how serialized events are delimited in the output stream
+pub use codec::Framing;
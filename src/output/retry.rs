@@ -0,0 +1,127 @@
+use std::time::Duration;
+use std::cmp::min;
+use std::io::Result as IoResult;
+use std::thread;
+
+use slog::Logger;
+
+use shutdown::ShutdownFlag;
+
+/// Exponential backoff between retries of failed output operations
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up after that many retries; None to retry forever
+    pub max_retries: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            max_retries: Some(8),
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before given retry (starting from 0) or None if we should give up
+    pub fn delay(&self, retry: u32) -> Option<Duration> {
+        if let Some(max_retries) = self.max_retries {
+            if retry >= max_retries {
+                return None
+            }
+        }
+
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::max_value());
+        Some(self.initial_delay.checked_mul(factor).map(|delay| min(delay, self.max_delay)).unwrap_or(self.max_delay))
+    }
+
+    /// Call operation until it succeeds sleeping between attempts; for use in output threads
+    pub fn retry_blocking<T, F>(&self, logger: &Logger, operation: F) -> IoResult<T> where F: FnMut() -> IoResult<T> {
+        self.retry(logger, None, operation)
+    }
+
+    /// Like retry_blocking but gives up with the last error once shutdown was triggered so that
+    /// retrying forever (max_retries of None) does not hold up shutdown
+    pub fn retry_blocking_until<T, F>(&self, logger: &Logger, shutdown: &ShutdownFlag, operation: F) -> IoResult<T> where F: FnMut() -> IoResult<T> {
+        self.retry(logger, Some(shutdown), operation)
+    }
+
+    fn retry<T, F>(&self, logger: &Logger, shutdown: Option<&ShutdownFlag>, mut operation: F) -> IoResult<T> where F: FnMut() -> IoResult<T> {
+        let mut retry = 0;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(err) => match self.delay(retry) {
+                    Some(_) if shutdown.map(|shutdown| shutdown.is_set()).unwrap_or(false) => return Err(err),
+                    Some(delay) => {
+                        warn!(logger, "Output operation failed; retrying"; "error" => format!("{}", err), "retry" => retry + 1, "delay_ms" => delay.as_secs() * 1000 + delay.subsec_nanos() as u64 / 1_000_000);
+                        thread::sleep(delay);
+                        retry += 1;
+                    }
+                    None => return Err(err)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use std::io::Error as IoError;
+    use std::io::ErrorKind as IoErrorKind;
+    use shutdown::Shutdown;
+    use null_logger;
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            max_retries: Some(4),
+        };
+
+        assert_eq!(backoff.delay(0), Some(Duration::from_millis(100)));
+        assert_eq!(backoff.delay(1), Some(Duration::from_millis(200)));
+        assert_eq!(backoff.delay(2), Some(Duration::from_millis(400)));
+        assert_eq!(backoff.delay(3), Some(Duration::from_millis(500)));
+        assert_eq!(backoff.delay(4), None);
+    }
+
+    #[test]
+    fn delay_forever() {
+        let backoff = Backoff {
+            max_retries: None,
+            .. Backoff::default()
+        };
+
+        assert_eq!(backoff.delay(100), Some(backoff.max_delay));
+    }
+
+    #[test]
+    fn give_up_on_shutdown() {
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            max_retries: None,
+        };
+        let shutdown = Shutdown::new();
+        let flag = shutdown.flag();
+
+        let mut attempts = 0;
+        let result: IoResult<()> = backoff.retry_blocking_until(&null_logger(), &flag, || {
+            attempts += 1;
+            if attempts == 3 {
+                shutdown.trigger();
+            }
+            Err(IoError::new(IoErrorKind::Other, "failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+}
@@ -0,0 +1,63 @@
+use std::net::SocketAddr;
+
+use slog::Logger;
+
+use futures::Sink;
+
+use PipeError;
+use shutdown::Shutdown;
+use codec::syslog::{OwnedSyslogEvent, Framing};
+use serialize::SyslogSerializer;
+use output::OutputError;
+use output::retry::Backoff;
+use output::tcp::tcp_output;
+
+/// Relay events to another syslog collector as RFC 5424 messages over TCP
+///
+/// Use Framing::OctetCounting for RFC 6587 receivers and Framing::Newline for receivers that
+/// expect one message per line (new lines in messages are sent as #012). Connection is made and
+/// re-established as described in tcp_output.
+pub fn tcp_syslog_output<IE>(logger: &Logger, shutdown: &Shutdown, addr: &SocketAddr, framing: Framing, queue_size: usize, backoff: Backoff) -> Box<Sink<SinkItem=OwnedSyslogEvent, SinkError=PipeError<IE, OutputError>>> where IE: 'static {
+    tcp_output(logger, "tcp_syslog", shutdown, addr, SyslogSerializer::new(framing), framing, queue_size, backoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use futures::Future;
+    use null_logger;
+    use codec::syslog::{SyslogEvent, parse_errors};
+
+    fn relay(framing: Framing) -> Vec<u8> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).unwrap();
+            data
+        });
+
+        let shutdown = Shutdown::new();
+        let output = tcp_syslog_output::<()>(&null_logger(), &shutdown, &addr, framing, 10, Backoff::default());
+        let (_, event) = parse_errors::syslog_rfc5424(b"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo\nbar").unwrap();
+        output.send(SyslogEvent::into_owned(event)).wait().ok().unwrap();
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        received.join().unwrap()
+    }
+
+    #[test]
+    fn newline_framing() {
+        assert_eq!(relay(Framing::Newline), b"<165>1 2003-10-11T22:14:15.003+00:00 mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo#012bar\n".to_vec());
+    }
+
+    #[test]
+    fn octet_counting_framing() {
+        assert_eq!(relay(Framing::OctetCounting), b"87 <165>1 2003-10-11T22:14:15.003+00:00 mymachine.example.com evntslog - ID47 - \xEF\xBB\xBFfoo\nbar".to_vec());
+    }
+}
@@ -0,0 +1,187 @@
+use std::net::{SocketAddr, TcpStream};
+use std::io::Write;
+
+use slog::Logger;
+
+use futures::{Stream, Sink};
+use futures::sync::mpsc::{channel, Sender, Receiver};
+
+use PipeError;
+use shutdown::Shutdown;
+use serialize::Serializer;
+use output::OutputError;
+pub use output::Framing;
+use output::retry::Backoff;
+use output::write::{Failure, WriterSink, fail};
+
+/// Send serialized events to TCP peer from separate thread
+///
+/// Connection is made when first event is sent. When peer goes away we reconnect according to
+/// backoff (use max_retries of None to never give up until shutdown) and send the event again; events
+/// received in the meantime are held in queue of queue_size events after which the sink stops
+/// accepting new events. Event that was being sent when connection broke may be delivered twice
+/// and events written just before the peer went away may be lost as TCP reports the broken
+/// connection only on subsequent writes.
+pub fn tcp_output<T, S, IE>(logger: &Logger, name: &'static str, shutdown: &Shutdown, addr: &SocketAddr, serializer: S, framing: Framing, queue_size: usize, backoff: Backoff) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: Send + 'static, S: Serializer<T> + Send + 'static, IE: 'static {
+    let logger = logger.new(o!("output" => name, "addr" => format!("{}", addr)));
+    let (sender, receiver): (Sender<T>, Receiver<T>) = channel(queue_size);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
+    let addr = *addr;
+    let shutdown_flag = shutdown.flag();
+
+    shutdown.spawn(name, move || {
+        let mut connection: Option<TcpStream> = None;
+        let mut buf = Vec::with_capacity(64);
+        let mut frame = Vec::with_capacity(64);
+
+        // ends when all senders are dropped and queued events are sent
+        for event in receiver.wait() {
+            let event = event.expect("channel receiver does not fail");
+
+            buf.clear();
+            if let Err(err) = serializer.serialize(&event, &mut buf) {
+                error!(&logger, "Event failed to serialize: {}", err);
+                continue
+            }
+            frame.clear();
+            framing.frame(&buf, &mut frame);
+
+            let result = backoff.retry_blocking_until(&logger, &shutdown_flag, || {
+                if connection.is_none() {
+                    connection = Some(TcpStream::connect(&addr)?);
+                    info!(&logger, "Connected to peer");
+                }
+
+                let result = connection.as_mut().expect("connected").write_all(&frame);
+                if result.is_err() {
+                    // reconnect on next attempt
+                    connection = None;
+                }
+                result
+            });
+
+            if let Err(err) = result {
+                error!(&logger, "Failed to send to peer; giving up: {}", err);
+                fail(&writer_failure, err);
+                return
+            }
+        }
+
+        if let Some(mut stream) = connection {
+            if let Err(err) = stream.flush() {
+                error!(&logger, "Failed to flush connection: {}", err);
+                fail(&writer_failure, err);
+                return
+            }
+        }
+        info!(&logger, "Sender done");
+    }).expect("failed to spawn thread for tcp_output");
+
+    Box::new(WriterSink::new(sender, failure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use futures::{Future, AsyncSink, future};
+    use null_logger;
+
+    struct Plain;
+
+    impl Serializer<String> for Plain {
+        type Error = io::Error;
+
+        fn serialize<W: Write>(&self, event: &String, mut out: W) -> Result<W, io::Error> {
+            out.write_all(event.as_bytes())?;
+            Ok(out)
+        }
+    }
+
+    // accept single connection and pass received lines; connection is closed on "quit"
+    fn serve(listener: TcpListener, lines: mpsc::Sender<String>) {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            drop(listener);
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                if line == "quit" {
+                    return
+                }
+                lines.send(line).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (lines_sender, lines) = mpsc::channel();
+        serve(listener, lines_sender.clone());
+
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(100),
+            max_retries: None,
+        };
+        let shutdown = Shutdown::new();
+        let mut output = tcp_output::<_, _, ()>(&null_logger(), "tcp", &shutdown, &addr, Plain, Framing::Newline, 10, backoff);
+
+        for line in ["foo", "quit"].iter() {
+            output = output.send(line.to_string()).wait().ok().unwrap();
+        }
+        assert_eq!(lines.recv().unwrap(), "foo");
+
+        // restart the peer; events sent while it was gone may be lost but we need to reconnect
+        serve(TcpListener::bind(addr).unwrap(), lines_sender);
+        for i in 0..100 {
+            output = output.send(i.to_string()).wait().ok().unwrap();
+        }
+        drop(output);
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        loop {
+            match lines.recv_timeout(Duration::from_secs(5)) {
+                Ok(ref line) if line == "99" => break,
+                Ok(_) => continue,
+                Err(_) => panic!("last event was not delivered")
+            }
+        }
+    }
+
+    #[test]
+    fn backpressure() {
+        // nobody listens on the port so the first event is retried until shutdown
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            max_retries: None,
+        };
+        let shutdown = Shutdown::new();
+        let mut output = tcp_output::<_, _, ()>(&null_logger(), "tcp", &shutdown, &addr, Plain, Framing::Newline, 4, backoff);
+
+        let accepted = future::lazy(|| {
+            let mut accepted = 0;
+            loop {
+                match output.start_send(accepted.to_string()) {
+                    Ok(AsyncSink::Ready) => accepted += 1,
+                    Ok(AsyncSink::NotReady(_)) => return Ok::<usize, ()>(accepted),
+                    Err(_) => panic!("output failed")
+                }
+            }
+        }).wait().unwrap();
+        // queue plus slot of the sender and possibly the event being retried
+        assert!(accepted >= 5 && accepted <= 6, "accepted {} events", accepted);
+
+        shutdown.trigger();
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+        assert_matches!(output.send("foo".to_owned()).wait().err(), Some(PipeError::Output(OutputError::Write(_))));
+    }
+}
@@ -1,90 +1,211 @@
 use std::fmt::{Display, Debug};
-use std::io::{Write, BufWriter};
+use std::io::{Write, BufWriter, Stdout};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Result as IoResult;
+use std::fs::File;
+use std::net::TcpStream;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::mem::replace;
-use std::thread;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
 
 use slog::Logger;
 
-use futures::{Future, Stream, Sink};
-use futures::future::ok;
+use futures::{Future, Stream, Sink, StartSend, Poll};
 use futures::sync::mpsc::{channel, Sender, Receiver};
 
 use tokio_core::io::write_all;
 use tokio_core::reactor::Handle;
 
 use PipeError;
+use shutdown::{Shutdown, ShutdownFlag};
+use ack::Acked;
+use output::OutputError;
+use output::retry::Backoff;
 
-pub fn write_threaded<T, W, IE, SE, F>(logger: &Logger, name: &'static str, out: W, serialize: F) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, ()>>> where T: Send + 'static, W: Send + 'static, IE: 'static, SE: Debug + Display + 'static, W: Write, F: Fn(&T, &mut Vec<u8>) -> Result<(), SE> + Send + 'static {
+/// Output that can make sure written data is persisted
+pub trait Durable: Write {
+    fn sync(&mut self) -> IoResult<()>;
+}
+
+impl Durable for File {
+    fn sync(&mut self) -> IoResult<()> {
+        self.sync_all()
+    }
+}
+
+impl Durable for TcpStream {
+    fn sync(&mut self) -> IoResult<()> {
+        self.flush()
+    }
+}
+
+impl Durable for Stdout {
+    fn sync(&mut self) -> IoResult<()> {
+        self.flush()
+    }
+}
+
+/// Error that stopped the writer
+pub type Failure = Arc<Mutex<Option<IoError>>>;
+
+pub fn fail(failure: &Failure, error: IoError) {
+    *failure.lock().expect("failure lock") = Some(error);
+}
+
+/// Sink feeding the writer; once writer stops it reports the error that stopped it
+pub struct WriterSink<T, IE> {
+    sender: Sender<T>,
+    failure: Failure,
+    input_error: PhantomData<IE>,
+}
+
+impl<T, IE> WriterSink<T, IE> {
+    pub fn new(sender: Sender<T>, failure: Failure) -> WriterSink<T, IE> {
+        WriterSink {
+            sender: sender,
+            failure: failure,
+            input_error: PhantomData,
+        }
+    }
+
+    fn error(&self) -> PipeError<IE, OutputError> {
+        match self.failure.lock().expect("failure lock").take() {
+            Some(error) => PipeError::Output(OutputError::Write(error)),
+            None => PipeError::Output(OutputError::Closed)
+        }
+    }
+}
+
+impl<T, IE> Sink for WriterSink<T, IE> {
+    type SinkItem = T;
+    type SinkError = PipeError<IE, OutputError>;
+
+    fn start_send(&mut self, item: T) -> StartSend<T, Self::SinkError> {
+        self.sender.start_send(item).map_err(|_| self.error())
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.sender.poll_complete().map_err(|_| self.error())
+    }
+}
+
+// serialize and write event; false if event failed to serialize and was dropped
+fn write_event<T, W, SE, F>(logger: &Logger, out: &mut BufWriter<W>, buf: &mut Vec<u8>, backoff: &Backoff, shutdown: &ShutdownFlag, serialize: &F, event: &T, sync: bool) -> IoResult<bool> where W: Durable, SE: Display, F: Fn(&T, &mut Vec<u8>) -> Result<(), SE> {
+    buf.clear();
+    if let Err(err) = serialize(event, buf) {
+        // if something when wrong log and drop the message
+        error!(logger, "Event failed to serialize: {}", err);
+        return Ok(false)
+    }
+
+    // BufWriter either accepts part of the data or fails without accepting any of it (keeping
+    // what it failed to flush) so retrying only the rest never duplicates data
+    let mut written = 0;
+    while written < buf.len() {
+        let count = backoff.retry_blocking_until(logger, shutdown, || match out.write(&buf[written..]) {
+            Ok(0) => Err(IoError::new(IoErrorKind::WriteZero, "failed to write whole event")),
+            result => result
+        })?;
+        written += count;
+    }
+    if sync {
+        backoff.retry_blocking_until(logger, shutdown, || out.flush().and_then(|_| out.get_mut().sync()))?;
+    }
+    Ok(true)
+}
+
+/// Writes serialized events in separate thread; on shutdown writer thread writes all queued
+/// events and syncs the output before main is allowed to exit (see Shutdown::wait_drained)
+///
+/// Failed writes are retried according to backoff until shutdown is triggered; when writer gives
+/// up the sink fails with the write error.
+pub fn write_threaded<T, W, IE, SE, F>(logger: &Logger, name: &'static str, shutdown: &Shutdown, out: W, backoff: Backoff, serialize: F) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: Send + 'static, W: Send + 'static, IE: 'static, SE: Debug + Display + 'static, W: Durable, F: Fn(&T, &mut Vec<u8>) -> Result<(), SE> + Send + 'static {
     let logger = logger.new(o!("output" => name));
     let (sender, receiver): (Sender<T>, Receiver<T>) = channel(100);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
+    let shutdown_flag = shutdown.flag();
 
-    thread::Builder::new().name("write_threaded".into()).spawn(move || {
+    shutdown.spawn(name, move || {
         info!(&logger, "Writer thread running");
 
-        let buf_cell = Rc::new(RefCell::new(Some(Vec::with_capacity(64))));
-        let buf_cell_taker = buf_cell.clone();
-        let buf_cell_putter = buf_cell.clone();
+        let mut out = BufWriter::new(out);
+        let mut buf = Vec::with_capacity(64);
 
-        let out_cell = Rc::new(RefCell::new(Some(BufWriter::new(out))));
-        let out_cell_taker = out_cell.clone();
-        let out_cell_putter = out_cell.clone();
+        // ends when all senders are dropped and queued events are written
+        for event in receiver.wait() {
+            let event = event.expect("channel receiver does not fail");
 
-        let ser_err_logger = logger.clone();
+            if let Err(err) = write_event(&logger, &mut out, &mut buf, &backoff, &shutdown_flag, &serialize, &event, false) {
+                error!(&logger, "Failed to write to output; giving up: {}", err);
+                fail(&writer_failure, err);
+                return
+            }
+        }
 
-        let pipe = receiver
-            // populate the buffer with message
-            .map(move |event| {
-                let mut buf = buf_cell_taker.borrow_mut().take().expect("taken");
-                serialize(&event, &mut buf)
-                    .map(|_| buf)
-            })
-            // if something when wrong log and drop the message
-            .filter_map(move |ser_result|
-                match ser_result {
-                    Ok(ok) => Some(ok),
-                    Err(err) => {
-                        error!(&ser_err_logger, "Event failed to serialize: {}", err);
-                        None
-                    }
-                }
-            )
-            // write message to stdout and send back the buffer for reuse
-            .and_then(move |body| {
-                let out = out_cell_taker.borrow_mut().take().expect("taken");
-                write_all(out, body).map_err(|err| panic!("Failed to write to output: {}", err))
-            })
-            // cleanup and back for reuse
-            .map(move |(out, mut buf)| {
-                // give out back
-                replace(&mut *(out_cell_putter.borrow_mut()), Some(out));
+        match out.into_inner().map_err(|err| err.into_error()).and_then(|mut out| out.sync()) {
+            Ok(()) => info!(&logger, "Writer done"),
+            Err(err) => {
+                error!(&logger, "Failed to flush output: {}", err);
+                fail(&writer_failure, err);
+            }
+        }
+    }).expect("failed to spawn thread for write_threaded");
 
-                // clear buffer and give it back
-                buf.clear();
-                replace(&mut *(buf_cell_putter.borrow_mut()), Some(buf));
+    Box::new(WriterSink::new(sender, failure))
+}
 
-                ()
-            })
-            .for_each(|_| Ok(()));
+/// Like write_threaded but each event is acknowledged (see ack::Acked) only after it was written
+/// and synced to the output
+///
+/// Events that failed to serialize or were not written before writer gave up are dropped without
+/// acknowledgment so the input can tell sender that they were not accepted.
+pub fn write_threaded_acked<T, W, IE, SE, F>(logger: &Logger, name: &'static str, shutdown: &Shutdown, out: W, backoff: Backoff, serialize: F) -> Box<Sink<SinkItem=Acked<T>, SinkError=PipeError<IE, OutputError>>> where T: Send + 'static, W: Send + 'static, IE: 'static, SE: Debug + Display + 'static, W: Durable, F: Fn(&T, &mut Vec<u8>) -> Result<(), SE> + Send + 'static {
+    let logger = logger.new(o!("output" => name));
+    let (sender, receiver): (Sender<Acked<T>>, Receiver<Acked<T>>) = channel(100);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
+    let shutdown_flag = shutdown.flag();
 
-        pipe.wait().expect("write_threaded future failed");
+    shutdown.spawn(name, move || {
+        info!(&logger, "Writer thread running");
 
-        error!(&logger, "Writer done");
-    }).expect("failed to spawn thread for write_threaded");
+        let mut out = BufWriter::new(out);
+        let mut buf = Vec::with_capacity(64);
+
+        for event in receiver.wait() {
+            let event = event.expect("channel receiver does not fail");
 
-    // TODO: thread needs to be joined to make sure that file is synced at shutdown
+            match write_event(&logger, &mut out, &mut buf, &backoff, &shutdown_flag, &serialize, &event.event, true) {
+                Ok(true) => {
+                    event.ack();
+                }
+                Ok(false) => (),
+                Err(err) => {
+                    error!(&logger, "Failed to write to output; giving up: {}", err);
+                    fail(&writer_failure, err);
+                    return
+                }
+            }
+        }
 
-    Box::new(sender.with(|message| {
-        ok::<T, PipeError<IE, ()>>(message)
-    }))
+        info!(&logger, "Writer done");
+    }).expect("failed to spawn thread for write_threaded_acked");
+
+    Box::new(WriterSink::new(sender, failure))
 }
 
 // This will block unless W can register events in event loop and
-// write can return Err(std::io::ErrorKind::WouldBlock) if it would block
-pub fn write_blocking<T, W, IE, SE, F>(logger: &Logger, name: &'static str, handle: Handle, out: W, serialize: F) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, ()>>> where T: 'static, W: 'static, IE: 'static, SE: Debug + Display + 'static, W: Write, F: Fn(&T, &mut Vec<u8>) -> Result<(), SE> + 'static {
+// write can return Err(std::io::ErrorKind::WouldBlock) if it would block;
+// write errors are not retried and fail the sink
+pub fn write_blocking<T, W, IE, SE, F>(logger: &Logger, name: &'static str, handle: Handle, out: W, serialize: F) -> Box<Sink<SinkItem=T, SinkError=PipeError<IE, OutputError>>> where T: 'static, W: 'static, IE: 'static, SE: Debug + Display + 'static, W: Write, F: Fn(&T, &mut Vec<u8>) -> Result<(), SE> + 'static {
     let logger = logger.new(o!("output" => name));
     let (sender, receiver): (Sender<T>, Receiver<T>) = channel(100);
+    let failure = Failure::default();
+    let writer_failure = failure.clone();
 
     let buf_cell = Rc::new(RefCell::new(Some(Vec::with_capacity(64))));
     let buf_cell_taker = buf_cell.clone();
@@ -95,6 +216,7 @@ pub fn write_blocking<T, W, IE, SE, F>(logger: &Logger, name: &'static str, hand
     let out_cell_putter = out_cell.clone();
 
     let ser_err_logger = logger.clone();
+    let write_err_logger = logger.clone();
 
     let pipe = receiver
         // populate the buffer with message
@@ -116,7 +238,12 @@ pub fn write_blocking<T, W, IE, SE, F>(logger: &Logger, name: &'static str, hand
         // write message to stdout and send back the buffer for reuse
         .and_then(move |body| {
             let out = out_cell_taker.borrow_mut().take().expect("taken");
-            write_all(out, body).map_err(|err| panic!("Failed to write to output: {}", err))
+            let write_err_logger = write_err_logger.clone();
+            let writer_failure = writer_failure.clone();
+            write_all(out, body).map_err(move |err| {
+                error!(&write_err_logger, "Failed to write to output: {}", err);
+                fail(&writer_failure, err);
+            })
         })
         // cleanup and back for reuse
         .map(move |(out, mut buf)| {
@@ -129,11 +256,87 @@ pub fn write_blocking<T, W, IE, SE, F>(logger: &Logger, name: &'static str, hand
 
             ()
         })
-        .for_each(|_| Ok(()));
+        .for_each(|_| Ok(()))
+        // flush what is left in the buffer once all senders are gone
+        .map(move |_| {
+            if let Some(mut out) = out_cell.borrow_mut().take() {
+                if let Err(err) = out.flush() {
+                    error!(&logger, "Failed to flush output: {}", err);
+                }
+            }
+        });
 
     handle.spawn(pipe);
 
-    Box::new(sender.with(|message| {
-        ok::<T, PipeError<IE, ()>>(message)
-    }))
+    Box::new(WriterSink::new(sender, failure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::min;
+    use std::time::Duration;
+    use null_logger;
+
+    // accepts up to 4096 bytes per write; every other write fails if flaky and all fail if broken
+    #[derive(Clone, Default)]
+    struct Out {
+        data: Arc<Mutex<Vec<u8>>>,
+        writes: Arc<Mutex<usize>>,
+        flaky: bool,
+        broken: bool,
+    }
+
+    impl Write for Out {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            let mut writes = self.writes.lock().unwrap();
+            *writes += 1;
+            if self.broken || (self.flaky && *writes % 2 == 0) {
+                return Err(IoError::new(IoErrorKind::Other, "out failed"))
+            }
+            let len = min(buf.len(), 4096);
+            self.data.lock().unwrap().extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Durable for Out {
+        fn sync(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    fn output(shutdown: &Shutdown, out: Out, max_retries: u32) -> Box<Sink<SinkItem=Vec<u8>, SinkError=PipeError<(), OutputError>>> {
+        let backoff = Backoff { initial_delay: Duration::from_millis(1), max_retries: Some(max_retries), .. Backoff::default() };
+        write_threaded(&null_logger(), "test", shutdown, out, backoff, |event: &Vec<u8>, buf: &mut Vec<u8>| -> Result<(), IoError> {
+            buf.extend_from_slice(event);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn partial_write_not_duplicated() {
+        let shutdown = Shutdown::new();
+        let out = Out { flaky: true, .. Out::default() };
+        // larger than BufWriter buffer so it is written in chunks directly to out
+        let event: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+
+        output(&shutdown, out.clone(), 1).send(event.clone()).wait().unwrap();
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+        assert!(out.data.lock().unwrap().as_slice() == event.as_slice());
+    }
+
+    #[test]
+    fn write_failure_fails_sink() {
+        let shutdown = Shutdown::new();
+        let out = Out { broken: true, .. Out::default() };
+
+        let sink = output(&shutdown, out, 0).send(vec![0; 10000]).wait().unwrap();
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+        assert_matches!(sink.send(vec![1]).wait().err(), Some(PipeError::Output(OutputError::Write(_))));
+    }
 }
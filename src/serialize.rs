@@ -1,11 +1,13 @@
 use std::io::Write;
+use std::io::Error as IoError;
 use std::cell::RefCell;
 use std::error::Error;
-use serde::ser::{Serialize, SerializeMap};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq};
 use serde::Serializer as SerdeSerializer;
 use serde_json::error::Error as JsonError;
 use serde_json::ser::Serializer as JsonSerializer;
 use event::{Event, LogstashEvent, Payload, MetaValue};
+use codec::syslog::{SyslogEvent, Framing, write_rfc5424};
 
 pub trait Serializer<T> {
     type Error: Error;
@@ -21,8 +23,21 @@ struct MetaValueSerde<'i>(RefCell<MetaValue<'i>>);
 impl<'i> Serialize for MetaValueSerde<'i> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: SerdeSerializer {
         match *self.0.borrow_mut() {
-            MetaValue::String(string) => serializer.serialize_str(string),
+            MetaValue::Null => serializer.serialize_unit(),
+            MetaValue::Bool(b) => serializer.serialize_bool(b),
+            MetaValue::I64(num) => serializer.serialize_i64(num),
             MetaValue::U64(num) => serializer.serialize_u64(num),
+            MetaValue::F64(num) => serializer.serialize_f64(num),
+            MetaValue::String(ref string) => serializer.serialize_str(string),
+            MetaValue::Bytes(ref bytes) => serializer.serialize_str(&String::from_utf8_lossy(bytes)),
+            MetaValue::Timestamp(ref timestamp) => serializer.serialize_str(&timestamp.to_rfc3339()),
+            MetaValue::Array(ref mut iter) => {
+                let mut seq = serializer.serialize_seq(None)?;
+                for value in iter {
+                    seq.serialize_element(MetaValueSerde(RefCell::new(value)))?;
+                }
+                seq.end()
+            }
             MetaValue::Object(ref mut iter) => {
                 let mut map = serializer.serialize_map(None)?;
                 for (key, value) in iter {
@@ -122,3 +137,28 @@ impl<T: LogstashEvent> Serializer<T> for JsonLogstashEventSerializer {
         Ok(serializer.into_inner())
     }
 }
+
+/// RFC 5424 syslog messages prepared for given framing (see codec::syslog::write_rfc5424); the
+/// framing itself is applied by the output
+pub struct SyslogSerializer {
+    framing: Framing,
+}
+
+impl SyslogSerializer {
+    pub fn new(framing: Framing) -> SyslogSerializer {
+        SyslogSerializer {
+            framing: framing
+        }
+    }
+}
+
+impl<'a> Serializer<SyslogEvent<'a>> for SyslogSerializer {
+    type Error = IoError;
+
+    fn serialize<W: Write>(&self, event: &SyslogEvent<'a>, mut out: W) -> Result<W, IoError> {
+        let mut buf = Vec::with_capacity(128);
+        write_rfc5424(event, self.framing, &mut buf)?;
+        out.write_all(&buf)?;
+        Ok(out)
+    }
+}
@@ -0,0 +1,363 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+use std::os::unix::io::RawFd;
+
+use slog::Logger;
+use libc;
+
+use futures::{Future, Stream};
+use futures::future::Shared;
+use futures::sync::oneshot;
+
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_signal::unix::{Signal, SIGTERM, SIGINT};
+
+/// Shutdown signal shared by the pipeline
+///
+/// Inputs stop accepting new data once signal is triggered and finish processing what they have
+/// already received; this ends their streams and so the pipeline. Outputs running their own
+/// threads report when they have flushed everything so that main can wait for them before exiting.
+#[derive(Clone)]
+pub struct Shutdown {
+    trigger: Rc<RefCell<Option<oneshot::Sender<()>>>>,
+    triggered: Rc<Cell<Option<Instant>>>,
+    flag: ShutdownFlag,
+    signal: Shared<oneshot::Receiver<()>>,
+    drained_sender: mpsc::Sender<String>,
+    drained: Rc<mpsc::Receiver<String>>,
+    pending: Rc<Cell<usize>>,
+    threads: Rc<RefCell<Vec<thread::JoinHandle<()>>>>,
+}
+
+/// Tells output threads that shutdown was triggered (see Shutdown::flag)
+#[derive(Clone, Debug)]
+pub struct ShutdownFlag(Arc<AtomicBool>);
+
+impl ShutdownFlag {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Notifies Shutdown that thread finished when dropped
+struct Drained {
+    name: String,
+    sender: mpsc::Sender<String>,
+}
+
+impl Drop for Drained {
+    fn drop(&mut self) {
+        let _ = self.sender.send(self.name.clone());
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (trigger, signal) = oneshot::channel();
+        let (drained_sender, drained) = mpsc::channel();
+
+        Shutdown {
+            trigger: Rc::new(RefCell::new(Some(trigger))),
+            triggered: Rc::new(Cell::new(None)),
+            flag: ShutdownFlag(Arc::new(AtomicBool::new(false))),
+            signal: signal.shared(),
+            drained_sender: drained_sender,
+            drained: Rc::new(drained),
+            pending: Rc::new(Cell::new(0)),
+            threads: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn trigger(&self) {
+        if let Some(trigger) = self.trigger.borrow_mut().take() {
+            self.triggered.set(Some(Instant::now()));
+            self.flag.0.store(true, Ordering::SeqCst);
+            trigger.complete(());
+        }
+    }
+
+    /// Shutdown signal that can be checked from output threads
+    pub fn flag(&self) -> ShutdownFlag {
+        self.flag.clone()
+    }
+
+    /// Resolves when shutdown was triggered
+    pub fn signal(&self) -> Box<Future<Item=(), Error=()>> {
+        Box::new(self.signal.clone().then(|_| Ok(())))
+    }
+
+    /// Trigger shutdown on SIGTERM or SIGINT
+    pub fn on_signals(&self, logger: &Logger, handle: &Handle) {
+        let shutdown = self.clone();
+        let logger = logger.clone();
+        let err_logger = logger.clone();
+
+        handle.spawn(Signal::new(SIGTERM, handle).flatten_stream()
+            .select(Signal::new(SIGINT, handle).flatten_stream())
+            .into_future()
+            .map(move |(signal, _signals)| {
+                info!(&logger, "Shutting down"; "signal" => format!("{:?}", signal));
+                shutdown.trigger();
+            })
+            .map_err(move |(err, _signals)| {
+                error!(&err_logger, "Failed to listen for signals: {}", err);
+                ()}));
+    }
+
+    /// Point in time by which shutdown should complete; counted from shutdown signal or from now if
+    /// shutdown was not triggered (e.g. pipeline ended on its own)
+    fn deadline_instant(&self, after: Duration) -> Instant {
+        self.triggered.get().unwrap_or_else(Instant::now) + after
+    }
+
+    /// Resolves after given time from shutdown signal
+    pub fn deadline(&self, handle: &Handle, after: Duration) -> Box<Future<Item=(), Error=()>> {
+        let handle = handle.clone();
+        let shutdown = self.clone();
+        Box::new(self.signal()
+            .and_then(move |_| {
+                let deadline = shutdown.deadline_instant(after);
+                let now = Instant::now();
+                let remaining = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+                Timeout::new(remaining, &handle).expect("shutdown deadline timeout").map_err(|_| ())
+            }))
+    }
+
+    /// Spawn output thread that main will wait for with wait_drained before exiting
+    pub fn spawn<F>(&self, name: &str, f: F) -> IoResult<()> where F: FnOnce() + Send + 'static {
+        let drained = Drained {
+            name: name.to_owned(),
+            sender: self.drained_sender.clone(),
+        };
+
+        let thread = thread::Builder::new().name(name.to_owned()).spawn(move || {
+            let _drained = drained;
+            f()
+        })?;
+
+        self.pending.set(self.pending.get() + 1);
+        self.threads.borrow_mut().push(thread);
+        Ok(())
+    }
+
+    /// Wait for all spawned output threads to finish within given time from shutdown signal (same
+    /// deadline as passed to deadline); returns false if they did not finish in time
+    pub fn wait_drained(&self, timeout: Duration) -> bool {
+        let deadline = self.deadline_instant(timeout);
+
+        while self.pending.get() > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false
+            }
+
+            match self.drained.recv_timeout(deadline - now) {
+                Ok(_name) => self.pending.set(self.pending.get() - 1),
+                Err(_) => return false
+            }
+        }
+
+        for thread in self.threads.borrow_mut().drain(..) {
+            let _ = thread.join();
+        }
+        true
+    }
+
+    /// Stop reading from connection socket on shutdown so that connection can decode what it has
+    /// already buffered and finish on EOF
+    ///
+    /// Connection is always spawned; error is returned if it could not be set up to stop on
+    /// shutdown in which case it will run until peer closes it.
+    pub fn spawn_connection<F>(&self, handle: &Handle, fd: RawFd, connection: F) -> IoResult<()> where F: Future<Item=(), Error=()> + 'static {
+        let (closed_sender, closed) = oneshot::channel::<()>();
+        let socket = SocketRef::dup(fd);
+
+        handle.spawn(connection.then(move |result| {
+            drop(closed_sender);
+            result
+        }));
+
+        let socket = socket?;
+        handle.spawn(closed.then(|_| Ok::<bool, ()>(false))
+            .select(self.signal().map(|_| true))
+            .map(move |(shutting_down, _next)| if shutting_down {
+                // not much we can do; connection will finish when peer closes it
+                let _ = socket.shutdown_read();
+            })
+            .map_err(|_| ()));
+        Ok(())
+    }
+}
+
+/// Our own duplicate of connection socket descriptor
+///
+/// Shutting it down affects the connection socket but since we own the descriptor it cannot be
+/// closed and reused by unrelated socket while we hold it. It is closed once connection is done.
+struct SocketRef(RawFd);
+
+impl SocketRef {
+    fn dup(fd: RawFd) -> IoResult<SocketRef> {
+        match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) } {
+            -1 => Err(IoError::last_os_error()),
+            fd => Ok(SocketRef(fd))
+        }
+    }
+
+    fn shutdown_read(&self) -> IoResult<()> {
+        match unsafe { libc::shutdown(self.0, libc::SHUT_RD) } {
+            -1 => Err(IoError::last_os_error()),
+            _ => Ok(())
+        }
+    }
+}
+
+impl Drop for SocketRef {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0); }
+    }
+}
+
+/// End the stream on shutdown
+pub fn until_shutdown<S>(shutdown: &Shutdown, stream: S) -> Box<Stream<Item=S::Item, Error=S::Error>> where S: Stream + 'static {
+    Box::new(stream.map(Some)
+        .select(shutdown.signal().then(|_| Ok::<Option<S::Item>, S::Error>(None)).into_stream())
+        .take_while(|item| Ok(item.is_some()))
+        .map(|item| item.expect("some item")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream as StdTcpStream;
+    use std::os::unix::io::AsRawFd;
+    use futures::{future, stream};
+    use futures::sync::mpsc as future_mpsc;
+    use tokio_core::io::read_to_end;
+    use tokio_core::net::TcpListener;
+    use event_loop;
+
+    #[test]
+    fn stream_ends_on_shutdown() {
+        let mut event_loop = event_loop();
+        let shutdown = Shutdown::new();
+        let (sender, receiver) = future_mpsc::unbounded::<u8>();
+
+        sender.send(1).unwrap();
+        let (first, rest) = event_loop.run(until_shutdown(&shutdown, receiver).into_future()).map_err(|_| ()).unwrap();
+        assert_eq!(first, Some(1));
+
+        shutdown.trigger();
+        assert_eq!(event_loop.run(rest.collect()).unwrap(), Vec::<u8>::new());
+        drop(sender);
+    }
+
+    #[test]
+    fn flag() {
+        let shutdown = Shutdown::new();
+        let flag = shutdown.flag();
+        assert!(!flag.is_set());
+        shutdown.trigger();
+        assert!(flag.is_set());
+    }
+
+    #[test]
+    fn wait_for_threads() {
+        let shutdown = Shutdown::new();
+        shutdown.spawn("test", || thread::sleep(Duration::from_millis(50))).unwrap();
+        assert!(shutdown.wait_drained(Duration::from_secs(5)));
+
+        let (_blocker, block) = mpsc::channel::<()>();
+        shutdown.spawn("stuck", move || { let _ = block.recv(); }).unwrap();
+        assert!(!shutdown.wait_drained(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn deadline_from_signal() {
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+        let shutdown = Shutdown::new();
+
+        let (_blocker, block) = mpsc::channel::<()>();
+        shutdown.spawn("stuck", move || { let _ = block.recv(); }).unwrap();
+
+        shutdown.trigger();
+        thread::sleep(Duration::from_millis(200));
+
+        // deadline has already passed so neither should wait
+        let start = Instant::now();
+        event_loop.run(shutdown.deadline(&handle, Duration::from_millis(100))).unwrap();
+        assert!(!shutdown.wait_drained(Duration::from_millis(100)));
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    fn connection(shutdown: &Shutdown, trigger: bool, close: bool) -> Vec<u8> {
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        let mut client = StdTcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let (stream, _addr) = event_loop.run(listener.incoming().into_future()).map_err(|(err, _)| err).unwrap().0.unwrap();
+        let fd = stream.as_raw_fd();
+        let (read_sender, read) = oneshot::channel();
+        shutdown.spawn_connection(&handle, fd, read_to_end(stream, Vec::new())
+            .map(|(_stream, data)| read_sender.complete(data))
+            .map_err(|_| ())).unwrap();
+
+        client.write_all(b"foo").unwrap();
+        let client = if close { None } else { Some(client) };
+        if trigger {
+            shutdown.trigger();
+        }
+        let data = event_loop.run(read).unwrap();
+        drop(client);
+        data
+    }
+
+    #[test]
+    fn connection_stops_reading_on_shutdown() {
+        let shutdown = Shutdown::new();
+        assert_eq!(connection(&shutdown, true, false), b"foo".to_vec());
+    }
+
+    #[test]
+    fn closed_connection_before_shutdown() {
+        let shutdown = Shutdown::new();
+        assert_eq!(connection(&shutdown, false, true), b"foo".to_vec());
+        shutdown.trigger();
+    }
+
+    #[test]
+    fn socket_closed_when_connection_done() {
+        let mut event_loop = event_loop();
+        let handle = event_loop.handle();
+        let shutdown = Shutdown::new();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+        let mut client = StdTcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+        let (stream, _addr) = event_loop.run(listener.incoming().into_future()).map_err(|(err, _)| err).unwrap().0.unwrap();
+        let fd = stream.as_raw_fd();
+        // connection done without reading; our duplicate must not keep socket open
+        shutdown.spawn_connection(&handle, fd, future::lazy(move || { drop(stream); Ok(()) })).unwrap();
+        event_loop.turn(Some(Duration::from_millis(100)));
+
+        let mut data = Vec::new();
+        client.read_to_end(&mut data).unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn bad_fd() {
+        let event_loop = event_loop();
+        let shutdown = Shutdown::new();
+        assert!(shutdown.spawn_connection(&event_loop.handle(), -1, stream::empty::<(), ()>().for_each(|_| Ok(()))).is_err());
+    }
+}